@@ -0,0 +1,238 @@
+use crate::replication;
+use crate::settings::Settings;
+use futures::{Async, Poll, Stream};
+use hyper::header::CONTENT_LENGTH;
+use hyper::{HeaderMap, Method};
+use std::io;
+
+/// The number of bytes that an execution's body may have in addition to its
+/// script and arguments, for the JSON object or multipart body around them.
+const ENVELOPE_BYTES: usize = 4096;
+
+/// The kinds of request bodies, which are limited separately: the source of
+/// scripts and other definitions, the arguments that scripts are executed
+/// with, batches of either, and executions, which have both a script and
+/// arguments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Script,
+    Arguments,
+    Batch,
+    Execution,
+}
+
+/// The maximum size of each kind of request body. A limit of `None` is
+/// unbounded.
+#[derive(Clone, Copy)]
+pub(crate) struct BodyLimits {
+    pub(crate) script: Option<usize>,
+    pub(crate) arguments: Option<usize>,
+    pub(crate) batch: Option<usize>,
+}
+
+impl BodyLimits {
+    pub(crate) fn new(settings: &Settings) -> Self {
+        // A limit of 0 signifies that bodies of that kind aren't limited
+
+        let limit = |limit: usize| if limit == 0 { None } else { Some(limit) };
+
+        Self {
+            script: limit(settings.request_max_script_bytes),
+            arguments: limit(settings.request_max_arguments_bytes),
+            batch: limit(settings.request_max_batch_bytes),
+        }
+    }
+
+    /// Returns the limit of a kind of body. An execution is limited by the
+    /// sum of the limits of its script and its arguments, along with its
+    /// envelope, and they're then checked separately once they've been
+    /// parsed, by `exceeded`.
+    pub(crate) fn get(&self, kind: Kind) -> Option<usize> {
+        match kind {
+            Kind::Script => self.script,
+            Kind::Arguments => self.arguments,
+            Kind::Batch => self.batch,
+            Kind::Execution => match (self.script, self.arguments) {
+                (Some(script), Some(arguments)) => Some(script + arguments + ENVELOPE_BYTES),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the limit of a kind of body if `length` exceeds it.
+    pub(crate) fn exceeded(&self, kind: Kind, length: usize) -> Option<usize> {
+        self.get(kind).filter(|limit| length > *limit)
+    }
+}
+
+/// Determines the kind of a request's body from its method and its path
+/// within a namespace, and whether it's a stream of newline-delimited JSON:
+///
+/// * Streams, `POST /admin/import`, and the changes and digests that peers
+///   send to `/replication/changes` and `/replication/sync`, are batches.
+/// * `POST /scripts`, `POST /scripts/lint`, and `PUT` of scripts, names
+///   and pipelines, are scripts, as they define something.
+/// * `POST /execute` is an execution.
+/// * Everything else, e.g. executing a script or pipeline, or starting a
+///   job, is limited as arguments.
+pub(crate) fn kind(method: &Method, path: &str, stream: bool) -> Kind {
+    match (method, path) {
+        (&Method::POST, "/admin/import")
+        | (&Method::POST, replication::CHANGES_PATH)
+        | (&Method::POST, replication::SYNC_PATH) => Kind::Batch,
+        (&Method::POST, _) if stream => Kind::Batch,
+        (&Method::POST, "/execute") => Kind::Execution,
+        (&Method::POST, "/scripts")
+        | (&Method::POST, "/scripts/")
+        | (&Method::POST, "/scripts/lint") => Kind::Script,
+        (&Method::PUT, path)
+            if path.starts_with("/scripts/") || path.starts_with("/pipelines/") =>
+        {
+            Kind::Script
+        }
+        _ => Kind::Arguments,
+    }
+}
+
+/// Returns the length that a request declares for its body, if any.
+pub(crate) fn declared_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Determines if an error signifies that a body has exceeded its limit.
+pub(crate) fn is_too_large(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::InvalidData
+}
+
+/// Passes on the chunks of a body until more than `limit` bytes have been
+/// received, at which point it yields an error that `is_too_large` and then
+/// ends, without reading the rest of the body.
+pub(crate) struct Limited<S> {
+    inner: S,
+    received: usize,
+    limit: Option<usize>,
+    done: bool,
+}
+
+impl<S> Limited<S> {
+    pub(crate) fn new(inner: S, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            received: 0,
+            limit,
+            done: false,
+        }
+    }
+}
+
+impl<S> Stream for Limited<S>
+where
+    S: Stream<Error = io::Error>,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = S::Item;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, io::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        match self.inner.poll()? {
+            Async::Ready(Some(chunk)) => {
+                self.received += chunk.as_ref().len();
+
+                match self.limit {
+                    Some(limit) if self.received > limit => {
+                        self.done = true;
+
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("request body exceeds {} bytes", limit),
+                        ))
+                    }
+
+                    _ => Ok(Async::Ready(Some(chunk))),
+                }
+            }
+
+            other => Ok(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{stream, Future};
+
+    #[test]
+    fn test_body_kind() {
+        assert_eq!(kind(&Method::POST, "/scripts", false), Kind::Script);
+        assert_eq!(kind(&Method::POST, "/execute", false), Kind::Execution);
+        assert_eq!(kind(&Method::PUT, "/scripts/abc", false), Kind::Script);
+        assert_eq!(kind(&Method::PUT, "/pipelines/abc", false), Kind::Script);
+        assert_eq!(
+            kind(&Method::PUT, "/scripts/by-name/adder", false),
+            Kind::Script
+        );
+        assert_eq!(kind(&Method::PUT, "/admin", false), Kind::Arguments);
+        assert_eq!(kind(&Method::POST, "/scripts/abc", false), Kind::Arguments);
+        assert_eq!(
+            kind(&Method::POST, "/scripts/abc/jobs", false),
+            Kind::Arguments
+        );
+        assert_eq!(
+            kind(&Method::POST, "/pipelines/abc", false),
+            Kind::Arguments
+        );
+        assert_eq!(kind(&Method::POST, "/scripts/abc", true), Kind::Batch);
+        assert_eq!(kind(&Method::POST, "/admin/import", false), Kind::Batch);
+        assert_eq!(
+            kind(&Method::POST, "/replication/changes", false),
+            Kind::Batch
+        );
+    }
+
+    #[test]
+    fn test_body_limits() {
+        let limits = BodyLimits {
+            script: Some(100),
+            arguments: Some(10),
+            batch: None,
+        };
+
+        assert_eq!(limits.get(Kind::Execution), Some(110 + ENVELOPE_BYTES));
+        assert_eq!(limits.exceeded(Kind::Script, 100), None);
+        assert_eq!(limits.exceeded(Kind::Script, 101), Some(100));
+        assert_eq!(limits.exceeded(Kind::Arguments, 11), Some(10));
+        assert_eq!(limits.exceeded(Kind::Batch, 1000000), None);
+
+        let unbounded = BodyLimits {
+            arguments: None,
+            ..limits
+        };
+
+        assert_eq!(unbounded.get(Kind::Execution), None);
+    }
+
+    #[test]
+    fn test_body_limited() {
+        let chunks = || stream::iter_ok::<_, io::Error>(vec!["abcd", "efgh", "ij"]);
+
+        let body = Limited::new(chunks(), Some(10)).collect().wait().unwrap();
+        assert_eq!(body, vec!["abcd", "efgh", "ij"]);
+
+        let body = Limited::new(chunks(), None).collect().wait().unwrap();
+        assert_eq!(body, vec!["abcd", "efgh", "ij"]);
+
+        let mut limited = Limited::new(chunks(), Some(9)).wait();
+        assert_eq!(limited.next().unwrap().unwrap(), "abcd");
+        assert_eq!(limited.next().unwrap().unwrap(), "efgh");
+        assert!(is_too_large(&limited.next().unwrap().unwrap_err()));
+        assert!(limited.next().is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::script_registry::{self, Named, Script, ScriptId};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+/// The version of the bundle format, which changes if bundles that were
+/// exported earlier can no longer be imported as they are.
+pub const FORMAT: u32 = 1;
+
+/// A portable copy of a script registry: its scripts, with their ids and
+/// metadata, and the names of scripts. Times are milliseconds since the
+/// Unix epoch.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Bundle {
+    pub format: u32,
+    pub scripts: Vec<BundledScript>,
+    #[serde(default)]
+    pub names: BTreeMap<String, Named>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct BundledScript {
+    pub id: ScriptId,
+    pub script: Script,
+    pub created: u64,
+    #[serde(default)]
+    pub executions: u64,
+}
+
+/// How a bundle is imported. When merging, the scripts and names that it
+/// holds are added to the registry, replacing those with the same id or
+/// name. When replacing, the registry holds only what's in the bundle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Merge,
+    Replace,
+}
+
+/// What was imported from a bundle.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Imported {
+    pub scripts: usize,
+    pub names: usize,
+}
+
+impl Mode {
+    /// Parses the mode from the query string of an import request, e.g.
+    /// `?mode=replace`. Bundles are merged by default.
+    pub fn parse(query: Option<&str>) -> io::Result<Self> {
+        let mut mode = Mode::Merge;
+
+        for param in query.unwrap_or_default().split('&') {
+            mode = match param {
+                "" => continue,
+                "mode=merge" => Mode::Merge,
+                "mode=replace" => Mode::Replace,
+                _ => return invalid(format!("invalid parameter {}", param)),
+            };
+        }
+
+        Ok(mode)
+    }
+}
+
+/// Checks that a bundle can be imported as a whole: that it has the current
+/// format, that each script appears once and matches its hash, and its id if
+/// that's content-addressed, and that the names of scripts are valid, and
+/// refer to scripts that are in the bundle, or that `exists`.
+pub fn validate<F>(bundle: &Bundle, exists: F) -> io::Result<()>
+where
+    F: Fn(&ScriptId) -> bool,
+{
+    if bundle.format != FORMAT {
+        return invalid(format!(
+            "cannot import a bundle of format {}",
+            bundle.format
+        ));
+    }
+
+    let mut ids = HashSet::new();
+
+    for bundled in &bundle.scripts {
+        let script = &bundled.script;
+
+        if !ids.insert(&bundled.id) {
+            return invalid(format!("script {} appears more than once", bundled.id));
+        }
+
+        if script.hash != script_registry::sha256_hex(script.code.as_bytes()) {
+            return invalid(format!("script {} doesn't match its hash", bundled.id));
+        }
+
+        if script.content_addressed && bundled.id != ScriptId::for_code(&script.code) {
+            return invalid(format!("script {} doesn't match its id", bundled.id));
+        }
+    }
+
+    for (name, named) in &bundle.names {
+        script_registry::validate_name(name)?;
+
+        for alias in named.aliases.keys() {
+            script_registry::validate_alias(alias)?;
+        }
+
+        let missing = named
+            .versions
+            .iter()
+            .any(|id| !ids.contains(id) && !exists(id));
+
+        let dangling = named
+            .aliases
+            .values()
+            .any(|&version| version == 0 || version > named.versions.len());
+
+        if missing || dangling {
+            return invalid(format!("{} refers to a script that doesn't exist", name));
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid<T>(message: String) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundled(code: &str, content_addressed: bool) -> BundledScript {
+        let script = Script {
+            content_addressed,
+            ..Script::new(code.to_string())
+        };
+
+        BundledScript {
+            id: if content_addressed {
+                ScriptId::for_code(code)
+            } else {
+                ScriptId::random()
+            },
+            script,
+            created: 0,
+            executions: 0,
+        }
+    }
+
+    #[test]
+    fn test_bundle_parse_mode() {
+        assert_eq!(Mode::parse(None).unwrap(), Mode::Merge);
+        assert_eq!(Mode::parse(Some("mode=merge")).unwrap(), Mode::Merge);
+        assert_eq!(Mode::parse(Some("mode=replace")).unwrap(), Mode::Replace);
+        assert!(Mode::parse(Some("mode=overwrite")).is_err());
+        assert!(Mode::parse(Some("force=true")).is_err());
+    }
+
+    #[test]
+    fn test_bundle_validate() {
+        let bundle = || Bundle {
+            format: FORMAT,
+            scripts: vec![
+                bundled("function() { return 1; }", false),
+                bundled("function() { return 2; }", true),
+            ],
+            names: BTreeMap::new(),
+        };
+
+        let named = |versions: Vec<ScriptId>, alias: usize| {
+            let mut aliases = BTreeMap::new();
+            aliases.insert(script_registry::LATEST.to_string(), alias);
+
+            Named { versions, aliases }
+        };
+
+        assert!(validate(&bundle(), |_| false).is_ok());
+
+        let mut b = bundle();
+        b.format = FORMAT + 1;
+        assert!(validate(&b, |_| false).is_err());
+
+        let mut b = bundle();
+        let duplicate = b.scripts[0].id.clone();
+        b.scripts[1].id = duplicate;
+        assert!(validate(&b, |_| false).is_err());
+
+        let mut b = bundle();
+        b.scripts[0].script.code.push(' ');
+        assert!(validate(&b, |_| false).is_err());
+
+        let mut b = bundle();
+        b.scripts[1].script = Script {
+            content_addressed: true,
+            ..Script::new("function() { return 3; }".to_string())
+        };
+        assert!(validate(&b, |_| false).is_err());
+
+        let mut b = bundle();
+        let id = b.scripts[0].id.clone();
+        b.names.insert("adder".to_string(), named(vec![id], 1));
+        assert!(validate(&b, |_| false).is_ok());
+
+        let mut b = bundle();
+        let existing = ScriptId::random();
+        b.names
+            .insert("adder".to_string(), named(vec![existing.clone()], 1));
+        assert!(validate(&b, |_| false).is_err());
+        assert!(validate(&b, |id| *id == existing).is_ok());
+
+        let mut b = bundle();
+        let id = b.scripts[0].id.clone();
+        b.names.insert("adder".to_string(), named(vec![id], 2));
+        assert!(validate(&b, |_| false).is_err());
+
+        let mut b = bundle();
+        let id = b.scripts[0].id.clone();
+        b.names.insert("not a name".to_string(), named(vec![id], 1));
+        assert!(validate(&b, |_| false).is_err());
+    }
+}
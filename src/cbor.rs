@@ -0,0 +1,353 @@
+use crate::codec::{self, invalid_data, Builder, Encoder, Reader};
+use std::io;
+
+pub(crate) const CONTENT_TYPE_CBOR: &str = "application/cbor";
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const INDEFINITE: u8 = 31;
+const BREAK: u8 = 0xff;
+
+/// Decodes a single CBOR data item (RFC 7049), supplying its values to
+/// `builder`. Tags are ignored, and their content decoded in their place.
+pub(crate) fn decode<B: Builder>(data: &[u8], builder: &mut B) -> io::Result<()> {
+    let mut reader = Reader::new(data);
+
+    decode_item(&mut reader, builder, 0)?;
+
+    if reader.is_empty() {
+        Ok(())
+    } else {
+        invalid_data("unexpected data after CBOR item")
+    }
+}
+
+/// Reads the argument of an item's initial byte, i.e. its value or length.
+/// Returns `None` for indefinite lengths.
+fn argument(reader: &mut Reader, info: u8) -> io::Result<Option<u64>> {
+    match info {
+        0..=23 => Ok(Some(u64::from(info))),
+        24 => reader.uint(1).map(Some),
+        25 => reader.uint(2).map(Some),
+        26 => reader.uint(4).map(Some),
+        27 => reader.uint(8).map(Some),
+        INDEFINITE => Ok(None),
+        _ => invalid_data("invalid CBOR additional information"),
+    }
+}
+
+/// Reads a length, which must fit within the remaining data given that
+/// each element occupies at least one byte.
+fn length(reader: &mut Reader, info: u8) -> io::Result<Option<usize>> {
+    match argument(reader, info)? {
+        Some(len) if len > reader.remaining() as u64 => invalid_data("unexpected end of data"),
+        Some(len) => Ok(Some(len as usize)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the chunks of a string, which are concatenated if its length is
+/// indefinite.
+fn chunks(reader: &mut Reader, major: u8, info: u8) -> io::Result<Vec<u8>> {
+    match length(reader, info)? {
+        Some(len) => Ok(reader.take(len)?.to_vec()),
+
+        None => {
+            let mut bytes = vec![];
+
+            while reader.peek()? != BREAK {
+                let initial = reader.byte()?;
+
+                if initial >> 5 != major || initial & 0x1f == INDEFINITE {
+                    return invalid_data("invalid CBOR string chunk");
+                }
+
+                bytes.extend_from_slice(&chunks(reader, major, initial & 0x1f)?);
+            }
+
+            reader.byte()?;
+
+            Ok(bytes)
+        }
+    }
+}
+
+fn decode_item<B: Builder>(reader: &mut Reader, builder: &mut B, depth: usize) -> io::Result<()> {
+    if depth > codec::MAX_DEPTH {
+        return invalid_data("value is nested too deeply");
+    }
+
+    let initial = reader.byte()?;
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+
+    match major {
+        MAJOR_UNSIGNED => match argument(reader, info)? {
+            Some(value) => builder.number(value as f64),
+            None => invalid_data("invalid CBOR integer"),
+        },
+
+        MAJOR_NEGATIVE => match argument(reader, info)? {
+            Some(value) => builder.number(-1.0 - value as f64),
+            None => invalid_data("invalid CBOR integer"),
+        },
+
+        MAJOR_BYTES => builder.bytes(&chunks(reader, major, info)?),
+
+        MAJOR_TEXT => {
+            let bytes = chunks(reader, major, info)?;
+
+            match std::str::from_utf8(&bytes) {
+                Ok(value) => builder.string(value),
+                Err(_) => invalid_data("string is not valid UTF-8"),
+            }
+        }
+
+        MAJOR_ARRAY => {
+            builder.start_array()?;
+
+            match length(reader, info)? {
+                Some(len) => {
+                    for index in 0..len {
+                        decode_item(reader, builder, depth + 1)?;
+                        builder.array_item(index)?;
+                    }
+                }
+
+                None => {
+                    let mut index = 0;
+
+                    while reader.peek()? != BREAK {
+                        decode_item(reader, builder, depth + 1)?;
+                        builder.array_item(index)?;
+                        index += 1;
+                    }
+
+                    reader.byte()?;
+                }
+            }
+
+            Ok(())
+        }
+
+        MAJOR_MAP => {
+            builder.start_map()?;
+
+            match length(reader, info)? {
+                Some(len) => {
+                    for _ in 0..len {
+                        decode_item(reader, builder, depth + 1)?;
+                        decode_item(reader, builder, depth + 1)?;
+                        builder.map_entry()?;
+                    }
+                }
+
+                None => {
+                    while reader.peek()? != BREAK {
+                        decode_item(reader, builder, depth + 1)?;
+                        decode_item(reader, builder, depth + 1)?;
+                        builder.map_entry()?;
+                    }
+
+                    reader.byte()?;
+                }
+            }
+
+            Ok(())
+        }
+
+        MAJOR_TAG => {
+            argument(reader, info)?;
+
+            decode_item(reader, builder, depth + 1)
+        }
+
+        _ => match info {
+            20 => builder.boolean(false),
+            21 => builder.boolean(true),
+            22 => builder.null(),
+            23 => builder.undefined(),
+            25 => builder.number(half_to_f64(reader.uint(2)? as u16)),
+            26 => builder.number(f64::from(f32::from_bits(reader.uint(4)? as u32))),
+            27 => builder.number(f64::from_bits(reader.uint(8)?)),
+            _ => invalid_data("unsupported CBOR simple value"),
+        },
+    }
+}
+
+/// Converts an IEEE 754 half-precision float to a double.
+fn half_to_f64(half: u16) -> f64 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f64::from(half & 0x3ff);
+
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => std::f64::INFINITY,
+        31 => std::f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+/// Encodes values as CBOR, using the shortest form for integers and lengths.
+pub(crate) struct CborEncoder {
+    bytes: Vec<u8>,
+}
+
+impl CborEncoder {
+    pub(crate) fn new() -> Self {
+        Self { bytes: vec![] }
+    }
+
+    fn header(&mut self, major: u8, value: u64) {
+        let major = major << 5;
+
+        if value < 24 {
+            self.bytes.push(major | value as u8);
+        } else if value <= 0xff {
+            self.bytes.push(major | 24);
+            self.bytes.push(value as u8);
+        } else if value <= 0xffff {
+            self.bytes.push(major | 25);
+            self.bytes.extend_from_slice(&(value as u16).to_be_bytes());
+        } else if value <= 0xffff_ffff {
+            self.bytes.push(major | 26);
+            self.bytes.extend_from_slice(&(value as u32).to_be_bytes());
+        } else {
+            self.bytes.push(major | 27);
+            self.bytes.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+impl Encoder for CborEncoder {
+    fn null(&mut self) {
+        self.bytes.push(0xf6);
+    }
+
+    fn undefined(&mut self) {
+        self.bytes.push(0xf7);
+    }
+
+    fn boolean(&mut self, value: bool) {
+        self.bytes.push(if value { 0xf5 } else { 0xf4 });
+    }
+
+    fn number(&mut self, value: f64) {
+        match codec::as_integer(value) {
+            Some(n) if n >= 0 => self.header(MAJOR_UNSIGNED, n as u64),
+            Some(n) => self.header(MAJOR_NEGATIVE, (-1 - n) as u64),
+
+            None => {
+                self.bytes.push((MAJOR_SIMPLE << 5) | 27);
+                self.bytes.extend_from_slice(&value.to_bits().to_be_bytes());
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.header(MAJOR_TEXT, value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.header(MAJOR_BYTES, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn array(&mut self, len: usize) {
+        self.header(MAJOR_ARRAY, len as u64);
+    }
+
+    fn map(&mut self, len: usize) {
+        self.header(MAJOR_MAP, len as u64);
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::tests::Recorder;
+
+    fn decoded(data: &[u8]) -> io::Result<Vec<String>> {
+        let mut recorder = Recorder(vec![]);
+
+        decode(data, &mut recorder).map(|_| recorder.0)
+    }
+
+    #[test]
+    fn test_cbor_decode() {
+        // [1, -2, "a", h'0102', {"k": true}, null, 1.5]
+        let data = [
+            0x87, 0x01, 0x21, 0x61, 0x61, 0x42, 0x01, 0x02, 0xa1, 0x61, 0x6b, 0xf5, 0xf6, 0xf9,
+            0x3e, 0x00,
+        ];
+
+        assert_eq!(
+            decoded(&data).unwrap(),
+            vec![
+                "[", "1", "#0", "-2", "#1", "\"a\"", "#2", "[1, 2]", "#3", "{", "\"k\"", "true",
+                ":", "#4", "null", "#5", "1.5", "#6"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cbor_decode_indefinite_and_tagged() {
+        // [_ "ab" "c", 0("x")] with the string in indefinite-length chunks
+        let data = [
+            0x9f, 0x7f, 0x62, 0x61, 0x62, 0x61, 0x63, 0xff, 0xc0, 0x61, 0x78, 0xff,
+        ];
+
+        assert_eq!(
+            decoded(&data).unwrap(),
+            vec!["[", "\"abc\"", "#0", "\"x\"", "#1"]
+        );
+    }
+
+    #[test]
+    fn test_cbor_decode_invalid() {
+        assert!(decoded(&[0x82, 0x01]).is_err());
+        assert!(decoded(&[0x01, 0x02]).is_err());
+        assert!(decoded(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decoded(&[0x62, 0xff, 0xfe]).is_err());
+        assert!(decoded(&vec![0x81; 1000]).is_err());
+    }
+
+    #[test]
+    fn test_cbor_encode() {
+        let mut encoder = CborEncoder::new();
+
+        encoder.map(2);
+        encoder.string("a");
+        encoder.array(3);
+        encoder.number(1.0);
+        encoder.number(-500.0);
+        encoder.number(1.5);
+        encoder.string("b");
+        encoder.bytes(&[1, 2]);
+
+        assert_eq!(
+            encoder.into_bytes(),
+            vec![
+                0xa2, 0x61, 0x61, 0x83, 0x01, 0x39, 0x01, 0xf3, 0xfb, 0x3f, 0xf8, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x61, 0x62, 0x42, 0x01, 0x02
+            ]
+        );
+    }
+}
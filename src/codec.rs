@@ -0,0 +1,191 @@
+use std::io;
+
+/// The encodings that arguments and results can be exchanged in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    Json,
+    Cbor,
+    MsgPack,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Json
+    }
+}
+
+/// Containers may be nested no deeper than this, which bounds the stack
+/// used when decoding, and stops cyclic values from being encoded forever.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+/// Receives the values that a decoder reads, in order. A container is
+/// started, and then each of its items (or each key followed by its
+/// value) is read and added to it.
+pub(crate) trait Builder {
+    fn null(&mut self) -> io::Result<()>;
+    fn undefined(&mut self) -> io::Result<()>;
+    fn boolean(&mut self, value: bool) -> io::Result<()>;
+    fn number(&mut self, value: f64) -> io::Result<()>;
+    fn string(&mut self, value: &str) -> io::Result<()>;
+    fn bytes(&mut self, value: &[u8]) -> io::Result<()>;
+    fn start_array(&mut self) -> io::Result<()>;
+    fn array_item(&mut self, index: usize) -> io::Result<()>;
+    fn start_map(&mut self) -> io::Result<()>;
+    fn map_entry(&mut self) -> io::Result<()>;
+}
+
+/// Writes values in a binary encoding. Arrays and maps are written as a
+/// header, followed by their items, or keys and values.
+pub(crate) trait Encoder {
+    fn null(&mut self);
+    fn undefined(&mut self);
+    fn boolean(&mut self, value: bool);
+    fn number(&mut self, value: f64);
+    fn string(&mut self, value: &str);
+    fn bytes(&mut self, value: &[u8]);
+    fn array(&mut self, len: usize);
+    fn map(&mut self, len: usize);
+    /// Returns the number of bytes written so far.
+    fn encoded_len(&self) -> usize;
+    fn into_bytes(self) -> Vec<u8>;
+}
+
+/// Returns the value as an integer if it has no fractional part and can be
+/// represented exactly, so that encoders can use their compact integer forms.
+pub(crate) fn as_integer(value: f64) -> Option<i64> {
+    if value.fract() == 0.0 && value >= -9_007_199_254_740_991.0 && value <= 9_007_199_254_740_991.0
+    {
+        Some(value as i64)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn invalid_data<E>(message: &str) -> io::Result<E> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Reads from a byte slice, failing when it ends prematurely.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub(crate) fn peek(&self) -> io::Result<u8> {
+        match self.data.get(self.position) {
+            Some(byte) => Ok(*byte),
+            None => invalid_data("unexpected end of data"),
+        }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.remaining() {
+            return invalid_data("unexpected end of data");
+        }
+
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+
+        Ok(bytes)
+    }
+
+    pub(crate) fn byte(&mut self) -> io::Result<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    /// Reads a big-endian unsigned integer of `len` bytes.
+    pub(crate) fn uint(&mut self, len: usize) -> io::Result<u64> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+    }
+
+    pub(crate) fn string(&mut self, len: usize) -> io::Result<&'a str> {
+        let bytes = self.take(len)?;
+
+        std::str::from_utf8(bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "string is not valid UTF-8"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Records the values that a decoder reads in a readable form.
+    pub(crate) struct Recorder(pub(crate) Vec<String>);
+
+    impl Builder for Recorder {
+        fn null(&mut self) -> io::Result<()> {
+            self.0.push("null".to_string());
+            Ok(())
+        }
+
+        fn undefined(&mut self) -> io::Result<()> {
+            self.0.push("undefined".to_string());
+            Ok(())
+        }
+
+        fn boolean(&mut self, value: bool) -> io::Result<()> {
+            self.0.push(value.to_string());
+            Ok(())
+        }
+
+        fn number(&mut self, value: f64) -> io::Result<()> {
+            self.0.push(value.to_string());
+            Ok(())
+        }
+
+        fn string(&mut self, value: &str) -> io::Result<()> {
+            self.0.push(format!("{:?}", value));
+            Ok(())
+        }
+
+        fn bytes(&mut self, value: &[u8]) -> io::Result<()> {
+            self.0.push(format!("{:?}", value));
+            Ok(())
+        }
+
+        fn start_array(&mut self) -> io::Result<()> {
+            self.0.push("[".to_string());
+            Ok(())
+        }
+
+        fn array_item(&mut self, index: usize) -> io::Result<()> {
+            self.0.push(format!("#{}", index));
+            Ok(())
+        }
+
+        fn start_map(&mut self) -> io::Result<()> {
+            self.0.push("{".to_string());
+            Ok(())
+        }
+
+        fn map_entry(&mut self) -> io::Result<()> {
+            self.0.push(":".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_codec_as_integer() {
+        assert_eq!(as_integer(42.0), Some(42));
+        assert_eq!(as_integer(-1.0), Some(-1));
+        assert_eq!(as_integer(1.5), None);
+        assert_eq!(as_integer(1e300), None);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::script_registry::{self, Script};
+
+/// Returns the entity tag of a script, which is the hash of the script as
+/// it's persisted, so that it changes along with any of its options, such as
+/// labels or tests, as well as with its code.
+pub(crate) fn etag(script: &Script) -> String {
+    let serialized = serde_json::to_vec(script).unwrap_or_default();
+
+    format!("\"{}\"", script_registry::sha256_hex(&serialized))
+}
+
+/// Determines if a request with an `If-Match` header may proceed, given the
+/// current entity tag of the resource, if it exists (RFC 7232). Entity tags
+/// are compared strongly, so weak tags never match.
+pub(crate) fn if_match(header: Option<&str>, current: Option<&str>) -> bool {
+    match (header, current) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(header), Some(current)) => {
+            header.trim() == "*" || tags(header).any(|tag| !is_weak(tag) && tag == current)
+        }
+    }
+}
+
+/// Determines if a resource with the provided entity tag is unchanged, given
+/// a request's `If-None-Match` header, in which case the request may be
+/// answered with status 304. Entity tags are compared weakly.
+pub(crate) fn if_none_match(header: Option<&str>, current: &str) -> bool {
+    match header {
+        None => false,
+        Some(header) => {
+            header.trim() == "*" || tags(header).any(|tag| strip_weak(tag) == strip_weak(current))
+        }
+    }
+}
+
+fn tags(header: &str) -> impl Iterator<Item = &str> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn strip_weak(tag: &str) -> &str {
+    if is_weak(tag) {
+        &tag[2..]
+    } else {
+        tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditional_etag() {
+        let script = Script::new("function() {}".to_string());
+        let current = etag(&script);
+
+        assert!(current.starts_with('"') && current.ends_with('"'));
+        assert_eq!(etag(&script.clone()), current);
+
+        let mut labeled = script.clone();
+        labeled.labels = vec!["billing".to_string()];
+        assert_ne!(etag(&labeled), current);
+
+        let mut pinned = script.clone();
+        pinned.pinned = true;
+        assert_ne!(etag(&pinned), current);
+
+        let mut expiring = script.clone();
+        expiring.ttl_ms = Some(1000);
+        assert_ne!(etag(&expiring), current);
+
+        assert_ne!(etag(&Script::new("function() { }".to_string())), current);
+    }
+
+    #[test]
+    fn test_conditional_if_match() {
+        let current = "\"abc\"".to_string();
+
+        assert!(if_match(None, Some(&current)));
+        assert!(if_match(None, None));
+        assert!(if_match(Some("\"abc\""), Some(&current)));
+        assert!(if_match(Some("\"xyz\", \"abc\""), Some(&current)));
+        assert!(if_match(Some("*"), Some(&current)));
+        assert!(!if_match(Some("*"), None));
+        assert!(!if_match(Some("\"xyz\""), Some(&current)));
+        assert!(!if_match(Some("W/\"abc\""), Some(&current)));
+        assert!(!if_match(Some("abc"), Some(&current)));
+    }
+
+    #[test]
+    fn test_conditional_if_none_match() {
+        let current = "\"abc\"".to_string();
+
+        assert!(!if_none_match(None, &current));
+        assert!(if_none_match(Some("\"abc\""), &current));
+        assert!(if_none_match(Some("W/\"abc\""), &current));
+        assert!(if_none_match(Some("\"xyz\",W/\"abc\""), &current));
+        assert!(if_none_match(Some("*"), &current));
+        assert!(!if_none_match(Some("\"xyz\""), &current));
+    }
+}
@@ -9,13 +9,17 @@ mod duktape {
     include!(concat!(env!("OUT_DIR"), "/duktape-bindings.rs"));
 }
 
+use crate::codec::{self, Encoding};
+use crate::{cbor, msgpack};
 use std::ffi::{c_void, CStr, CString};
-use std::{io, ptr, time};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{io, ptr, slice, str, time};
 
 #[no_mangle]
 /// Upon setup, this library configures each Duktape heap with a udata pointing to
-/// a `Vec<EvaluateContext>`. This vector will only ever have one entry in it, which
-/// contains the data for the latest call for this context.
+/// its `HeapData`. Its `Vec<EvaluateContext>` will only ever have one entry in it,
+/// which contains the data for the latest call for this context.
 ///
 /// The Duktape source is configured to call into this function (see build.rs)
 /// occasionally to allow our library to determine if execution should be stopped
@@ -23,12 +27,20 @@ use std::{io, ptr, time};
 ///
 /// In essence, this mechanism guards against infinite loops by bounding their
 /// execution time against a "wall clock" (ish -- time cannot go backwards).
+///
+/// It also allows an evaluation to be cancelled from another thread, by
+/// setting the flag that was supplied with it.
 extern "C" fn jsaas_duk_exec_timeout_check(udata: *mut c_void) -> duktape::duk_bool_t {
-    let ctx = udata as *const Vec<EvaluateContext>;
+    let ctx = unsafe { &(*(udata as *const HeapData)).evaluate_context };
 
     let cont = unsafe {
         if (*ctx).len() == 1 {
-            (*(ctx))[0].start.elapsed() <= (*(ctx))[0].limit
+            (*(ctx))[0].start.elapsed() <= (*(ctx))[0].limits.time
+                && !(*(ctx))[0]
+                    .cancelled
+                    .as_ref()
+                    .map(|c| c.load(Ordering::SeqCst))
+                    .unwrap_or(false)
         } else {
             false
         }
@@ -41,6 +53,111 @@ extern "C" fn jsaas_duk_exec_timeout_check(udata: *mut c_void) -> duktape::duk_b
     }
 }
 
+/// Each allocation is prefixed with a header that records its size, so that
+/// the number of bytes in use by a heap can be tracked. Its size preserves
+/// the alignment that `malloc` provides.
+const ALLOC_HEADER_SIZE: usize = 16;
+
+/// The allocation functions below are supplied to Duktape when creating each
+/// heap. They track the heap's memory use in its `HeapData`, and fail any
+/// allocation that would exceed the memory limit of the current evaluation.
+/// Duktape then runs its garbage collector and, failing that, throws an error.
+extern "C" fn jsaas_duk_alloc(udata: *mut c_void, size: duktape::duk_size_t) -> *mut c_void {
+    let data = udata as *mut HeapData;
+
+    unsafe {
+        if size == 0 || !(*data).may_allocate(size as usize) {
+            return ptr::null_mut();
+        }
+
+        let header = libc::malloc(size as usize + ALLOC_HEADER_SIZE) as *mut usize;
+
+        if header.is_null() {
+            return ptr::null_mut();
+        }
+
+        *header = size as usize;
+        (*data).allocated += size as usize;
+
+        (header as *mut u8).add(ALLOC_HEADER_SIZE) as *mut c_void
+    }
+}
+
+extern "C" fn jsaas_duk_realloc(
+    udata: *mut c_void,
+    ptr: *mut c_void,
+    size: duktape::duk_size_t,
+) -> *mut c_void {
+    if ptr.is_null() {
+        return jsaas_duk_alloc(udata, size);
+    }
+
+    if size == 0 {
+        jsaas_duk_free(udata, ptr);
+
+        return ptr::null_mut();
+    }
+
+    let data = udata as *mut HeapData;
+
+    unsafe {
+        let header = (ptr as *mut u8).sub(ALLOC_HEADER_SIZE) as *mut usize;
+        let previous_size = *header;
+        let size = size as usize;
+
+        if size > previous_size && !(*data).may_allocate(size - previous_size) {
+            return ptr::null_mut();
+        }
+
+        let header = libc::realloc(header as *mut c_void, size + ALLOC_HEADER_SIZE) as *mut usize;
+
+        if header.is_null() {
+            return ptr::null_mut();
+        }
+
+        *header = size;
+        (*data).allocated = (*data).allocated - previous_size + size;
+
+        (header as *mut u8).add(ALLOC_HEADER_SIZE) as *mut c_void
+    }
+}
+
+extern "C" fn jsaas_duk_free(udata: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let data = udata as *mut HeapData;
+
+    unsafe {
+        let header = (ptr as *mut u8).sub(ALLOC_HEADER_SIZE) as *mut usize;
+
+        (*data).allocated -= *header;
+
+        libc::free(header as *mut c_void);
+    }
+}
+
+/// The state of a protected call made with `Context::duk_safe_call`.
+struct SafeCall<'a> {
+    f: &'a mut dyn FnMut(*mut duktape::duk_context) -> io::Result<duktape::duk_ret_t>,
+    result: Option<io::Result<duktape::duk_ret_t>>,
+}
+
+extern "C" fn jsaas_safe_call(
+    ctx: *mut duktape::duk_context,
+    udata: *mut c_void,
+) -> duktape::duk_ret_t {
+    let call = unsafe { &mut *(udata as *mut SafeCall) };
+
+    let result = (call.f)(ctx);
+    let rets = *result.as_ref().unwrap_or(&0);
+
+    call.result = Some(result);
+
+    rets
+}
+
 extern "C" fn jsaas_btoa(ctx: *mut duktape::duk_context) -> duktape::duk_ret_t {
     unsafe { duktape::duk_base64_encode(ctx, -1) };
 
@@ -99,28 +216,112 @@ extern "C" fn jsaas_atob(ctx: *mut duktape::duk_context) -> duktape::duk_ret_t {
 
 const GLOBAL_FN_BTOA: *const u8 = b"btoa\0" as *const u8;
 const GLOBAL_FN_ATOB: *const u8 = b"atob\0" as *const u8;
+const COMPILE_FILENAME: *const u8 = b"function\0" as *const u8;
+
+/// Bounds the resources that an evaluation may use. `memory` is the number of
+/// bytes that may be allocated in addition to what the heap already uses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub(crate) time: time::Duration,
+    pub(crate) memory: Option<usize>,
+}
+
+impl From<time::Duration> for Limits {
+    fn from(time: time::Duration) -> Self {
+        Limits { time, memory: None }
+    }
+}
+
+/// The arguments for an evaluation: an array, encoded as JSON, CBOR or
+/// MessagePack. Binary encodings are decoded directly onto the stack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Args<'a> {
+    Json(&'a str),
+    Cbor(&'a [u8]),
+    MsgPack(&'a [u8]),
+}
+
+impl<'a> From<&'a str> for Args<'a> {
+    fn from(args: &'a str) -> Self {
+        Args::Json(args)
+    }
+}
+
+/// The result of an evaluation.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Output {
+    /// A JSON-encoded value.
+    Json(String),
+    /// A CBOR-encoded value.
+    Cbor(Vec<u8>),
+    /// A MessagePack-encoded value.
+    MsgPack(Vec<u8>),
+    /// The function returned nothing, or `undefined`.
+    Undefined,
+    /// A string, as returned by the function.
+    Text(String),
+    /// The contents of a buffer, e.g. a `Uint8Array`, as returned by the function.
+    Binary(Vec<u8>),
+}
+
+/// Determines how results are encoded, and which kinds of results are
+/// returned as they are instead. By default, every result is encoded as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct OutputFormat {
+    pub(crate) encoding: Encoding,
+    pub(crate) raw_strings: bool,
+    pub(crate) raw_buffers: bool,
+}
 
 struct EvaluateContext {
-    limit: time::Duration,
+    cancelled: Option<Arc<AtomicBool>>,
+    limits: Limits,
+    memory_at_start: usize,
     start: time::Instant,
 }
 
+struct HeapData {
+    allocated: usize,
+    evaluate_context: Vec<EvaluateContext>,
+}
+
+impl HeapData {
+    fn may_allocate(&self, size: usize) -> bool {
+        match self.evaluate_context.first() {
+            Some(EvaluateContext {
+                limits:
+                    Limits {
+                        memory: Some(limit),
+                        ..
+                    },
+                memory_at_start,
+                ..
+            }) => self.allocated + size <= memory_at_start + limit,
+
+            _ => true,
+        }
+    }
+}
+
 pub(crate) struct Context {
     ctx: *mut duktape::duk_hthread,
-    latest_evaluate_context: *mut Vec<EvaluateContext>,
+    heap_data: *mut HeapData,
 }
 
 impl Context {
     /// Creates a new `Context` that can be used to evaluate JavaScript functions.
     pub(crate) fn new() -> io::Result<Context> {
-        let latest_evaluate_context = Box::into_raw(Box::new(vec![]));
+        let heap_data = Box::into_raw(Box::new(HeapData {
+            allocated: 0,
+            evaluate_context: vec![],
+        }));
 
         let ctx = unsafe {
             duktape::duk_create_heap(
-                None,
-                None,
-                None,
-                latest_evaluate_context as *mut c_void,
+                Some(jsaas_duk_alloc),
+                Some(jsaas_duk_realloc),
+                Some(jsaas_duk_free),
+                heap_data as *mut c_void,
                 None,
             )
         };
@@ -140,21 +341,42 @@ impl Context {
                 "error initializing Duktape heap",
             ))
         } else {
-            Ok(Context {
-                ctx,
-                latest_evaluate_context,
-            })
+            Ok(Context { ctx, heap_data })
         }
     }
 
     /// Evaluates a JavaScript function given its definition and a JSON-encoded array of
-    /// arguments for the function.
-    pub(crate) fn evaluate<S: AsRef<str>>(
+    /// arguments for the function. The result is encoded as JSON, or is `None` if the
+    /// function returns `undefined`. Execution is stopped once `cancelled` is set, which
+    /// can be done from any thread.
+    pub(crate) fn evaluate_with<S: AsRef<str>>(
         &mut self,
         code: S,
         args: S,
-        limit: time::Duration,
-    ) -> io::Result<String> {
+        limits: Limits,
+        cancelled: Option<Arc<AtomicBool>>,
+    ) -> io::Result<Option<String>> {
+        let args = Args::Json(args.as_ref());
+
+        match self.evaluate_as(code, args, limits, cancelled, OutputFormat::default())? {
+            Output::Json(json) => Ok(Some(json)),
+
+            Output::Undefined => Ok(None),
+
+            _ => Err(io::Error::new(io::ErrorKind::Other, "result is not JSON")),
+        }
+    }
+
+    /// Like `evaluate_with`, but the arguments may be in a binary encoding, and the
+    /// result is encoded, or returned as it is, as specified by `format`.
+    pub(crate) fn evaluate_as<'a, S: AsRef<str>, A: Into<Args<'a>>>(
+        &mut self,
+        code: S,
+        args: A,
+        limits: Limits,
+        cancelled: Option<Arc<AtomicBool>>,
+        format: OutputFormat,
+    ) -> io::Result<Output> {
         // @FIXME note the comment below:
         // my assumption is that it's still secure to reuse contexts given an empty stack
         // secure as in an execution can't reference any data in the heap from a
@@ -162,93 +384,111 @@ impl Context {
         // if this is not the case, we'd want to initialize a new heap on each call, but
         // this has overheads.
 
-        if !args.as_ref().trim_start().starts_with('[') {
-            // a simple validation hack, given that we require args to be an array, not
-            // simply any parseable JSON value
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "args must be a JSON-encoded array",
-            ));
-        }
+        let args = args.into();
 
-        let code = CString::new(code.as_ref())?;
-        let code_ptr = code.as_ptr();
+        let json_args = match args {
+            Args::Json(json) if !json.trim_start().starts_with('[') => {
+                // a simple validation hack, given that we require args to be an array, not
+                // simply any parseable JSON value
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "args must be a JSON-encoded array",
+                ));
+            }
+
+            Args::Json(json) => Some(CString::new(json)?),
+
+            Args::Cbor(_) | Args::MsgPack(_) => None,
+        };
 
-        let args = CString::new(args.as_ref())?;
-        let args_ptr = args.as_ptr();
+        let code = CString::new(code.as_ref())?;
 
-        let function = CString::new("function")?;
-        let function_ptr = function.as_ptr();
+        // Arguments in binary encodings are decoded onto the stack as an array,
+        // so only JSON-encoded arguments need to be parsed
 
-        let loader =
-            CString::new("function(fn, args) { return fn.apply(null, JSON.parse(args)); }")?;
-        let loader_ptr = loader.as_ptr();
+        let loader = CString::new(if json_args.is_some() {
+            "function(fn, args) { return fn.apply(null, JSON.parse(args)); }"
+        } else {
+            "function(fn, args) { return fn.apply(null, args); }"
+        })?;
 
         // Clear the existing stack and setup a new evaluation context (timestamp)
         self.duk_clear_stack();
 
+        let start = time::Instant::now();
+
+        // Results in binary encodings are encoded outside of Duktape, so the
+        // limits are checked as they're encoded too
+
+        let mut budget = EncodeBudget {
+            limits,
+            start,
+            cancelled: cancelled.clone(),
+            items: 0,
+        };
+
         unsafe {
-            (*self.latest_evaluate_context).clear();
-            (*self.latest_evaluate_context).push(EvaluateContext {
-                start: time::Instant::now(),
-                limit,
+            (*self.heap_data).evaluate_context.clear();
+            (*self.heap_data).evaluate_context.push(EvaluateContext {
+                cancelled,
+                limits,
+                memory_at_start: (*self.heap_data).allocated,
+                start,
             });
         }
 
-        // Load our functions onto the stack
-        {
-            unsafe {
-                duktape::duk_push_string(self.ctx, loader_ptr);
-                duktape::duk_push_string(self.ctx, function_ptr);
-            }
+        // Load our functions and arguments onto the stack. Each of these allocates
+        // within the limits, so they're pushed in protected calls
 
-            self.duk_compile().map_err(|e| {
-                self.duk_clear_stack();
-                e
-            })?;
-        }
+        self.duk_compile(&loader).map_err(|e| {
+            self.duk_clear_stack();
+            e
+        })?;
 
-        {
-            unsafe {
-                duktape::duk_push_string(self.ctx, code_ptr);
-                duktape::duk_push_string(self.ctx, function_ptr);
+        self.duk_compile(&code).map_err(|e| {
+            self.duk_clear_stack();
+            e
+        })?;
+
+        match json_args {
+            Some(json_args) => {
+                let json_args_ptr = json_args.as_ptr();
+
+                self.duk_safe_call(0, 1, |ctx| {
+                    unsafe {
+                        duktape::duk_push_string(ctx, json_args_ptr);
+                    }
+
+                    Ok(1)
+                })
+                .map_err(|e| {
+                    self.duk_clear_stack();
+                    e
+                })?;
             }
 
-            self.duk_compile().map_err(|e| {
-                self.duk_clear_stack();
-                e
-            })?;
+            None => {
+                self.duk_push_decoded(args).map_err(|e| {
+                    self.duk_clear_stack();
+                    e
+                })?;
+            }
         }
 
         // Execute
         let result = unsafe {
-            duktape::duk_push_string(self.ctx, args_ptr);
-
             duktape::duk_pcall(self.ctx, 2) // 2 arguments
         };
 
         if result == 0 {
-            // we've successfully executed, thus the stack is non-empty. Attempt to
-            // encode it as JSON, and if successful, copy it to an owned String
+            // we've successfully executed, thus the stack is non-empty. Encode the
+            // result and copy it to an owned value
 
-            let json_ptr = unsafe { duktape::duk_json_encode(self.ctx, 0) };
+            let output = self.duk_output(format, &mut budget);
 
-            if json_ptr.is_null() {
-                self.duk_clear_stack();
-
-                Err(io::Error::new(io::ErrorKind::Other, "undefined"))
-            } else {
-                let json_cstr = unsafe { CStr::from_ptr(json_ptr) };
-
-                let json_string: String = json_cstr
-                    .to_str()
-                    .map_err(|_e| io::Error::new(io::ErrorKind::Other, "undefined"))?
-                    .to_string();
-
-                self.duk_clear_stack();
+            self.duk_clear_stack();
 
-                Ok(json_string)
-            }
+            output
         } else {
             let error_result = self
                 .duk_error_message()
@@ -260,28 +500,256 @@ impl Context {
         }
     }
 
-    fn duk_clear_stack(&mut self) {
+    /// Compiles a JavaScript function given its definition, without executing it.
+    /// Fails with Duktape's error, e.g. "SyntaxError: ... (line 2)", if it cannot
+    /// be compiled, including when it doesn't fit within `limits`.
+    pub(crate) fn compile<S: AsRef<str>>(&mut self, code: S, limits: Limits) -> io::Result<()> {
+        let code = CString::new(code.as_ref())?;
+
+        self.duk_clear_stack();
+
         unsafe {
-            duktape::duk_pop_n(self.ctx, duktape::duk_get_top(self.ctx));
+            (*self.heap_data).evaluate_context.clear();
+            (*self.heap_data).evaluate_context.push(EvaluateContext {
+                cancelled: None,
+                limits,
+                memory_at_start: (*self.heap_data).allocated,
+                start: time::Instant::now(),
+            });
+        }
+
+        let result = self.duk_compile(&code);
+
+        self.duk_clear_stack();
+
+        result
+    }
+
+    /// Returns the names of the global object's properties, i.e. the globals
+    /// that are defined for scripts.
+    pub(crate) fn global_names(&mut self) -> io::Result<Vec<String>> {
+        let format = OutputFormat {
+            raw_strings: true,
+            ..OutputFormat::default()
+        };
+
+        let output = self.evaluate_as(
+            "function() { return Object.getOwnPropertyNames(this).join('\\n'); }",
+            "[]",
+            time::Duration::from_secs(1).into(),
+            None,
+            format,
+        )?;
+
+        match output {
+            Output::Text(names) => Ok(names.lines().map(String::from).collect()),
+
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "cannot determine global names",
+            )),
+        }
+    }
+
+    /// Encodes the result of an evaluation, which is at the bottom of the stack.
+    /// Strings and buffers are copied as they are if the format asks for them.
+    fn duk_output(
+        &mut self,
+        format: OutputFormat,
+        budget: &mut EncodeBudget,
+    ) -> io::Result<Output> {
+        unsafe {
+            if duktape::duk_is_undefined(self.ctx, 0) != 0 {
+                return Ok(Output::Undefined);
+            }
+
+            if duktape::duk_is_function(self.ctx, 0) != 0
+                || duktape::duk_get_type(self.ctx, 0) as u32 == duktape::DUK_TYPE_LIGHTFUNC
+            {
+                return Err(unserializable("functions have no representation"));
+            }
+        }
+
+        if let Some(output) = self.duk_raw_output(format) {
+            return Ok(output);
+        }
+
+        match format.encoding {
+            Encoding::Json => self.duk_json_encode().map(Output::Json),
+
+            Encoding::Cbor => self
+                .duk_encode(cbor::CborEncoder::new(), budget)
+                .map(Output::Cbor),
+
+            Encoding::MsgPack => self
+                .duk_encode(msgpack::MsgPackEncoder::new(), budget)
+                .map(Output::MsgPack),
+        }
+    }
+
+    /// Encodes the result of an evaluation as JSON. This throws for cyclic
+    /// values, so it's done in a protected call.
+    fn duk_json_encode(&mut self) -> io::Result<String> {
+        unsafe {
+            duktape::duk_dup(self.ctx, 0);
+        }
+
+        self.duk_safe_call(1, 1, |ctx| {
+            unsafe {
+                duktape::duk_json_encode(ctx, -1);
+            }
+
+            Ok(1)
+        })
+        .map_err(|e| unserializable(&e.to_string()))?;
+
+        if unsafe { duktape::duk_is_string(self.ctx, -1) } == 0 {
+            Err(unserializable("value has no JSON representation"))
+        } else {
+            Ok(unsafe { duk_string(self.ctx, -1) })
+        }
+    }
+
+    /// Decodes arguments in a binary encoding, pushing them onto the stack.
+    /// They're decoded before the protected call, which only replays them.
+    fn duk_push_decoded(&mut self, args: Args) -> io::Result<()> {
+        let mut tape = Tape::default();
+
+        match args {
+            Args::Cbor(data) => cbor::decode(data, &mut tape)?,
+            Args::MsgPack(data) => msgpack::decode(data, &mut tape)?,
+            Args::Json(_) => {}
+        }
+
+        self.duk_safe_call(0, 1, |ctx| {
+            tape.replay(&mut StackBuilder { ctx })?;
+
+            Ok(1)
+        })?;
+
+        if unsafe { duktape::duk_is_array(self.ctx, -1) } == 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "args must be an encoded array",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Encodes the result of an evaluation, which is at the bottom of the stack,
+    /// within the limits of the evaluation that `budget` tracks.
+    fn duk_encode<E: codec::Encoder>(
+        &mut self,
+        mut encoder: E,
+        budget: &mut EncodeBudget,
+    ) -> io::Result<Vec<u8>> {
+        unsafe {
+            duktape::duk_dup(self.ctx, 0);
         }
+
+        self.duk_safe_call(1, 0, |ctx| {
+            encode_value(ctx, &mut encoder, budget, 0)?;
+
+            Ok(0)
+        })
+        .map_err(|e| unserializable(&e.to_string()))?;
+
+        Ok(encoder.into_bytes())
     }
 
-    fn duk_compile(&mut self) -> io::Result<()> {
+    /// Calls `f` in a protected call, so that errors thrown by Duktape, e.g. when
+    /// an allocation fails, are caught rather than being fatal. Such errors unwind
+    /// past `f` without running destructors, so `f` must not hold anything that
+    /// needs to be dropped, such as a `String` or `Vec`, while it calls Duktape.
+    /// Data that it needs is borrowed from outside the call instead.
+    fn duk_safe_call<F>(
+        &mut self,
+        nargs: duktape::duk_idx_t,
+        nrets: duktape::duk_idx_t,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(*mut duktape::duk_context) -> io::Result<duktape::duk_ret_t>,
+    {
+        let mut call = SafeCall {
+            f: &mut f,
+            result: None,
+        };
+
         let result = unsafe {
-            duktape::duk_compile_raw(
+            duktape::duk_safe_call(
                 self.ctx,
-                ptr::null_mut(),
-                0,
-                2 | duktape::DUK_COMPILE_FUNCTION | duktape::DUK_COMPILE_SAFE,
+                Some(jsaas_safe_call),
+                &mut call as *mut SafeCall as *mut c_void,
+                nargs,
+                nrets,
             )
         };
 
         if result != 0 {
-            self.duk_error_message()
-                .and_then(|e| Err(io::Error::new(io::ErrorKind::Other, e)))
-        } else {
-            Ok(())
+            return self
+                .duk_error_message()
+                .and_then(|e| Err(io::Error::new(io::ErrorKind::Other, e)));
         }
+
+        match call.result {
+            Some(Err(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn duk_raw_output(&mut self, format: OutputFormat) -> Option<Output> {
+        unsafe {
+            if format.raw_strings && duktape::duk_is_string(self.ctx, 0) != 0 {
+                let mut length: duktape::duk_size_t = 0;
+                let string_ptr = duktape::duk_get_lstring(self.ctx, 0, &mut length);
+                let bytes = slice::from_raw_parts(string_ptr as *const u8, length as usize);
+
+                Some(Output::Text(String::from_utf8_lossy(bytes).into_owned()))
+            } else if format.raw_buffers && duktape::duk_is_buffer_data(self.ctx, 0) != 0 {
+                let mut size: duktape::duk_size_t = 0;
+                let buffer_ptr = duktape::duk_get_buffer_data(self.ctx, 0, &mut size);
+
+                if buffer_ptr.is_null() {
+                    Some(Output::Binary(vec![]))
+                } else {
+                    let bytes = slice::from_raw_parts(buffer_ptr as *const u8, size as usize);
+
+                    Some(Output::Binary(bytes.to_vec()))
+                }
+            } else {
+                None
+            }
+        }
+    }
+
+    fn duk_clear_stack(&mut self) {
+        unsafe {
+            duktape::duk_pop_n(self.ctx, duktape::duk_get_top(self.ctx));
+        }
+    }
+
+    /// Compiles a function, pushing it onto the stack. Pushing its source can
+    /// fail as well as compiling it, e.g. when the memory limit is reached, so
+    /// both are done in a protected call.
+    fn duk_compile(&mut self, code: &CStr) -> io::Result<()> {
+        let code_ptr = code.as_ptr();
+
+        self.duk_safe_call(0, 1, |ctx| {
+            unsafe {
+                duktape::duk_push_string(ctx, code_ptr);
+                duktape::duk_push_string(ctx, COMPILE_FILENAME as *const std::os::raw::c_char);
+                duktape::duk_compile_raw(
+                    ctx,
+                    ptr::null_mut(),
+                    0,
+                    2 | duktape::DUK_COMPILE_FUNCTION,
+                );
+            }
+
+            Ok(1)
+        })
     }
 
     fn duk_error_message(&mut self) -> io::Result<String> {
@@ -300,72 +768,480 @@ impl Context {
     }
 }
 
-impl Drop for Context {
-    fn drop(&mut self) {
-        self.duk_clear_stack();
+/// Pushes decoded values onto a context's stack.
+struct StackBuilder {
+    ctx: *mut duktape::duk_context,
+}
 
-        unsafe {
-            duktape::duk_destroy_heap(self.ctx);
-            Box::from_raw(self.latest_evaluate_context);
+impl StackBuilder {
+    /// Ensures that there is room on the stack for a container and its items.
+    fn reserve(&mut self) -> io::Result<()> {
+        if unsafe { duktape::duk_check_stack(self.ctx, 4) } == 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "value is nested too deeply",
+            ))
+        } else {
+            Ok(())
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::error::Error;
+/// Records the values that a decoder reads, so that they can be pushed onto
+/// the stack afterwards by a protected call that owns nothing. The contents
+/// of strings and byte strings are kept in `data`.
+#[derive(Default)]
+struct Tape {
+    ops: Vec<Op>,
+    data: Vec<u8>,
+}
 
-    #[test]
-    fn test_duktake_add_result_number() {
-        let mut ctx = Context::new().unwrap();
-        let r = ctx
-            .evaluate(
-                "function(a, b) { return a + b; }",
-                "[2, 4]",
-                time::Duration::from_millis(5000),
-            )
-            .unwrap();
-        assert_eq!(r, "6");
+enum Op {
+    Null,
+    Undefined,
+    Boolean(bool),
+    Number(f64),
+    String(usize, usize),
+    Bytes(usize, usize),
+    StartArray,
+    ArrayItem(usize),
+    StartMap,
+    MapEntry,
+}
+
+impl Tape {
+    fn record(&mut self, value: &[u8]) -> (usize, usize) {
+        let start = self.data.len();
+        self.data.extend_from_slice(value);
+
+        (start, self.data.len())
     }
 
-    #[test]
-    fn test_duktake_add_mult_result_obj() {
-        let mut ctx = Context::new().unwrap();
-        let r = ctx
-            .evaluate(
-                "function(a, b) { return { sum: a + b, product: a * b };}",
-                "[2, 4]",
-                time::Duration::from_millis(5000),
-            )
-            .unwrap();
-        assert_eq!(r, "{\"sum\":6,\"product\":8}");
+    /// Passes the recorded values to another builder, in order.
+    fn replay<B: codec::Builder>(&self, builder: &mut B) -> io::Result<()> {
+        for op in &self.ops {
+            match *op {
+                Op::Null => builder.null()?,
+                Op::Undefined => builder.undefined()?,
+                Op::Boolean(value) => builder.boolean(value)?,
+                Op::Number(value) => builder.number(value)?,
+
+                // Strings were valid UTF-8 when they were recorded
+                Op::String(start, end) => {
+                    builder.string(unsafe { str::from_utf8_unchecked(&self.data[start..end]) })?
+                }
+
+                Op::Bytes(start, end) => builder.bytes(&self.data[start..end])?,
+                Op::StartArray => builder.start_array()?,
+                Op::ArrayItem(index) => builder.array_item(index)?,
+                Op::StartMap => builder.start_map()?,
+                Op::MapEntry => builder.map_entry()?,
+            }
+        }
+
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_duktape_bad_code() {
-        let mut ctx = Context::new().unwrap();
-        match ctx.evaluate(
-            "function()) { return 0; }}",
-            "[]",
-            time::Duration::from_millis(5000),
-        ) {
-            Ok(_) => panic!("should have failed"),
+impl codec::Builder for Tape {
+    fn null(&mut self) -> io::Result<()> {
+        self.ops.push(Op::Null);
 
-            Err(e) => assert_eq!(e.to_string(), "SyntaxError: parse error (line 1)"),
-        };
+        Ok(())
     }
 
-    #[test]
-    fn test_duktape_bad_args() {
-        let mut ctx = Context::new().unwrap();
-        let r = ctx.evaluate(
-            "function(a, b) { return { sum: a + b, product: a * b };}",
-            "[{{",
-            time::Duration::from_millis(5000),
-        );
-        match r {
-            Ok(_) => panic!("should have failed"),
+    fn undefined(&mut self) -> io::Result<()> {
+        self.ops.push(Op::Undefined);
+
+        Ok(())
+    }
+
+    fn boolean(&mut self, value: bool) -> io::Result<()> {
+        self.ops.push(Op::Boolean(value));
+
+        Ok(())
+    }
+
+    fn number(&mut self, value: f64) -> io::Result<()> {
+        self.ops.push(Op::Number(value));
+
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        let (start, end) = self.record(value.as_bytes());
+        self.ops.push(Op::String(start, end));
+
+        Ok(())
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> io::Result<()> {
+        let (start, end) = self.record(value);
+        self.ops.push(Op::Bytes(start, end));
+
+        Ok(())
+    }
+
+    fn start_array(&mut self) -> io::Result<()> {
+        self.ops.push(Op::StartArray);
+
+        Ok(())
+    }
+
+    fn array_item(&mut self, index: usize) -> io::Result<()> {
+        self.ops.push(Op::ArrayItem(index));
+
+        Ok(())
+    }
+
+    fn start_map(&mut self) -> io::Result<()> {
+        self.ops.push(Op::StartMap);
+
+        Ok(())
+    }
+
+    fn map_entry(&mut self) -> io::Result<()> {
+        self.ops.push(Op::MapEntry);
+
+        Ok(())
+    }
+}
+
+impl codec::Builder for StackBuilder {
+    fn null(&mut self) -> io::Result<()> {
+        unsafe { duktape::duk_push_null(self.ctx) };
+
+        Ok(())
+    }
+
+    fn undefined(&mut self) -> io::Result<()> {
+        unsafe { duktape::duk_push_undefined(self.ctx) };
+
+        Ok(())
+    }
+
+    fn boolean(&mut self, value: bool) -> io::Result<()> {
+        unsafe { duktape::duk_push_boolean(self.ctx, value as duktape::duk_bool_t) };
+
+        Ok(())
+    }
+
+    fn number(&mut self, value: f64) -> io::Result<()> {
+        unsafe { duktape::duk_push_number(self.ctx, value) };
+
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        unsafe {
+            duktape::duk_push_lstring(
+                self.ctx,
+                value.as_ptr() as *const std::os::raw::c_char,
+                value.len() as duktape::duk_size_t,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> io::Result<()> {
+        // Byte strings become a Uint8Array, backed by a plain buffer
+
+        unsafe {
+            let size = value.len() as duktape::duk_size_t;
+            let buffer = duktape::duk_push_buffer_raw(self.ctx, size, 0);
+
+            if !buffer.is_null() {
+                ptr::copy_nonoverlapping(value.as_ptr(), buffer as *mut u8, value.len());
+            }
+
+            duktape::duk_push_buffer_object(self.ctx, -1, 0, size, duktape::DUK_BUFOBJ_UINT8ARRAY);
+            duktape::duk_remove(self.ctx, -2);
+        }
+
+        Ok(())
+    }
+
+    fn start_array(&mut self) -> io::Result<()> {
+        self.reserve()?;
+
+        unsafe { duktape::duk_push_array(self.ctx) };
+
+        Ok(())
+    }
+
+    fn array_item(&mut self, index: usize) -> io::Result<()> {
+        unsafe { duktape::duk_put_prop_index(self.ctx, -2, index as duktape::duk_uarridx_t) };
+
+        Ok(())
+    }
+
+    fn start_map(&mut self) -> io::Result<()> {
+        self.reserve()?;
+
+        unsafe { duktape::duk_push_object(self.ctx) };
+
+        Ok(())
+    }
+
+    fn map_entry(&mut self) -> io::Result<()> {
+        unsafe { duktape::duk_put_prop(self.ctx, -3) };
+
+        Ok(())
+    }
+}
+
+fn unserializable(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("result cannot be serialized: {}", reason),
+    )
+}
+
+/// How many values are encoded between checks of the time limit.
+const ENCODE_CHECK_INTERVAL: usize = 1024;
+
+/// Bounds the encoding of a result in a binary encoding, which happens outside
+/// of Duktape's executor and allocator, by the limits of its evaluation. The
+/// encoded bytes count against the memory limit.
+struct EncodeBudget {
+    limits: Limits,
+    start: time::Instant,
+    cancelled: Option<Arc<AtomicBool>>,
+    items: usize,
+}
+
+impl EncodeBudget {
+    /// Counts a value that's about to be encoded, given the number of bytes
+    /// encoded so far, failing if the limits have been exceeded.
+    fn check(&mut self, encoded: usize) -> io::Result<()> {
+        if let Some(memory) = self.limits.memory {
+            if encoded > memory {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "result exceeds the memory limit",
+                ));
+            }
+        }
+
+        self.items += 1;
+
+        if self.items % ENCODE_CHECK_INTERVAL == 0 {
+            let cancelled = self
+                .cancelled
+                .as_ref()
+                .map(|c| c.load(Ordering::SeqCst))
+                .unwrap_or(false);
+
+            if cancelled || self.start.elapsed() > self.limits.time {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "result took too long to encode",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the string at `idx` on the stack.
+unsafe fn duk_string(ctx: *mut duktape::duk_context, idx: duktape::duk_idx_t) -> String {
+    let mut length: duktape::duk_size_t = 0;
+    let string_ptr = duktape::duk_get_lstring(ctx, idx, &mut length);
+
+    if string_ptr.is_null() {
+        String::new()
+    } else {
+        String::from_utf8_lossy(slice::from_raw_parts(
+            string_ptr as *const u8,
+            length as usize,
+        ))
+        .into_owned()
+    }
+}
+
+/// Returns the data of the buffer at `idx` on the stack.
+unsafe fn duk_bytes<'a>(ctx: *mut duktape::duk_context, idx: duktape::duk_idx_t) -> &'a [u8] {
+    let mut size: duktape::duk_size_t = 0;
+    let buffer_ptr = duktape::duk_get_buffer_data(ctx, idx, &mut size);
+
+    if buffer_ptr.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(buffer_ptr as *const u8, size as usize)
+    }
+}
+
+/// Determines whether a value is omitted from objects, as with JSON.
+unsafe fn duk_is_omitted(ctx: *mut duktape::duk_context, idx: duktape::duk_idx_t) -> bool {
+    duktape::duk_is_undefined(ctx, idx) != 0
+        || duktape::duk_is_function(ctx, idx) != 0
+        || duktape::duk_get_type(ctx, idx) as u32 == duktape::DUK_TYPE_LIGHTFUNC
+}
+
+/// Encodes the value at the top of the stack. Arrays and buffers are encoded
+/// as such, and other objects as maps of their own enumerable properties.
+/// Nesting is limited, which also stops cyclic values from being encoded.
+///
+/// This runs in a protected call, so values that it copies out of Duktape
+/// are dropped before it calls Duktape again, and the keys of objects are
+/// collected in a Duktape array rather than a `Vec`.
+fn encode_value<E: codec::Encoder>(
+    ctx: *mut duktape::duk_context,
+    encoder: &mut E,
+    budget: &mut EncodeBudget,
+    depth: usize,
+) -> io::Result<()> {
+    if depth > codec::MAX_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "value is nested too deeply or cyclic",
+        ));
+    }
+
+    budget.check(encoder.encoded_len())?;
+
+    unsafe {
+        match duktape::duk_get_type(ctx, -1) as u32 {
+            duktape::DUK_TYPE_UNDEFINED => encoder.undefined(),
+            duktape::DUK_TYPE_BOOLEAN => encoder.boolean(duktape::duk_get_boolean(ctx, -1) != 0),
+            duktape::DUK_TYPE_NUMBER => encoder.number(duktape::duk_get_number(ctx, -1)),
+            duktape::DUK_TYPE_STRING => encoder.string(&duk_string(ctx, -1)),
+            duktape::DUK_TYPE_BUFFER => encoder.bytes(duk_bytes(ctx, -1)),
+
+            duktape::DUK_TYPE_OBJECT if duktape::duk_is_buffer_data(ctx, -1) != 0 => {
+                encoder.bytes(duk_bytes(ctx, -1))
+            }
+
+            duktape::DUK_TYPE_OBJECT if duktape::duk_is_function(ctx, -1) == 0 => {
+                if duktape::duk_check_stack(ctx, 5) == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "value is nested too deeply or cyclic",
+                    ));
+                }
+
+                if duktape::duk_is_array(ctx, -1) != 0 {
+                    let len = duktape::duk_get_length(ctx, -1) as usize;
+
+                    encoder.array(len);
+
+                    for index in 0..len {
+                        duktape::duk_get_prop_index(ctx, -1, index as duktape::duk_uarridx_t);
+                        encode_value(ctx, encoder, budget, depth + 1)?;
+                        duktape::duk_pop(ctx);
+                    }
+                } else {
+                    let mut len = 0;
+
+                    duktape::duk_push_array(ctx);
+                    duktape::duk_enum(ctx, -2, duktape::DUK_ENUM_OWN_PROPERTIES_ONLY);
+
+                    while duktape::duk_next(ctx, -1, 1) != 0 {
+                        if duk_is_omitted(ctx, -1) {
+                            duktape::duk_pop_n(ctx, 2);
+                        } else {
+                            duktape::duk_pop(ctx);
+                            duktape::duk_put_prop_index(ctx, -3, len as duktape::duk_uarridx_t);
+                            len += 1;
+                        }
+                    }
+
+                    duktape::duk_pop(ctx);
+
+                    encoder.map(len);
+
+                    for index in 0..len {
+                        duktape::duk_get_prop_index(ctx, -1, index as duktape::duk_uarridx_t);
+                        encoder.string(&duk_string(ctx, -1));
+
+                        duktape::duk_get_prop(ctx, -3);
+                        encode_value(ctx, encoder, budget, depth + 1)?;
+                        duktape::duk_pop(ctx);
+                    }
+
+                    duktape::duk_pop(ctx);
+                }
+            }
+
+            _ => encoder.null(),
+        }
+    }
+
+    Ok(())
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.duk_clear_stack();
+
+        unsafe {
+            duktape::duk_destroy_heap(self.ctx);
+            Box::from_raw(self.heap_data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_duktake_add_result_number() {
+        let mut ctx = Context::new().unwrap();
+        let r = ctx
+            .evaluate_with(
+                "function(a, b) { return a + b; }",
+                "[2, 4]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktake_add_mult_result_obj() {
+        let mut ctx = Context::new().unwrap();
+        let r = ctx
+            .evaluate_with(
+                "function(a, b) { return { sum: a + b, product: a * b };}",
+                "[2, 4]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(r, Some("{\"sum\":6,\"product\":8}".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_bad_code() {
+        let mut ctx = Context::new().unwrap();
+        match ctx.evaluate_with(
+            "function()) { return 0; }}",
+            "[]",
+            time::Duration::from_millis(5000).into(),
+            None,
+        ) {
+            Ok(_) => panic!("should have failed"),
+
+            Err(e) => assert_eq!(e.to_string(), "SyntaxError: parse error (line 1)"),
+        };
+    }
+
+    #[test]
+    fn test_duktape_bad_args() {
+        let mut ctx = Context::new().unwrap();
+        let r = ctx.evaluate_with(
+            "function(a, b) { return { sum: a + b, product: a * b };}",
+            "[{{",
+            time::Duration::from_millis(5000).into(),
+            None,
+        );
+        match r {
+            Ok(_) => panic!("should have failed"),
 
             Err(e) => assert_eq!(e.to_string(), "SyntaxError: invalid json (at offset 3)"),
         };
@@ -374,10 +1250,11 @@ mod tests {
     #[test]
     fn test_duktape_wrong_args() {
         let mut ctx = Context::new().unwrap();
-        let r = ctx.evaluate(
+        let r = ctx.evaluate_with(
             "function(a, b) { return { sum: a + b, product: a * b };}",
             " {}",
-            time::Duration::from_millis(5000),
+            time::Duration::from_millis(5000).into(),
+            None,
         );
         assert!(r.is_err());
     }
@@ -386,91 +1263,562 @@ mod tests {
     fn test_duktape_no_return() {
         let mut ctx = Context::new().unwrap();
         let r = ctx
-            .evaluate("function() {}", "[]", time::Duration::from_millis(5000))
-            .err()
+            .evaluate_with(
+                "function() {}",
+                "[]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
             .unwrap();
-        assert_eq!(r.description(), "undefined");
+        assert_eq!(r, None);
     }
 
     #[test]
     fn test_duktape_return_undefined() {
         let mut ctx = Context::new().unwrap();
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "function() { return undefined; }",
                 "[]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
-            .err()
             .unwrap();
-        assert_eq!(r.description(), "undefined");
+        assert_eq!(r, None);
     }
 
     #[test]
     fn test_duktape_return_null() {
         let mut ctx = Context::new().unwrap();
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "function() { return null; }",
                 "[]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .unwrap();
-        assert_eq!(r, "null");
+        assert_eq!(r, Some("null".to_string()));
     }
 
     #[test]
     fn test_duktape_return_func() {
         let mut ctx = Context::new().unwrap();
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "function() { return function() {}; }",
                 "[]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .err()
             .unwrap();
-        assert_eq!(r.description(), "undefined");
+        assert_eq!(
+            r.description(),
+            "result cannot be serialized: functions have no representation"
+        );
+    }
+
+    #[test]
+    fn test_duktape_undefined_output() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits::from(time::Duration::from_millis(5000));
+
+        for code in &["function() {}", "function() { return undefined; }"] {
+            let r = ctx
+                .evaluate_as(*code, "[]", limits, None, OutputFormat::default())
+                .unwrap();
+            assert_eq!(r, Output::Undefined);
+        }
+    }
+
+    #[test]
+    fn test_duktape_function_output() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits::from(time::Duration::from_millis(5000));
+
+        // Functions fail to encode in every format, including built-ins,
+        // which may be lightfuncs
+
+        for encoding in &[Encoding::Json, Encoding::Cbor, Encoding::MsgPack] {
+            let format = OutputFormat {
+                encoding: *encoding,
+                raw_strings: true,
+                raw_buffers: true,
+            };
+
+            for code in &[
+                "function() { return function() {}; }",
+                "function() { return Math.max; }",
+            ] {
+                let e = ctx
+                    .evaluate_as(*code, "[]", limits, None, format)
+                    .unwrap_err();
+                assert_eq!(
+                    e.to_string(),
+                    "result cannot be serialized: functions have no representation"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_duktape_cyclic_result() {
+        let mut ctx = Context::new().unwrap();
+
+        let r = ctx
+            .evaluate_with(
+                "function() { var a = {}; a.self = a; return a; }",
+                "[]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
+            .err()
+            .unwrap();
+        assert!(r
+            .to_string()
+            .starts_with("result cannot be serialized: TypeError"));
+
+        let r = ctx
+            .evaluate_with(
+                "function(a, b) { return a + b; }",
+                "[2, 4]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_compile() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits::from(time::Duration::from_millis(5000));
+
+        assert!(ctx
+            .compile("function(a) { while (true) {} }", limits)
+            .is_ok());
+
+        let e = ctx
+            .compile("function(a) {\n  return a +;\n}", limits)
+            .err()
+            .unwrap();
+        assert!(e.to_string().starts_with("SyntaxError"));
+        assert!(e.to_string().contains("line 2"));
+
+        // Code that doesn't fit within the memory limit fails to compile
+        // rather than aborting
+
+        let memory_limits = Limits {
+            time: time::Duration::from_millis(5000),
+            memory: Some(64 * 1024),
+        };
+
+        let code = format!(
+            "function() {{ return '{}'.length; }}",
+            "a".repeat(1024 * 1024)
+        );
+        assert!(ctx.compile(&code, memory_limits).is_err());
+        assert!(ctx
+            .compile("function(a) { return a; }", memory_limits)
+            .is_ok());
+
+        let globals = ctx.global_names().unwrap();
+        assert!(globals.contains(&"JSON".to_string()));
+        assert!(globals.contains(&"btoa".to_string()));
     }
 
     #[test]
     fn test_duktape_usable_after_error() {
         let mut ctx = Context::new().unwrap();
-        let r = ctx.evaluate(
+        let r = ctx.evaluate_with(
             "funktion()) { return 0; }}",
             "[]",
-            time::Duration::from_millis(5000),
+            time::Duration::from_millis(5000).into(),
+            None,
         );
         assert!(r.is_err());
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "function(a, b) { return a + b; }",
                 "[2, 4]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .unwrap();
-        assert_eq!(r, "6");
+        assert_eq!(r, Some("6".to_string()));
     }
 
     #[test]
     fn test_duktake_while_true_recoverable() {
         let mut ctx = Context::new().unwrap();
-        let r = ctx.evaluate(
+        let r = ctx.evaluate_with(
             "function() { while(true) {}",
             "[]",
-            time::Duration::from_millis(100),
+            time::Duration::from_millis(100).into(),
+            None,
+        );
+
+        assert!(r.is_err());
+
+        let r = ctx
+            .evaluate_with(
+                "function(a, b) { return a + b; }",
+                "[2, 4]",
+                time::Duration::from_millis(5000).into(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_while_true_cancellable() {
+        let mut ctx = Context::new().unwrap();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let canceller = {
+            let cancelled = cancelled.clone();
+
+            std::thread::spawn(move || {
+                std::thread::sleep(time::Duration::from_millis(100));
+
+                cancelled.store(true, Ordering::SeqCst);
+            })
+        };
+
+        let start = time::Instant::now();
+
+        let r = ctx.evaluate_with(
+            "function() { while(true) {} }",
+            "[]",
+            Limits {
+                time: time::Duration::from_millis(60000),
+                memory: None,
+            },
+            Some(cancelled),
         );
 
+        canceller.join().unwrap();
+
         assert!(r.is_err());
+        assert!(start.elapsed() < time::Duration::from_millis(60000));
 
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "function(a, b) { return a + b; }",
                 "[2, 4]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .unwrap();
-        assert_eq!(r, "6");
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_memory_limit() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits {
+            time: time::Duration::from_millis(5000),
+            memory: Some(1024 * 1024),
+        };
+
+        let r = ctx.evaluate_with(
+            "function() { var a = []; for (var i = 0; i < 1000000; i++) { a.push('item ' + i); } return a.length; }",
+            "[]",
+            limits,
+            None,
+        );
+
+        assert!(r.is_err());
+
+        let r = ctx
+            .evaluate_with(
+                "function() { var a = []; for (var i = 0; i < 1000; i++) { a.push('item ' + i); } return a.length; }",
+                "[]",
+                limits,
+                None,
+            )
+            .unwrap();
+        assert_eq!(r, Some("1000".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_json_args_memory_limit() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits {
+            time: time::Duration::from_millis(5000),
+            memory: Some(64 * 1024),
+        };
+
+        // Pushing arguments that don't fit within the limit fails without
+        // aborting, as does pushing code that doesn't fit
+
+        let args = format!("[\"{}\"]", "a".repeat(1024 * 1024));
+
+        let r = ctx.evaluate_with(
+            "function(a) { return a.length; }",
+            args.as_str(),
+            limits,
+            None,
+        );
+        assert!(r.is_err());
+
+        let code = format!(
+            "function() {{ return '{}'.length; }}",
+            "a".repeat(1024 * 1024)
+        );
+
+        let r = ctx.evaluate_with(code.as_str(), "[]", limits, None);
+        assert!(r.is_err());
+
+        let r = ctx
+            .evaluate_with("function(a, b) { return a + b; }", "[2, 4]", limits, None)
+            .unwrap();
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_raw_output() {
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits::from(time::Duration::from_millis(5000));
+
+        let format = OutputFormat {
+            raw_strings: true,
+            raw_buffers: true,
+            ..OutputFormat::default()
+        };
+
+        let r = ctx
+            .evaluate_as(
+                "function() { return 'a,b\\n1,2'; }",
+                "[]",
+                limits,
+                None,
+                format,
+            )
+            .unwrap();
+        assert_eq!(r, Output::Text("a,b\n1,2".to_string()));
+
+        let r = ctx
+            .evaluate_as(
+                "function() { return new Uint8Array([1, 2, 255]); }",
+                "[]",
+                limits,
+                None,
+                format,
+            )
+            .unwrap();
+        assert_eq!(r, Output::Binary(vec![1, 2, 255]));
+
+        let r = ctx
+            .evaluate_as(
+                "function() { return { a: 1 }; }",
+                "[]",
+                limits,
+                None,
+                format,
+            )
+            .unwrap();
+        assert_eq!(r, Output::Json("{\"a\":1}".to_string()));
+
+        let r = ctx
+            .evaluate_as(
+                "function() { return 'text'; }",
+                "[]",
+                limits,
+                None,
+                OutputFormat::default(),
+            )
+            .unwrap();
+        assert_eq!(r, Output::Json("\"text\"".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_binary_args_and_output() {
+        use crate::codec::Encoder;
+
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits::from(time::Duration::from_millis(5000));
+
+        let code = "function(n, s, b) { return { n: n + 1, s: s, b: b[1], nested: [true, null, 0.5], f: function() {} }; }";
+
+        let mut args = cbor::CborEncoder::new();
+        args.array(3);
+        args.number(41.0);
+        args.string("hi");
+        args.bytes(&[7, 8]);
+        let args = args.into_bytes();
+
+        let mut expected = cbor::CborEncoder::new();
+        expected.map(4);
+        expected.string("n");
+        expected.number(42.0);
+        expected.string("s");
+        expected.string("hi");
+        expected.string("b");
+        expected.number(8.0);
+        expected.string("nested");
+        expected.array(3);
+        expected.boolean(true);
+        expected.null();
+        expected.number(0.5);
+
+        let format = OutputFormat {
+            encoding: Encoding::Cbor,
+            ..OutputFormat::default()
+        };
+
+        let r = ctx
+            .evaluate_as(code, Args::Cbor(&args), limits, None, format)
+            .unwrap();
+        assert_eq!(r, Output::Cbor(expected.into_bytes()));
+
+        let mut args = msgpack::MsgPackEncoder::new();
+        args.array(3);
+        args.number(1.0);
+        args.string("hi");
+        args.bytes(&[7, 8]);
+        let args = args.into_bytes();
+
+        let r = ctx
+            .evaluate_as(
+                code,
+                Args::MsgPack(&args),
+                limits,
+                None,
+                OutputFormat::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            r,
+            Output::Json(r#"{"n":2,"s":"hi","b":8,"nested":[true,null,0.5]}"#.to_string())
+        );
+
+        // Arguments must be an array, and cyclic results cannot be encoded
+
+        let r = ctx.evaluate_as(code, Args::Cbor(&[0x01]), limits, None, format);
+        assert!(r.is_err());
+
+        let r = ctx.evaluate_as(
+            "function() { var a = {}; a.a = a; return a; }",
+            Args::Cbor(&[0x80]),
+            limits,
+            None,
+            format,
+        );
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_duktape_binary_args_and_output_memory_limit() {
+        use crate::codec::Encoder;
+
+        let mut ctx = Context::new().unwrap();
+
+        let limits = Limits {
+            time: time::Duration::from_millis(5000),
+            memory: Some(64 * 1024),
+        };
+
+        let format = OutputFormat {
+            encoding: Encoding::Cbor,
+            ..OutputFormat::default()
+        };
+
+        // Decoding arguments that don't fit within the limit fails without
+        // aborting, as does encoding results that need more memory
+
+        let mut args = cbor::CborEncoder::new();
+        args.array(1);
+        args.array(10000);
+
+        for index in 0..10000 {
+            args.string(&format!("item {} of the arguments", index));
+        }
+
+        let args = args.into_bytes();
+
+        let r = ctx.evaluate_as(
+            "function(a) { return a.length; }",
+            Args::Cbor(&args),
+            limits,
+            None,
+            format,
+        );
+        assert!(r.is_err());
+
+        for count in &[10, 100, 500, 1000, 2000] {
+            let code = format!(
+                "function() {{ var o = {{}}; for (var i = 0; i < {}; i++) {{ o['key ' + i] = i; }} return o; }}",
+                count
+            );
+
+            let _ = ctx.evaluate_as(&code, Args::Cbor(&[0x80]), limits, None, format);
+        }
+
+        let r = ctx
+            .evaluate_with("function(a, b) { return a + b; }", "[2, 4]", limits, None)
+            .unwrap();
+        assert_eq!(r, Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_duktape_binary_output_limits() {
+        let mut ctx = Context::new().unwrap();
+
+        let format = OutputFormat {
+            encoding: Encoding::Cbor,
+            ..OutputFormat::default()
+        };
+
+        // Encoding is bounded by the memory limit, as its output is kept
+        // outside of the heap
+
+        let limits = Limits {
+            time: time::Duration::from_millis(60000),
+            memory: Some(64 * 1024),
+        };
+
+        let e = ctx
+            .evaluate_as(
+                "function() { return new Array(4294967295); }",
+                "[]",
+                limits,
+                None,
+                format,
+            )
+            .unwrap_err();
+        assert!(e.to_string().contains("memory limit"));
+
+        // ...and by the time limit, e.g. for values that share their items
+
+        let limits = Limits::from(time::Duration::from_millis(500));
+        let start = time::Instant::now();
+
+        let e = ctx
+            .evaluate_as(
+                "function() { var a = []; for (var i = 0; i < 30; i++) { a = [a, a]; } return a; }",
+                "[]",
+                limits,
+                None,
+                format,
+            )
+            .unwrap_err();
+        assert!(e.to_string().contains("too long"));
+        assert!(start.elapsed() < time::Duration::from_millis(5000));
+
+        let r = ctx
+            .evaluate_as("function() { return [1, 2]; }", "[]", limits, None, format)
+            .unwrap();
+        assert_eq!(r, Output::Cbor(vec![0x82, 0x01, 0x02]));
     }
 
     #[test]
@@ -478,7 +1826,7 @@ mod tests {
         let mut ctx = Context::new().unwrap();
 
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 "
                 function() {
                     return {
@@ -494,11 +1842,12 @@ mod tests {
                 }
             ",
                 "[]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .unwrap();
 
-        assert_eq!(r, r#"{"string":"aGVsbG8=","number":"MTIzNA==","undefined":"dW5kZWZpbmVk","undefinedExplicit":"dW5kZWZpbmVk","true":"dHJ1ZQ==","false":"ZmFsc2U=","null":"bnVsbA==","obj":"W29iamVjdCBPYmplY3Rd"}"#);
+        assert_eq!(r, Some(r#"{"string":"aGVsbG8=","number":"MTIzNA==","undefined":"dW5kZWZpbmVk","undefinedExplicit":"dW5kZWZpbmVk","true":"dHJ1ZQ==","false":"ZmFsc2U=","null":"bnVsbA==","obj":"W29iamVjdCBPYmplY3Rd"}"#.to_string()));
     }
 
     #[test]
@@ -506,7 +1855,7 @@ mod tests {
         let mut ctx = Context::new().unwrap();
 
         let r = ctx
-            .evaluate(
+            .evaluate_with(
                 r#"
                 function() {
                     return {
@@ -521,27 +1870,30 @@ mod tests {
                 }
                 "#,
                 "[]",
-                time::Duration::from_millis(5000),
+                time::Duration::from_millis(5000).into(),
+                None,
             )
             .unwrap();
 
         assert_eq!(
             r,
-            r#"{"one":"hello","two":[1,167,0,5,220,95,160,36],"three":""}"#
+            Some(r#"{"one":"hello","two":[1,167,0,5,220,95,160,36],"three":""}"#.to_string())
         );
 
-        let r = ctx.evaluate(
+        let r = ctx.evaluate_with(
             "function() { return atob(1234); }",
             "[]",
-            time::Duration::from_millis(5000),
+            time::Duration::from_millis(5000).into(),
+            None,
         );
 
         assert!(r.is_err());
 
-        let r = ctx.evaluate(
+        let r = ctx.evaluate_with(
             r#"function() { return atob("Z"); }"#,
             "[]",
-            time::Duration::from_millis(5000),
+            time::Duration::from_millis(5000).into(),
+            None,
         );
 
         assert!(r.is_err());
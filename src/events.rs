@@ -0,0 +1,439 @@
+use crate::persistence;
+use crate::script_registry::ScriptId;
+use futures::sync::mpsc;
+use futures::Future;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const SEQ_FILE: &str = "events.seq";
+const SEQ_TEMP_FILE: &str = "events.seq.tmp";
+
+/// How many sequence numbers a feed reserves at a time, so that it records
+/// them once per that many events rather than for every event.
+const SEQ_RESERVATION: u64 = 10_000;
+
+/// What happened in a registry. Scripts are created, updated and deleted by
+/// requests, or by peers, and removed by the registry when they expire or
+/// are evicted. Names are updated when a version is stored or an alias is
+/// moved. A `reset` signifies that events were missed, so consumers should
+/// reload whatever they derived from them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Kind {
+    Created { id: ScriptId },
+    Updated { id: ScriptId },
+    Deleted { id: ScriptId },
+    Expired { id: ScriptId },
+    Evicted { id: ScriptId },
+    NameUpdated { name: String },
+    NameDeleted { name: String },
+    Reset,
+}
+
+/// An event in a namespace, numbered in the order in which it happened.
+/// The time is in milliseconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+    pub seq: u64,
+    pub time: u64,
+    pub namespace: String,
+    #[serde(flatten)]
+    pub kind: Kind,
+}
+
+impl Event {
+    /// Encodes the event as a server-sent event, with its sequence number
+    /// as the id, so that clients can resume from it.
+    pub fn to_sse(&self) -> String {
+        format!(
+            "id: {}\ndata: {}\n\n",
+            self.seq,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+/// Numbers the events of a namespace and delivers them to subscribers,
+/// retaining the latest `capacity` of them so that subscribers can resume
+/// after a disconnect.
+///
+/// Events aren't retained when the server restarts, so subscribers that
+/// resume from before a restart are sent a `reset` if they may have missed
+/// any. Feeds that are opened in a directory reserve sequence numbers in it
+/// before using them, and continue after the reserved ones, so that they
+/// keep increasing. Otherwise, they start from the time at which the feed
+/// was created, in microseconds since the Unix epoch, which only keeps them
+/// increasing if fewer than 1000 events were published per millisecond.
+///
+/// While more sequence numbers cannot be reserved, events are withheld, and
+/// a `reset` is published once they can be.
+pub struct Feed {
+    namespace: String,
+    next: u64,
+    reserved: Option<Reserved>,
+    withheld: bool,
+    history: VecDeque<Event>,
+    capacity: usize,
+    subscribers: Vec<mpsc::UnboundedSender<Event>>,
+}
+
+/// The sequence numbers that a feed may use, up to but excluding `until`,
+/// as recorded in `dir`.
+struct Reserved {
+    dir: PathBuf,
+    until: u64,
+}
+
+impl Reserved {
+    /// Records that sequence numbers up to `until` may be used. The record
+    /// is written to a temporary file and then renamed, so that it's never
+    /// seen partially written.
+    fn reserve(&mut self, until: u64) -> io::Result<()> {
+        let temp_path = self.dir.join(SEQ_TEMP_FILE);
+        let mut temp = File::create(&temp_path)?;
+
+        temp.write_all(until.to_string().as_bytes())?;
+        temp.sync_all()?;
+
+        fs::rename(&temp_path, self.dir.join(SEQ_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.until = until;
+
+        Ok(())
+    }
+}
+
+impl Feed {
+    pub fn new(namespace: &str, capacity: usize) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            next: persistence::to_millis(SystemTime::now()) * 1000 + 1,
+            reserved: None,
+            withheld: false,
+            history: VecDeque::new(),
+            capacity,
+            subscribers: vec![],
+        }
+    }
+
+    /// Creates a feed that reserves its sequence numbers in `dir`, starting
+    /// after those reserved by any feed that was opened in it before.
+    pub fn open(namespace: &str, capacity: usize, dir: &Path) -> io::Result<Self> {
+        let next = match fs::read_to_string(dir.join(SEQ_FILE)) {
+            Ok(content) => content
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            next,
+            reserved: Some(Reserved {
+                dir: dir.to_path_buf(),
+                until: next,
+            }),
+            ..Self::new(namespace, capacity)
+        })
+    }
+
+    /// Numbers events and delivers them to subscribers, returning them so
+    /// that they can be sent elsewhere too. Events that cannot be numbered,
+    /// because more sequence numbers cannot be reserved, are withheld.
+    pub fn publish(&mut self, mut kinds: Vec<Kind>) -> Vec<Event> {
+        if kinds.is_empty() {
+            return vec![];
+        }
+
+        if self.withheld {
+            kinds.insert(0, Kind::Reset);
+        }
+
+        let needed = self.next + kinds.len() as u64;
+
+        if let Some(ref mut reserved) = self.reserved {
+            if needed > reserved.until {
+                if let Err(e) = reserved.reserve(needed + SEQ_RESERVATION) {
+                    eprintln!(
+                        "cannot reserve event sequence numbers, withholding {} events: {}",
+                        kinds.len(),
+                        e
+                    );
+
+                    self.withheld = true;
+
+                    return vec![];
+                }
+            }
+        }
+
+        self.withheld = false;
+
+        let time = persistence::to_millis(SystemTime::now());
+
+        let events = kinds
+            .into_iter()
+            .map(|kind| {
+                let seq = self.next;
+                self.next += 1;
+
+                Event {
+                    seq,
+                    time,
+                    namespace: self.namespace.clone(),
+                    kind,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for event in &events {
+            self.history.push_back(event.clone());
+        }
+
+        while self.history.len() > self.capacity {
+            self.history.pop_front();
+        }
+
+        // Subscribers that have disconnected are dropped
+
+        self.subscribers.retain(|subscriber| {
+            events
+                .iter()
+                .all(|event| subscriber.unbounded_send(event.clone()).is_ok())
+        });
+
+        events
+    }
+
+    /// Subscribes to the events that happen from now on. Given the sequence
+    /// number of the last event that a subscriber received, the events that
+    /// followed it are delivered first, or if some of them are no longer
+    /// retained, a `reset` event is.
+    pub fn subscribe(&mut self, last: Option<u64>) -> mpsc::UnboundedReceiver<Event> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        if let Some(last) = last {
+            let oldest = self.history.front().map(|e| e.seq).unwrap_or(self.next);
+
+            if last + 1 >= oldest && last < self.next {
+                for event in self.history.iter().filter(|e| e.seq > last) {
+                    let _ = sender.unbounded_send(event.clone());
+                }
+            } else {
+                let _ = sender.unbounded_send(Event {
+                    seq: self.next - 1,
+                    time: persistence::to_millis(SystemTime::now()),
+                    namespace: self.namespace.clone(),
+                    kind: Kind::Reset,
+                });
+            }
+        }
+
+        self.subscribers.push(sender);
+
+        receiver
+    }
+}
+
+/// Posts events to a URL as a JSON array, e.g. `http://10.0.0.2:8080/hooks`.
+/// Requests aren't ordered, nor retried if they fail, so receivers should
+/// order events by their sequence numbers, and look out for gaps.
+#[derive(Clone)]
+pub struct Webhook {
+    client: Client<HttpConnector>,
+    url: Option<Uri>,
+}
+
+impl Webhook {
+    pub fn new(url: Option<Uri>) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+        }
+    }
+
+    /// Sends events, if there are any and there's a URL to send them to.
+    /// Failures are reported.
+    pub fn send(&self, events: &[Event]) {
+        let url = match self.url {
+            Some(ref url) if !events.is_empty() => url.clone(),
+            _ => return,
+        };
+
+        let request = serde_json::to_vec(events)
+            .map_err(|e| e.to_string())
+            .and_then(|body| {
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(url.clone())
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body))
+                    .map_err(|e| e.to_string())
+            });
+
+        let request = match request {
+            Ok(request) => request,
+
+            Err(e) => {
+                eprintln!("cannot send events to {}: {}", url, e);
+                return;
+            }
+        };
+
+        tokio::spawn(
+            self.client
+                .request(request)
+                .map_err(|e| e.to_string())
+                .and_then(|response| {
+                    if response.status().is_success() {
+                        Ok(())
+                    } else {
+                        Err(format!("status {}", response.status()))
+                    }
+                })
+                .map_err(move |e| eprintln!("cannot send events to {}: {}", url, e)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    fn created() -> Kind {
+        Kind::Created {
+            id: ScriptId::random(),
+        }
+    }
+
+    fn receive(receiver: mpsc::UnboundedReceiver<Event>, count: usize) -> Vec<Event> {
+        receiver.take(count as u64).collect().wait().unwrap()
+    }
+
+    #[test]
+    fn test_events_feed() {
+        let mut feed = Feed::new("billing", 2);
+
+        let live = feed.subscribe(None);
+        let events = feed.publish(vec![created(), created()]);
+
+        assert_eq!(events[1].seq, events[0].seq + 1);
+        assert_eq!(events[0].namespace, "billing");
+        assert_eq!(receive(live, 2), events);
+
+        let first = events[0].seq;
+
+        // Subscribers resume after the last event they received
+
+        let resumed = feed.subscribe(Some(first));
+        assert_eq!(receive(resumed, 1), vec![events[1].clone()]);
+
+        let events = feed.publish(vec![created(), created()]);
+        let resumed = feed.subscribe(Some(first + 1));
+        assert_eq!(receive(resumed, 2), events);
+
+        // Events that are no longer retained cannot be resumed from
+
+        let reset = receive(feed.subscribe(Some(first)), 1);
+        assert_eq!(reset[0].kind, Kind::Reset);
+        assert_eq!(reset[0].seq, events[1].seq);
+
+        let reset = receive(feed.subscribe(Some(first + 100)), 1);
+        assert_eq!(reset[0].kind, Kind::Reset);
+    }
+
+    #[test]
+    fn test_events_feed_restart() {
+        let dir = crate::persistence::tests::data_dir();
+
+        // More events are published than fit in a millisecond, or in one
+        // reservation
+
+        let mut feed = Feed::open("billing", 10, &dir).unwrap();
+        let events = feed.publish((0..20_000).map(|_| created()).collect());
+
+        assert_eq!(events[0].seq, 1);
+
+        drop(feed);
+
+        let mut feed = Feed::open("billing", 10, &dir).unwrap();
+        let restarted = feed.publish(vec![created()]);
+
+        assert!(restarted[0].seq > events.last().unwrap().seq);
+
+        // Subscribers cannot resume from before the restart
+
+        let reset = receive(feed.subscribe(Some(events[19_998].seq)), 1);
+        assert_eq!(reset[0].kind, Kind::Reset);
+        assert_eq!(reset[0].seq, restarted[0].seq);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_events_feed_reservation_failure() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::persistence::tests::data_dir();
+
+        let mut feed = Feed::open("billing", 10, &dir).unwrap();
+        let live = feed.subscribe(None);
+
+        // The directory is read-only, and its temporary file cannot be
+        // created regardless, e.g. when running as root
+
+        fs::create_dir(dir.join(SEQ_TEMP_FILE)).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        assert!(feed.publish(vec![created()]).is_empty());
+        assert!(feed.publish(vec![created()]).is_empty());
+        assert_eq!(feed.reserved.as_ref().unwrap().until, 1);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir(dir.join(SEQ_TEMP_FILE)).unwrap();
+
+        // Once numbers can be reserved again, a reset is published first
+
+        let events = feed.publish(vec![created()]);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, Kind::Reset);
+        assert_eq!(events[0].seq, 1);
+        assert_eq!(receive(live, 2), events);
+
+        drop(feed);
+
+        let mut feed = Feed::open("billing", 10, &dir).unwrap();
+        let restarted = feed.publish(vec![created()]);
+
+        assert!(restarted[0].seq > events[1].seq);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_events_to_sse() {
+        let event = Event {
+            seq: 7,
+            time: 1000,
+            namespace: String::new(),
+            kind: Kind::NameDeleted {
+                name: "adder".to_string(),
+            },
+        };
+
+        assert_eq!(
+            event.to_sse(),
+            "id: 7\ndata: {\"seq\":7,\"time\":1000,\"namespace\":\"\",\"type\":\"name_deleted\",\"name\":\"adder\"}\n\n"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use crate::codec::Encoding;
+use crate::multipart;
+use crate::negotiation;
+use std::io;
+
+/// Code to execute once, along with the arguments to call it with.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Execution {
+    pub(crate) code: String,
+    pub(crate) encoding: Encoding,
+    pub(crate) args: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct ExecutionDefinition {
+    code: String,
+    #[serde(default)]
+    args: Option<serde_json::Value>,
+}
+
+impl Execution {
+    /// Parses an execution from a request body, given its `Content-Type`.
+    /// The body is either the code itself, which is called without any
+    /// arguments, or a JSON object that also supplies them, e.g.
+    ///
+    ///   {"code": "function(a, b) { return a + b; }", "args": [1, 2]}
+    ///
+    /// Alternatively, a `multipart/form-data` body has a `code` part and an
+    /// optional `args` part, whose own `Content-Type` declares its encoding
+    /// as for a registered script's arguments.
+    pub(crate) fn parse(content_type: Option<&str>, body: &[u8]) -> io::Result<Self> {
+        match content_type.and_then(multipart::boundary) {
+            Some(boundary) => Self::parse_multipart(body, &boundary),
+            None => Self::parse_json(body),
+        }
+    }
+
+    fn parse_json(body: &[u8]) -> io::Result<Self> {
+        let code = utf8(body, "cannot extract script from request body")?;
+
+        if !code.trim_start().starts_with('{') {
+            return Ok(Self::new(code.to_string()));
+        }
+
+        let definition = serde_json::from_str::<ExecutionDefinition>(code)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(match definition.args {
+            Some(args) => Self {
+                code: definition.code,
+                encoding: Encoding::Json,
+                args: args.to_string().into_bytes(),
+            },
+
+            None => Self::new(definition.code),
+        })
+    }
+
+    fn parse_multipart(body: &[u8], boundary: &str) -> io::Result<Self> {
+        let mut code = None;
+        let mut args = None;
+
+        for part in multipart::parse(body, boundary)? {
+            match part.name.as_ref().map(String::as_str) {
+                Some("code") => {
+                    code = Some(utf8(part.body, "cannot extract script from request body")?);
+                }
+
+                Some("args") => {
+                    let encoding =
+                        negotiation::args_encoding(part.content_type.as_ref().map(String::as_str));
+
+                    if encoding == Encoding::Json {
+                        utf8(part.body, "cannot extract arguments from request body")?;
+                    }
+
+                    args = Some((encoding, part.body));
+                }
+
+                _ => {}
+            }
+        }
+
+        let code = code.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "multipart body has no code part",
+            )
+        })?;
+
+        Ok(match args {
+            Some((encoding, args)) => Self {
+                code: code.to_string(),
+                encoding,
+                args: args.to_vec(),
+            },
+
+            None => Self::new(code.to_string()),
+        })
+    }
+
+    fn new(code: String) -> Self {
+        Self {
+            code,
+            encoding: Encoding::Json,
+            args: b"[]".to_vec(),
+        }
+    }
+}
+
+fn utf8<'a>(data: &'a [u8], message: &str) -> io::Result<&'a str> {
+    std::str::from_utf8(data).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execution_parse() {
+        assert_eq!(
+            Execution::parse(None, b"function() { return 1; }").unwrap(),
+            Execution {
+                code: "function() { return 1; }".to_string(),
+                encoding: Encoding::Json,
+                args: b"[]".to_vec(),
+            }
+        );
+
+        assert_eq!(
+            Execution::parse(
+                Some("application/json"),
+                b" {\"code\": \"function(a) { return a; }\", \"args\": [{\"b\": 2}]}"
+            )
+            .unwrap(),
+            Execution {
+                code: "function(a) { return a; }".to_string(),
+                encoding: Encoding::Json,
+                args: b"[{\"b\":2}]".to_vec(),
+            }
+        );
+
+        assert_eq!(
+            Execution::parse(None, b"{\"code\": \"function() {}\"}")
+                .unwrap()
+                .args,
+            b"[]".to_vec()
+        );
+
+        assert!(Execution::parse(None, b"{\"args\": []}").is_err());
+        assert!(Execution::parse(None, &[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_execution_parse_multipart() {
+        let content_type = Some("multipart/form-data; boundary=b");
+
+        let body = b"--b\r\n\
+            Content-Disposition: form-data; name=\"args\"\r\n\
+            Content-Type: application/msgpack\r\n\
+            \r\n\
+            \x91\x01\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"code\"\r\n\
+            \r\n\
+            function(a) { return a; }\r\n\
+            --b--\r\n";
+
+        assert_eq!(
+            Execution::parse(content_type, body).unwrap(),
+            Execution {
+                code: "function(a) { return a; }".to_string(),
+                encoding: Encoding::MsgPack,
+                args: vec![0x91, 0x01],
+            }
+        );
+
+        let body = b"--b\r\n\
+            Content-Disposition: form-data; name=\"args\"\r\n\
+            \r\n\
+            [1]\r\n\
+            --b--\r\n";
+
+        assert!(Execution::parse(content_type, body).is_err());
+    }
+}
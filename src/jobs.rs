@@ -0,0 +1,287 @@
+use crate::script_registry::ScriptId;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// An execution of a script that happens in the background. Its result
+/// is retained until some time after it has finished.
+pub(crate) struct Job {
+    pub(crate) script: ScriptId,
+    pub(crate) status: JobStatus,
+    pub(crate) result: Option<String>,
+    pub(crate) error: Option<String>,
+    cancelled: Arc<AtomicBool>,
+    finished: Option<Instant>,
+}
+
+/// The representation of a job that is returned to clients.
+#[derive(Serialize)]
+pub(crate) struct JobView {
+    id: String,
+    script: String,
+    status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Job {
+    pub(crate) fn view(&self, id: &Uuid) -> JobView {
+        JobView {
+            id: id.to_string(),
+            script: self.script.to_string(),
+            status: self.status,
+            result: self
+                .result
+                .as_ref()
+                .and_then(|r| serde_json::from_str(r).ok()),
+            error: self.error.clone(),
+        }
+    }
+
+    fn finish(&mut self, status: JobStatus) {
+        self.status = status;
+        self.finished = Some(Instant::now());
+    }
+
+    fn is_expired(&self, retention: Duration, now: Instant) -> bool {
+        match self.finished {
+            Some(finished) => finished + retention < now,
+            None => false,
+        }
+    }
+}
+
+/// Defines a local registry for jobs, where they're tracked from
+/// creation until their retention time elapses after they've
+/// finished. At most `max_pending` jobs may be unfinished at a time,
+/// where 0 signifies no maximum.
+pub(crate) struct JobRegistry {
+    retention: Duration,
+    max_pending: usize,
+    jobs: HashMap<Uuid, Job>,
+}
+
+impl JobRegistry {
+    pub(crate) fn new(retention: Duration, max_pending: usize) -> Self {
+        Self {
+            retention,
+            max_pending,
+            jobs: HashMap::new(),
+        }
+    }
+
+    /// Creates a pending job for the provided script, evicting any
+    /// finished jobs whose retention time has elapsed. Returns the job's
+    /// id and a flag that signals its cancellation, or an error if there
+    /// are too many unfinished jobs already.
+    pub(crate) fn create(&mut self, script: ScriptId) -> io::Result<(Uuid, Arc<AtomicBool>)> {
+        self.remove_expired();
+
+        let pending = self
+            .jobs
+            .values()
+            .filter(|job| job.finished.is_none())
+            .count();
+
+        if self.max_pending > 0 && pending >= self.max_pending {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("cannot hold more than {} unfinished jobs", self.max_pending),
+            ));
+        }
+
+        let id = Uuid::new_v4();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        self.jobs.insert(
+            id,
+            Job {
+                script,
+                status: JobStatus::Pending,
+                result: None,
+                error: None,
+                cancelled: cancelled.clone(),
+                finished: None,
+            },
+        );
+
+        Ok((id, cancelled))
+    }
+
+    /// Returns a job, unless its retention time has elapsed.
+    pub(crate) fn get(&self, id: &Uuid) -> Option<&Job> {
+        let now = Instant::now();
+
+        self.jobs
+            .get(id)
+            .filter(|job| !job.is_expired(self.retention, now))
+    }
+
+    /// Removes finished jobs whose retention time has elapsed.
+    pub(crate) fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let retention = self.retention;
+
+        self.jobs.retain(|_, job| !job.is_expired(retention, now));
+    }
+
+    /// Marks a pending job as running.
+    pub(crate) fn start(&mut self, id: &Uuid) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            if job.status == JobStatus::Pending {
+                job.status = JobStatus::Running;
+            }
+        }
+    }
+
+    /// Records the result of a job, unless it has been cancelled. A job whose
+    /// script returned `undefined` completes without a result.
+    pub(crate) fn complete(&mut self, id: &Uuid, result: io::Result<Option<String>>) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            if job.status == JobStatus::Pending || job.status == JobStatus::Running {
+                match result {
+                    Ok(json) => {
+                        job.result = json;
+                        job.finish(JobStatus::Completed);
+                    }
+
+                    Err(e) => {
+                        job.error = Some(e.to_string());
+                        job.finish(JobStatus::Failed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cancels a job that hasn't finished yet, stopping its execution, or
+    /// removes a job that has. Returns `false` if the job cannot be found.
+    pub(crate) fn cancel(&mut self, id: &Uuid) -> bool {
+        self.remove_expired();
+
+        let finished = match self.jobs.get_mut(id) {
+            Some(job) if job.finished.is_none() => {
+                job.cancelled.store(true, Ordering::SeqCst);
+                job.finish(JobStatus::Cancelled);
+
+                false
+            }
+
+            Some(_) => true,
+
+            None => return false,
+        };
+
+        if finished {
+            self.jobs.remove(id);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_registry_lifecycle() {
+        let mut jobs = JobRegistry::new(Duration::from_millis(60000), 0);
+        let script = ScriptId::random();
+
+        let (id, _) = jobs.create(script.clone()).unwrap();
+        assert_eq!(jobs.get(&id).map(|j| j.status), Some(JobStatus::Pending));
+
+        jobs.start(&id);
+        assert_eq!(jobs.get(&id).map(|j| j.status), Some(JobStatus::Running));
+
+        jobs.complete(&id, Ok(Some("42".to_string())));
+        assert_eq!(jobs.get(&id).map(|j| j.status), Some(JobStatus::Completed));
+        assert_eq!(
+            jobs.get(&id).and_then(|j| j.result.clone()),
+            Some("42".to_string())
+        );
+
+        let (failed, _) = jobs.create(script).unwrap();
+        jobs.complete(&failed, Err(io::Error::new(io::ErrorKind::Other, "oops")));
+        assert_eq!(jobs.get(&failed).map(|j| j.status), Some(JobStatus::Failed));
+        assert_eq!(
+            jobs.get(&failed).and_then(|j| j.error.clone()),
+            Some("oops".to_string())
+        );
+    }
+
+    #[test]
+    fn test_job_registry_cancel() {
+        let mut jobs = JobRegistry::new(Duration::from_millis(60000), 0);
+
+        let (id, cancelled) = jobs.create(ScriptId::random()).unwrap();
+        jobs.start(&id);
+
+        assert!(jobs.cancel(&id));
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert_eq!(jobs.get(&id).map(|j| j.status), Some(JobStatus::Cancelled));
+
+        // A result arriving after cancellation is ignored
+        jobs.complete(&id, Ok(Some("42".to_string())));
+        assert_eq!(jobs.get(&id).map(|j| j.status), Some(JobStatus::Cancelled));
+
+        // Cancelling a finished job removes it
+        assert!(jobs.cancel(&id));
+        assert!(jobs.get(&id).is_none());
+        assert!(!jobs.cancel(&id));
+    }
+
+    #[test]
+    fn test_job_registry_evicts_finished_jobs() {
+        let mut jobs = JobRegistry::new(Duration::from_millis(1), 0);
+
+        let (finished, _) = jobs.create(ScriptId::random()).unwrap();
+        let (running, _) = jobs.create(ScriptId::random()).unwrap();
+        jobs.complete(&finished, Ok(None));
+        jobs.start(&running);
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Expired jobs cannot be read, even before they're removed
+
+        assert!(jobs.get(&finished).is_none());
+        assert!(jobs.get(&running).is_some());
+        assert_eq!(jobs.jobs.len(), 2);
+
+        jobs.remove_expired();
+        assert_eq!(jobs.jobs.len(), 1);
+    }
+
+    #[test]
+    fn test_job_registry_max_pending() {
+        let mut jobs = JobRegistry::new(Duration::from_millis(60000), 2);
+
+        let (first, _) = jobs.create(ScriptId::random()).unwrap();
+        let (second, _) = jobs.create(ScriptId::random()).unwrap();
+        jobs.start(&second);
+
+        assert!(jobs.create(ScriptId::random()).is_err());
+
+        // Finished jobs don't count towards the maximum
+
+        jobs.complete(&first, Ok(None));
+        assert!(jobs.create(ScriptId::random()).is_ok());
+        assert!(jobs.create(ScriptId::random()).is_err());
+    }
+}
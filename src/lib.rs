@@ -0,0 +1,23 @@
+//! The script registry that jsaas keeps defined scripts in, along with the
+//! stores that hold them. Other stores can be written against the
+//! `store::ScriptStore` trait.
+
+extern crate futures;
+extern crate hyper;
+extern crate serde_json;
+extern crate sha2;
+extern crate uuid;
+
+#[macro_use]
+extern crate serde_derive;
+
+pub mod bundle;
+pub mod events;
+pub mod listing;
+pub mod persistence;
+pub mod replication;
+pub mod script_registry;
+pub mod self_test;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
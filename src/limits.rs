@@ -0,0 +1,205 @@
+use crate::duktape::Limits;
+use hyper::http::response::Builder;
+use hyper::HeaderMap;
+use std::io;
+use std::time::Duration;
+
+pub(crate) const HEADER_TIMEOUT_MS: &str = "X-Jsaas-Timeout-Ms";
+pub(crate) const HEADER_MEMORY_LIMIT: &str = "X-Jsaas-Memory-Limit";
+
+/// The smallest memory limit that a request may ask for. Below this, even
+/// loading a script and its arguments would fail.
+pub(crate) const MIN_MEMORY_LIMIT: usize = 64 * 1024;
+
+/// The limits that the operator has configured. `default` applies when a
+/// request doesn't specify a limit, and requested limits are clamped to
+/// `maximum`. A memory limit of `None` is unbounded.
+#[derive(Clone, Copy)]
+pub(crate) struct LimitSettings {
+    pub(crate) default: Limits,
+    pub(crate) maximum: Limits,
+}
+
+impl LimitSettings {
+    /// Determines the limits to apply for a request, given its headers. A
+    /// requested memory limit below `MIN_MEMORY_LIMIT` is rejected.
+    pub(crate) fn for_request(&self, headers: &HeaderMap) -> io::Result<Limits> {
+        let time = match header_value(headers, HEADER_TIMEOUT_MS)? {
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => self.default.time,
+        };
+
+        let requested_memory = header_value(headers, HEADER_MEMORY_LIMIT)?;
+
+        if let Some(memory) = requested_memory {
+            if memory < MIN_MEMORY_LIMIT {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} must be at least {}",
+                        HEADER_MEMORY_LIMIT, MIN_MEMORY_LIMIT
+                    ),
+                ));
+            }
+        }
+
+        let memory = requested_memory.or(self.default.memory);
+
+        Ok(Limits {
+            time: if time > self.maximum.time {
+                self.maximum.time
+            } else {
+                time
+            },
+
+            memory: match (memory, self.maximum.memory) {
+                (Some(m), Some(max)) if m > max => Some(max),
+                (None, max) => max,
+                (m, _) => m,
+            },
+        })
+    }
+}
+
+/// Adds headers to a response that describe the limits that were applied.
+pub(crate) fn echo(builder: &mut Builder, limits: &Limits) {
+    builder.header(HEADER_TIMEOUT_MS, limits.time.as_millis().to_string());
+
+    if let Some(memory) = limits.memory {
+        builder.header(HEADER_MEMORY_LIMIT, memory.to_string());
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> io::Result<Option<usize>> {
+    match headers.get(name) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .map(Some)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} must be a non-negative integer", name),
+                )
+            }),
+
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Response;
+
+    fn settings(default_memory: Option<usize>, maximum_memory: Option<usize>) -> LimitSettings {
+        LimitSettings {
+            default: Limits {
+                time: Duration::from_millis(10000),
+                memory: default_memory,
+            },
+            maximum: Limits {
+                time: Duration::from_millis(60000),
+                memory: maximum_memory,
+            },
+        }
+    }
+
+    fn headers(values: Vec<(&'static str, &'static str)>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in values {
+            headers.insert(name, value.parse().unwrap());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn test_limits_defaults() {
+        assert_eq!(
+            settings(None, None).for_request(&headers(vec![])).unwrap(),
+            Limits {
+                time: Duration::from_millis(10000),
+                memory: None
+            }
+        );
+
+        assert_eq!(
+            settings(None, Some(262144))
+                .for_request(&headers(vec![]))
+                .unwrap(),
+            Limits {
+                time: Duration::from_millis(10000),
+                memory: Some(262144)
+            }
+        );
+    }
+
+    #[test]
+    fn test_limits_requested_and_clamped() {
+        assert_eq!(
+            settings(None, Some(262144))
+                .for_request(&headers(vec![
+                    ("x-jsaas-timeout-ms", "500"),
+                    ("x-jsaas-memory-limit", "131072")
+                ]))
+                .unwrap(),
+            Limits {
+                time: Duration::from_millis(500),
+                memory: Some(131072)
+            }
+        );
+
+        assert_eq!(
+            settings(None, Some(262144))
+                .for_request(&headers(vec![
+                    ("x-jsaas-timeout-ms", "120000"),
+                    ("x-jsaas-memory-limit", "524288")
+                ]))
+                .unwrap(),
+            Limits {
+                time: Duration::from_millis(60000),
+                memory: Some(262144)
+            }
+        );
+    }
+
+    #[test]
+    fn test_limits_invalid() {
+        assert!(settings(None, None)
+            .for_request(&headers(vec![("x-jsaas-timeout-ms", "soon")]))
+            .is_err());
+
+        assert!(settings(None, None)
+            .for_request(&headers(vec![("x-jsaas-memory-limit", "-1")]))
+            .is_err());
+
+        assert!(settings(None, None)
+            .for_request(&headers(vec![("x-jsaas-memory-limit", "0")]))
+            .is_err());
+
+        assert!(settings(None, None)
+            .for_request(&headers(vec![("x-jsaas-memory-limit", "65535")]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_limits_echo() {
+        let mut builder = Response::builder();
+
+        echo(
+            &mut builder,
+            &Limits {
+                time: Duration::from_millis(500),
+                memory: Some(1024),
+            },
+        );
+
+        let response = builder.body(()).unwrap();
+
+        assert_eq!(response.headers()["x-jsaas-timeout-ms"], "500");
+        assert_eq!(response.headers()["x-jsaas-memory-limit"], "1024");
+    }
+}
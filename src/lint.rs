@@ -0,0 +1,858 @@
+use std::collections::HashSet;
+use std::io;
+
+const KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "return",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Names that are always defined within a function, but aren't globals.
+const FUNCTION_NAMES: &[&str] = &["arguments"];
+
+/// Keywords after which a `/` begins a regular expression, not a division.
+const REGEX_PRECEDING_KEYWORDS: &[&str] = &[
+    "case",
+    "delete",
+    "do",
+    "else",
+    "in",
+    "instanceof",
+    "new",
+    "return",
+    "throw",
+    "typeof",
+    "void",
+];
+
+const PUNCTUATORS: &[&str] = &[
+    ">>>=", "...", "===", "!==", "**=", "<<=", ">>=", ">>>", "=>", "==", "!=", "<=", ">=", "&&",
+    "||", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "**",
+];
+
+/// A problem found in a script, and where it is, if known. Lines and columns
+/// start at 1.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Problem {
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+/// A use of a global that isn't defined, at its first occurrence.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Global {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct Metrics {
+    bytes: usize,
+    lines: usize,
+    tokens: usize,
+    functions: usize,
+    complexity: usize,
+    max_depth: usize,
+}
+
+/// The outcome of linting a script.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Report {
+    valid: bool,
+    errors: Vec<Problem>,
+    parameters: Vec<String>,
+    undefined_globals: Vec<Global>,
+    warnings: Vec<Problem>,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Identifier,
+    Number,
+    String,
+    Regex,
+    Punctuator,
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::String && self.kind != Kind::Regex && self.text == text
+    }
+
+    fn is_name(&self) -> bool {
+        self.kind == Kind::Identifier && !KEYWORDS.contains(&self.text)
+    }
+
+    fn warning(&self, message: &str) -> Problem {
+        Problem {
+            message: message.to_string(),
+            line: Some(self.line),
+            column: Some(self.column),
+        }
+    }
+}
+
+/// Analyzes a script without executing it, given the outcome of compiling
+/// it, and the globals that are defined for scripts.
+///
+/// The analysis works on the script's tokens, so it's approximate: scopes
+/// aren't distinguished, so a name declared anywhere in the script is
+/// considered to be defined everywhere.
+pub(crate) fn lint(code: &str, compiled: io::Result<()>, globals: &[String]) -> Report {
+    let mut errors = vec![];
+    let mut warnings = vec![];
+
+    if let Err(e) = compiled {
+        let message = e.to_string();
+        let line = error_line(&message);
+
+        errors.push(Problem {
+            message,
+            line,
+            column: None,
+        });
+    }
+
+    let mut metrics = Metrics {
+        bytes: code.len(),
+        lines: code.lines().count(),
+        ..Metrics::default()
+    };
+
+    let tokens = match tokenize(code) {
+        Ok(tokens) => tokens,
+
+        Err(problem) => {
+            // Duktape usually reports the same problem, but without a column.
+            // If it compiled the script, the problem is with the analysis.
+
+            if errors.is_empty() {
+                warnings.push(Problem {
+                    message: format!("cannot analyze script: {}", problem.message),
+                    ..problem
+                });
+            } else if errors.iter().all(|e| e.line != problem.line) {
+                errors.push(problem);
+            }
+
+            return Report {
+                valid: errors.is_empty(),
+                errors,
+                parameters: vec![],
+                undefined_globals: vec![],
+                warnings,
+                metrics,
+            };
+        }
+    };
+
+    metrics.tokens = tokens.len();
+    metrics.functions = tokens
+        .iter()
+        .filter(|t| t.is("function") || t.is("=>"))
+        .count();
+    metrics.complexity = 1 + tokens
+        .iter()
+        .filter(|t| {
+            ["if", "for", "while", "case", "catch", "&&", "||", "?"]
+                .iter()
+                .any(|b| t.is(b))
+        })
+        .count();
+    metrics.max_depth = max_depth(&tokens);
+
+    warnings.extend(risky_constructs(&tokens));
+
+    Report {
+        valid: errors.is_empty(),
+        errors,
+        parameters: parameters(&tokens),
+        undefined_globals: undefined_globals(&tokens, globals),
+        warnings,
+        metrics,
+    }
+}
+
+/// Extracts the line from a Duktape error message, e.g. "... (line 3)".
+fn error_line(message: &str) -> Option<usize> {
+    let start = message.rfind("(line ")? + 6;
+
+    message[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|line| line.parse().ok())
+}
+
+/// The parameters of the script's function, i.e. of its first function.
+fn parameters(tokens: &[Token]) -> Vec<String> {
+    let start = match tokens.iter().position(|t| t.is("function")) {
+        Some(i) if tokens.get(i + 1).map(Token::is_name).unwrap_or(false) => i + 2,
+        Some(i) => i + 1,
+        None => return vec![],
+    };
+
+    if !tokens.get(start).map(|t| t.is("(")).unwrap_or(false) {
+        return vec![];
+    }
+
+    tokens[start + 1..]
+        .iter()
+        .take_while(|t| !t.is(")"))
+        .filter(|t| t.is_name())
+        .map(|t| t.text.to_string())
+        .collect()
+}
+
+/// Collects the names that the script declares: variables, functions and
+/// their parameters, and caught exceptions.
+fn declarations<'a>(tokens: &[Token<'a>]) -> HashSet<&'a str> {
+    let mut declared = HashSet::new();
+
+    // The depth of the variable declaration that's being read, if any
+    let mut declaration: Option<usize> = None;
+    let mut depth: usize = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = if i > 0 { tokens.get(i - 1) } else { None };
+
+        if token.is("(") || token.is("[") || token.is("{") {
+            depth += 1;
+        } else if token.is(")") || token.is("]") || token.is("}") {
+            depth = depth.saturating_sub(1);
+
+            if declaration.map(|d| depth < d).unwrap_or(false) {
+                declaration = None;
+            }
+        } else if token.is(";") || token.is("in") || token.is("of") {
+            if declaration == Some(depth) {
+                declaration = None;
+            }
+        } else if token.is("var") || token.is("let") || token.is("const") {
+            declaration = Some(depth);
+        } else if token.is_name() {
+            let declares = match previous {
+                Some(p) if p.is("var") || p.is("let") || p.is("const") || p.is("function") => true,
+                Some(p) if p.is(",") => declaration == Some(depth),
+                _ => false,
+            };
+
+            if declares {
+                declared.insert(token.text);
+            }
+        }
+
+        // Parameters of functions, and of catch clauses
+
+        let params_start = if token.is("function") {
+            match tokens.get(i + 1) {
+                Some(t) if t.is_name() => Some(i + 2),
+                _ => Some(i + 1),
+            }
+        } else if token.is("catch") {
+            Some(i + 1)
+        } else {
+            None
+        };
+
+        if let Some(start) = params_start {
+            if tokens.get(start).map(|t| t.is("(")).unwrap_or(false) {
+                tokens[start + 1..]
+                    .iter()
+                    .take_while(|t| !t.is(")"))
+                    .filter(|t| t.is_name())
+                    .for_each(|t| {
+                        declared.insert(t.text);
+                    });
+            }
+        }
+
+        // Parameters of arrow functions, e.g. `x => x` and `(x, y) => x`
+
+        if token.is("=>") {
+            match previous {
+                Some(p) if p.is_name() => {
+                    declared.insert(p.text);
+                }
+
+                Some(p) if p.is(")") => {
+                    tokens[..i - 1]
+                        .iter()
+                        .rev()
+                        .take_while(|t| !t.is("("))
+                        .filter(|t| t.is_name())
+                        .for_each(|t| {
+                            declared.insert(t.text);
+                        });
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    declared
+}
+
+/// Finds the names that the script uses, but neither declares nor are
+/// globals. Property names, object keys and labels aren't uses.
+fn undefined_globals(tokens: &[Token], globals: &[String]) -> Vec<Global> {
+    let declared = declarations(tokens);
+    let mut reported = HashSet::new();
+    let mut undefined = vec![];
+
+    // Pending `?` operators at each level of nesting, which distinguishes the
+    // `:` of a conditional expression from that of an object key or label
+    let mut conditionals: Vec<usize> = vec![0];
+
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = if i > 0 { tokens.get(i - 1) } else { None };
+        let next = tokens.get(i + 1);
+
+        if token.is("(") || token.is("[") || token.is("{") {
+            conditionals.push(0);
+        } else if (token.is(")") || token.is("]") || token.is("}")) && conditionals.len() > 1 {
+            conditionals.pop();
+        } else if token.is("?") {
+            if let Some(c) = conditionals.last_mut() {
+                *c += 1;
+            }
+        } else if token.is(":") {
+            if let Some(c) = conditionals.last_mut() {
+                *c = c.saturating_sub(1);
+            }
+        }
+
+        if !token.is_name() {
+            continue;
+        }
+
+        let is_property = previous.map(|p| p.is(".")).unwrap_or(false);
+
+        let is_label = previous
+            .map(|p| p.is("break") || p.is("continue"))
+            .unwrap_or(false);
+
+        let is_key = next.map(|n| n.is(":")).unwrap_or(false)
+            && conditionals.last().cloned().unwrap_or(0) == 0;
+
+        if is_property
+            || is_label
+            || is_key
+            || declared.contains(token.text)
+            || FUNCTION_NAMES.contains(&token.text)
+            || globals.iter().any(|g| g == token.text)
+            || !reported.insert(token.text)
+        {
+            continue;
+        }
+
+        undefined.push(Global {
+            name: token.text.to_string(),
+            line: token.line,
+            column: token.column,
+        });
+    }
+
+    undefined
+}
+
+/// Flags constructs that are risky to execute: dynamic evaluation of code,
+/// and loops that can only end by being stopped.
+fn risky_constructs(tokens: &[Token]) -> Vec<Problem> {
+    let mut warnings = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        let is_property = i > 0 && tokens[i - 1].is(".");
+
+        if token.is("eval") && !is_property {
+            warnings.push(token.warning("use of eval"));
+        } else if token.is("Function") && !is_property {
+            warnings.push(token.warning("use of the Function constructor"));
+        } else if (token.is("while") || token.is("for")) && is_unbounded_loop(tokens, i) {
+            warnings.push(token.warning("unbounded loop without break, return or throw"));
+        }
+    }
+
+    warnings
+}
+
+/// Determines if the loop at `start` always continues, e.g. `while (true)` or
+/// `for (;;)`, and its body has no way of leaving it.
+fn is_unbounded_loop(tokens: &[Token], start: usize) -> bool {
+    let condition: &[&str] = if tokens[start].is("while") {
+        &["(", "true", ")"]
+    } else {
+        &["(", ";", ";", ")"]
+    };
+
+    let always =
+        condition
+            .iter()
+            .enumerate()
+            .all(|(offset, text)| match tokens.get(start + 1 + offset) {
+                Some(t) if *text == "true" => {
+                    t.is("true") || (t.kind == Kind::Number && t.text != "0")
+                }
+                Some(t) => t.is(text),
+                None => false,
+            });
+
+    if !always {
+        return false;
+    }
+
+    let after = start + 1 + condition.len();
+
+    // The body is a block following the condition, or for `do ... while (true)`,
+    // the block preceding it. Otherwise, it's a single statement.
+
+    let body = if tokens.get(after).map(|t| t.is("{")).unwrap_or(false) {
+        let end = matching(tokens, after).unwrap_or(tokens.len());
+
+        &tokens[after..end]
+    } else if start > 0 && tokens[start - 1].is("}") {
+        match opening(tokens, start - 1) {
+            Some(open) if open > 0 && tokens[open - 1].is("do") => &tokens[open..start],
+            _ => &tokens[start..start],
+        }
+    } else {
+        let end = tokens[after..]
+            .iter()
+            .position(|t| t.is(";"))
+            .map(|p| after + p)
+            .unwrap_or(tokens.len());
+
+        &tokens[after..end]
+    };
+
+    !body
+        .iter()
+        .any(|t| t.is("break") || t.is("return") || t.is("throw"))
+}
+
+/// Finds the index of the `}` that closes the `{` at `open`.
+fn matching(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.is("{") {
+            depth += 1;
+        } else if token.is("}") {
+            depth -= 1;
+
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds the index of the `{` that the `}` at `close` closes.
+fn opening(tokens: &[Token], close: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for i in (0..=close).rev() {
+        if tokens[i].is("}") {
+            depth += 1;
+        } else if tokens[i].is("{") {
+            depth -= 1;
+
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+
+    None
+}
+
+fn max_depth(tokens: &[Token]) -> usize {
+    let mut depth: usize = 0;
+    let mut max = 0;
+
+    for token in tokens {
+        if token.is("{") {
+            depth += 1;
+            max = max.max(depth);
+        } else if token.is("}") {
+            depth = depth.saturating_sub(1);
+        }
+    }
+
+    max
+}
+
+/// Splits a script into tokens, skipping whitespace and comments. Fails
+/// with the position of an unterminated comment, string or regular
+/// expression.
+fn tokenize(code: &str) -> Result<Vec<Token>, Problem> {
+    let chars: Vec<(usize, char)> = code.char_indices().collect();
+    let mut tokens: Vec<Token> = vec![];
+
+    let mut i = 0;
+    let mut line = 1;
+    let mut column = 1;
+
+    // Advances past `count` characters, tracking the line and column
+    let advance = |i: &mut usize, line: &mut usize, column: &mut usize, count: usize| {
+        for _ in 0..count {
+            if chars.get(*i).map(|(_, c)| *c == '\n').unwrap_or(false) {
+                *line += 1;
+                *column = 1;
+            } else {
+                *column += 1;
+            }
+
+            *i += 1;
+        }
+    };
+
+    let char_at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let offset_at = |i: usize| chars.get(i).map(|(o, _)| *o).unwrap_or_else(|| code.len());
+
+    while let Some(c) = char_at(i) {
+        let (start, start_line, start_column) = (i, line, column);
+
+        let unterminated = |what: &str| Problem {
+            message: format!("unterminated {}", what),
+            line: Some(start_line),
+            column: Some(start_column),
+        };
+
+        let kind = if c.is_whitespace() {
+            advance(&mut i, &mut line, &mut column, 1);
+            None
+        } else if c == '/' && char_at(i + 1) == Some('/') {
+            while char_at(i).map(|c| c != '\n').unwrap_or(false) {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+
+            None
+        } else if c == '/' && char_at(i + 1) == Some('*') {
+            advance(&mut i, &mut line, &mut column, 2);
+
+            loop {
+                match (char_at(i), char_at(i + 1)) {
+                    (Some('*'), Some('/')) => break,
+                    (Some(_), _) => advance(&mut i, &mut line, &mut column, 1),
+                    (None, _) => return Err(unterminated("comment")),
+                }
+            }
+
+            advance(&mut i, &mut line, &mut column, 2);
+            None
+        } else if c == '"' || c == '\'' || c == '`' {
+            advance(&mut i, &mut line, &mut column, 1);
+
+            loop {
+                match char_at(i) {
+                    Some('\\') => advance(&mut i, &mut line, &mut column, 2),
+                    Some('\n') if c != '`' => return Err(unterminated("string")),
+                    Some(q) if q == c => break,
+                    Some(_) => advance(&mut i, &mut line, &mut column, 1),
+                    None => return Err(unterminated("string")),
+                }
+            }
+
+            advance(&mut i, &mut line, &mut column, 1);
+            Some(Kind::String)
+        } else if c == '/' && regex_allowed(tokens.last()) {
+            advance(&mut i, &mut line, &mut column, 1);
+
+            let mut in_class = false;
+
+            loop {
+                match char_at(i) {
+                    Some('\\') => advance(&mut i, &mut line, &mut column, 2),
+                    Some('[') => {
+                        in_class = true;
+                        advance(&mut i, &mut line, &mut column, 1);
+                    }
+                    Some(']') => {
+                        in_class = false;
+                        advance(&mut i, &mut line, &mut column, 1);
+                    }
+                    Some('/') if !in_class => break,
+                    Some('\n') | None => return Err(unterminated("regular expression")),
+                    Some(_) => advance(&mut i, &mut line, &mut column, 1),
+                }
+            }
+
+            advance(&mut i, &mut line, &mut column, 1);
+
+            while char_at(i).map(is_identifier_part).unwrap_or(false) {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+
+            Some(Kind::Regex)
+        } else if c.is_ascii_digit()
+            || (c == '.' && char_at(i + 1).map(|c| c.is_ascii_digit()).unwrap_or(false))
+        {
+            let hex = c == '0'
+                && char_at(i + 1)
+                    .map(|c| c == 'x' || c == 'X')
+                    .unwrap_or(false);
+
+            loop {
+                match char_at(i) {
+                    Some(c) if c.is_ascii_alphanumeric() || c == '.' || c == '_' => {}
+                    Some('+') | Some('-')
+                        if !hex
+                            && char_at(i - 1)
+                                .map(|c| c == 'e' || c == 'E')
+                                .unwrap_or(false) => {}
+                    _ => break,
+                }
+
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+
+            Some(Kind::Number)
+        } else if is_identifier_start(c) {
+            while char_at(i).map(is_identifier_part).unwrap_or(false) {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+
+            Some(Kind::Identifier)
+        } else {
+            let rest = &code[offset_at(i)..];
+
+            let len = PUNCTUATORS
+                .iter()
+                .find(|p| rest.starts_with(*p))
+                .map(|p| p.len())
+                .unwrap_or(1);
+
+            advance(&mut i, &mut line, &mut column, len);
+            Some(Kind::Punctuator)
+        };
+
+        if let Some(kind) = kind {
+            tokens.push(Token {
+                kind,
+                text: &code[offset_at(start)..offset_at(i)],
+                line: start_line,
+                column: start_column,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Determines if a `/` following the token begins a regular expression.
+fn regex_allowed(previous: Option<&Token>) -> bool {
+    match previous {
+        None => true,
+        Some(t) if t.kind == Kind::Identifier => REGEX_PRECEDING_KEYWORDS.contains(&t.text),
+        Some(t) if t.kind == Kind::Punctuator => !t.is(")") && !t.is("]") && !t.is("}"),
+        Some(_) => false,
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphabetic()
+}
+
+fn is_identifier_part(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globals() -> Vec<String> {
+        vec!["JSON".to_string(), "Math".to_string()]
+    }
+
+    #[test]
+    fn test_lint_report() {
+        let code = "function sum(a, b) {\n  var total = a + b, c = /[/]x/g;\n  return JSON.stringify({ total: total, d: d ? e : [1] }) + missing.x + total.y;\n}";
+
+        let report = lint(code, Ok(()), &globals());
+
+        assert!(report.valid);
+        assert_eq!(report.parameters, vec!["a", "b"]);
+        assert_eq!(
+            report.undefined_globals,
+            vec![
+                Global {
+                    name: "d".to_string(),
+                    line: 3,
+                    column: 44
+                },
+                Global {
+                    name: "e".to_string(),
+                    line: 3,
+                    column: 48
+                },
+                Global {
+                    name: "missing".to_string(),
+                    line: 3,
+                    column: 61
+                },
+            ]
+        );
+        assert!(report.warnings.is_empty());
+        assert_eq!(
+            report.metrics,
+            Metrics {
+                bytes: code.len(),
+                lines: 4,
+                tokens: 50,
+                functions: 1,
+                complexity: 2,
+                max_depth: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_lint_warnings() {
+        let code = "function(s) {\n  eval(s); new Function(s);\n  while (true) { s++; }\n  while (true) { if (s) break; }\n  for (;;) s++;\n  do { s--; } while (1);\n}";
+
+        let report = lint(code, Ok(()), &globals());
+
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .map(|w| (w.message.as_str(), w.line.unwrap(), w.column.unwrap()))
+            .collect();
+
+        assert_eq!(
+            warnings,
+            vec![
+                ("use of eval", 2, 3),
+                ("use of the Function constructor", 2, 16),
+                ("unbounded loop without break, return or throw", 3, 3),
+                ("unbounded loop without break, return or throw", 5, 3),
+                ("unbounded loop without break, return or throw", 6, 15),
+            ]
+        );
+
+        assert_eq!(
+            report.undefined_globals,
+            vec![
+                Global {
+                    name: "eval".to_string(),
+                    line: 2,
+                    column: 3
+                },
+                Global {
+                    name: "Function".to_string(),
+                    line: 2,
+                    column: 16
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_errors() {
+        let compiled = Err(io::Error::new(
+            io::ErrorKind::Other,
+            "SyntaxError: parse error (line 2)",
+        ));
+
+        let report = lint("function() {\n  return +;\n}", compiled, &globals());
+
+        assert!(!report.valid);
+        assert_eq!(
+            report.errors,
+            vec![Problem {
+                message: "SyntaxError: parse error (line 2)".to_string(),
+                line: Some(2),
+                column: None,
+            }]
+        );
+
+        let compiled = Err(io::Error::new(
+            io::ErrorKind::Other,
+            "SyntaxError: unterminated string (line 1, end of input)",
+        ));
+
+        let report = lint("function() {\n  return 'a;\n}", compiled, &globals());
+
+        assert_eq!(
+            report.errors,
+            vec![
+                Problem {
+                    message: "SyntaxError: unterminated string (line 1, end of input)".to_string(),
+                    line: Some(1),
+                    column: None,
+                },
+                Problem {
+                    message: "unterminated string".to_string(),
+                    line: Some(2),
+                    column: Some(10),
+                },
+            ]
+        );
+        assert_eq!(report.metrics.lines, 3);
+
+        // A problem only found by the analysis doesn't invalidate the script
+
+        let report = lint("function() { return 1 /* }", Ok(()), &globals());
+
+        assert!(report.valid);
+        assert_eq!(
+            report.warnings,
+            vec![Problem {
+                message: "cannot analyze script: unterminated comment".to_string(),
+                line: Some(1),
+                column: Some(23),
+            }]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use std::cmp::Ordering;
+use std::io;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// What a listing of scripts is sorted by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    Id,
+    Created,
+    Accessed,
+    Expires,
+    Size,
+    Executions,
+}
+
+/// The parameters of a listing of scripts, as specified by the query string
+/// of its request, e.g.
+///
+///   ?label=billing&name=invoice-&sort=accessed&order=desc&offset=20&limit=10
+///
+/// Scripts are only listed if they have the `label`, and if `name` is
+/// specified, are a version of a named script whose name starts with it.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub label: Option<String>,
+    pub name: Option<String>,
+    pub sort: Sort,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// Describes a script in a listing. Times are milliseconds since the Unix
+/// epoch, and scripts that never expire have no `expires`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    pub size: usize,
+    pub created: u64,
+    pub accessed: u64,
+    pub expires: Option<u64>,
+    pub executions: u64,
+}
+
+/// A page of a listing, along with the number of scripts that matched.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Page {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub scripts: Vec<Summary>,
+}
+
+impl Query {
+    pub fn parse(query: Option<&str>) -> io::Result<Self> {
+        let mut parsed = Self {
+            label: None,
+            name: None,
+            sort: Sort::Created,
+            descending: false,
+            offset: 0,
+            limit: DEFAULT_LIMIT,
+        };
+
+        for param in query.unwrap_or_default().split('&') {
+            if param.is_empty() {
+                continue;
+            }
+
+            let mut fields = param.splitn(2, '=');
+            let name = decode(fields.next().unwrap_or_default())?;
+            let value = decode(fields.next().unwrap_or_default())?;
+
+            match name.as_str() {
+                "label" => parsed.label = Some(value),
+
+                "name" => parsed.name = Some(value),
+
+                "sort" => {
+                    parsed.sort = match value.as_str() {
+                        "id" => Sort::Id,
+                        "created" => Sort::Created,
+                        "accessed" => Sort::Accessed,
+                        "expires" => Sort::Expires,
+                        "size" => Sort::Size,
+                        "executions" => Sort::Executions,
+                        _ => return invalid(format!("cannot sort by {}", value)),
+                    }
+                }
+
+                "order" => {
+                    parsed.descending = match value.as_str() {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => return invalid("order must be asc or desc".to_string()),
+                    }
+                }
+
+                "offset" => parsed.offset = number(&name, &value)?,
+
+                "limit" => match number(&name, &value)? {
+                    limit if limit > 0 && limit <= MAX_LIMIT => parsed.limit = limit,
+                    _ => return invalid(format!("limit must be from 1 to {}", MAX_LIMIT)),
+                },
+
+                _ => return invalid(format!("unknown parameter {}", name)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Filters, sorts and paginates summaries of scripts. Scripts that sort
+/// equally are listed by id, so that pages are consistent.
+pub fn page(summaries: Vec<Summary>, query: &Query) -> Page {
+    let mut summaries = summaries
+        .into_iter()
+        .filter(|s| {
+            query
+                .label
+                .as_ref()
+                .map(|label| s.labels.contains(label))
+                .unwrap_or(true)
+        })
+        .filter(|s| {
+            query
+                .name
+                .as_ref()
+                .map(|prefix| s.names.iter().any(|n| n.starts_with(prefix.as_str())))
+                .unwrap_or(true)
+        })
+        .collect::<Vec<_>>();
+
+    summaries.sort_by(|a, b| {
+        let ordering = match query.sort {
+            Sort::Id => Ordering::Equal,
+            Sort::Created => a.created.cmp(&b.created),
+            Sort::Accessed => a.accessed.cmp(&b.accessed),
+            Sort::Expires => expiry(a).cmp(&expiry(b)),
+            Sort::Size => a.size.cmp(&b.size),
+            Sort::Executions => a.executions.cmp(&b.executions),
+        };
+
+        let ordering = ordering.then_with(|| a.id.cmp(&b.id));
+
+        if query.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    Page {
+        total: summaries.len(),
+        offset: query.offset,
+        limit: query.limit,
+        scripts: summaries
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .collect(),
+    }
+}
+
+/// Scripts that never expire sort after those that do.
+fn expiry(summary: &Summary) -> u64 {
+    summary.expires.unwrap_or(u64::max_value())
+}
+
+fn number(name: &str, value: &str) -> io::Result<usize> {
+    value
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {}", name)))
+}
+
+/// Decodes a component of a query string, where '+' is a space and bytes
+/// may be percent-encoded.
+fn decode(component: &str) -> io::Result<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+
+            b'%' => {
+                let byte = component
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+                match byte {
+                    Some(byte) => decoded.push(byte),
+                    None => return invalid("invalid percent-encoding".to_string()),
+                }
+
+                i += 2;
+            }
+
+            byte => decoded.push(byte),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8(decoded).or_else(|_| invalid("query is not valid UTF-8".to_string()))
+}
+
+fn invalid<T>(message: String) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(id: &str, names: &[&str], labels: &[&str], created: u64) -> Summary {
+        Summary {
+            id: id.to_string(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            size: id.len(),
+            created,
+            accessed: created,
+            expires: if names.is_empty() {
+                Some(created + 1000)
+            } else {
+                None
+            },
+            executions: 0,
+        }
+    }
+
+    fn ids(page: &Page) -> Vec<&str> {
+        page.scripts.iter().map(|s| s.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_listing_parse_query() {
+        assert_eq!(
+            Query::parse(None).unwrap(),
+            Query {
+                label: None,
+                name: None,
+                sort: Sort::Created,
+                descending: false,
+                offset: 0,
+                limit: DEFAULT_LIMIT,
+            }
+        );
+
+        assert_eq!(
+            Query::parse(Some(
+                "label=team%3Abilling&name=a+b&sort=size&order=desc&offset=5&limit=2&"
+            ))
+            .unwrap(),
+            Query {
+                label: Some("team:billing".to_string()),
+                name: Some("a b".to_string()),
+                sort: Sort::Size,
+                descending: true,
+                offset: 5,
+                limit: 2,
+            }
+        );
+
+        assert!(Query::parse(Some("sort=color")).is_err());
+        assert!(Query::parse(Some("order=up")).is_err());
+        assert!(Query::parse(Some("limit=0")).is_err());
+        assert!(Query::parse(Some("limit=1001")).is_err());
+        assert!(Query::parse(Some("offset=-1")).is_err());
+        assert!(Query::parse(Some("label=%zz")).is_err());
+        assert!(Query::parse(Some("label=%e2")).is_err());
+        assert!(Query::parse(Some("colour=red")).is_err());
+    }
+
+    #[test]
+    fn test_listing_page() {
+        let summaries = || {
+            vec![
+                summary("c", &[], &["billing"], 3),
+                summary("a", &["invoice-total@1"], &["billing"], 2),
+                summary("b", &["invoice-tax@2", "tax@1"], &[], 2),
+                summary("d", &[], &[], 1),
+            ]
+        };
+
+        let query = Query::parse(None).unwrap();
+        assert_eq!(ids(&page(summaries(), &query)), vec!["d", "a", "b", "c"]);
+
+        let query = Query::parse(Some("order=desc")).unwrap();
+        assert_eq!(ids(&page(summaries(), &query)), vec!["c", "b", "a", "d"]);
+
+        let query = Query::parse(Some("sort=expires")).unwrap();
+        assert_eq!(ids(&page(summaries(), &query)), vec!["d", "c", "a", "b"]);
+
+        let query = Query::parse(Some("label=billing")).unwrap();
+        assert_eq!(ids(&page(summaries(), &query)), vec!["a", "c"]);
+
+        let query = Query::parse(Some("name=invoice-")).unwrap();
+        assert_eq!(ids(&page(summaries(), &query)), vec!["a", "b"]);
+
+        let query = Query::parse(Some("sort=id&offset=1&limit=2")).unwrap();
+        let page = page(summaries(), &query);
+        assert_eq!(ids(&page), vec!["b", "c"]);
+        assert_eq!(page.total, 4);
+    }
+}
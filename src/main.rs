@@ -1,9 +1,12 @@
 extern crate bytes;
 extern crate futures;
 extern crate hyper;
+extern crate jsaas;
+extern crate libc;
 extern crate native_tls;
 extern crate num_cpus;
 extern crate serde_json;
+extern crate sha2;
 extern crate tokio;
 extern crate tokio_signal;
 extern crate tokio_threadpool;
@@ -16,14 +19,33 @@ extern crate serde_derive;
 #[cfg(target_os = "linux")]
 extern crate openssl;
 
+pub(crate) mod body;
+pub(crate) mod cbor;
+pub(crate) mod codec;
+pub(crate) mod conditional;
 pub(crate) mod duktape;
-pub(crate) mod script_registry;
+pub(crate) mod execution;
+pub(crate) mod jobs;
+pub(crate) mod limits;
+pub(crate) mod lint;
+pub(crate) mod metrics;
+pub(crate) mod msgpack;
+pub(crate) mod multipart;
+pub(crate) mod namespace;
+pub(crate) mod ndjson;
+pub(crate) mod negotiation;
+pub(crate) mod pipeline;
+pub(crate) mod result_cache;
 pub(crate) mod settings;
 pub(crate) mod shims;
 
+use jsaas::{bundle, events, listing, replication, script_registry, self_test, store};
+
 use bytes::*;
+use futures::future::Either;
 use futures::lazy;
 use futures::sync::{mpsc, oneshot};
+use hyper::header::HeaderValue;
 use hyper::http::request::Parts;
 use hyper::rt::{Future, Stream};
 use hyper::server::conn::Http;
@@ -31,12 +53,15 @@ use hyper::service::service_fn;
 use hyper::{Body, Method, Request, Response, StatusCode};
 use native_tls::TlsAcceptor;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::Read;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::thread_local;
-use std::time::Duration;
-use std::{fs, io, net, path, process};
+use std::{cmp, fs, io, net, path, process};
 use tokio::net::TcpListener;
-use tokio_threadpool::Builder;
+use tokio::timer::Interval;
+use tokio_threadpool::{Builder, ThreadPool};
 use uuid::Uuid;
 
 #[derive(Serialize)]
@@ -44,14 +69,68 @@ struct ResponseCreated {
     id: String,
 }
 
+#[derive(Serialize)]
+struct ResponseVersionCreated {
+    id: String,
+    name: String,
+    version: usize,
+}
+
+/// The version that an alias of a named script is moved to.
+#[derive(Deserialize)]
+struct AliasDefinition {
+    version: usize,
+}
+
 /// Represents a request with its header and body information,
 /// as well as a oneshot channel to provide a response.
+///
+/// Requests that are processed as their body streams in carry
+/// the unread body in `req_body_stream`, and an empty `req_body`.
 struct RequestWithSender {
     req_parts: Parts,
     req_body: Bytes,
+    req_body_stream: Option<Body>,
     sender: oneshot::Sender<Response<Body>>,
 }
 
+/// Represents the messages that are processed by `request_handler`. Work
+/// that completes on the thread pool reports back via this channel, so that
+/// all state remains owned by the request handler.
+enum Message {
+    Request(RequestWithSender),
+    JobStarted(String, Uuid),
+    JobCompleted(String, Uuid, io::Result<Option<String>>),
+    ResultComputed(String, String, String),
+    ScriptTested(
+        String,
+        script_registry::Script,
+        Definition,
+        oneshot::Sender<Option<Response<Body>>>,
+    ),
+    Sweep,
+    Resync,
+    Replicated(String, Vec<replication::Change>),
+}
+
+/// Describes how a script that's being defined is stored.
+enum Definition {
+    /// A new script with its own id.
+    New,
+    /// The next version of a named script.
+    Version(String),
+    /// A replacement for an existing script, provided that its entity tag
+    /// matches the value of the request's `If-Match` header, if any.
+    Replacement(script_registry::ScriptId, Option<String>),
+}
+
+/// The state that is owned by `request_handler`. Messages about scripts
+/// and jobs name the namespace that they belong to.
+struct State {
+    namespaces: HashMap<String, namespace::Namespace>,
+    pool: ThreadPool,
+}
+
 /// Evaluates the provided JavaScript code with the
 /// provided arguments, and returns its value after
 /// encoding it via JSON. A thread-local Duktape
@@ -69,7 +148,315 @@ struct RequestWithSender {
 /// Example:
 ///
 ///   "[1, 2, \"hello world\"]"
-fn json_eval(code: &str, args: &str, limit: Duration) -> io::Result<String> {
+///
+/// `limits` bounds the time and memory that the execution may use, and
+/// execution is also stopped once `cancelled` is set, if provided.
+///
+/// Yields `None` if the function returns `undefined`, which has no JSON
+/// representation.
+fn json_eval(
+    code: &str,
+    args: &str,
+    limits: duktape::Limits,
+    cancelled: Option<Arc<AtomicBool>>,
+) -> io::Result<Option<String>> {
+    with_context(|c| c.evaluate_with(code, args, limits, cancelled))
+}
+
+/// Like `json_eval`, but `args` may be in a binary encoding, and the result is
+/// encoded, or returned as it is, as specified by `format`.
+fn output_eval(
+    code: &str,
+    encoding: codec::Encoding,
+    args: &[u8],
+    limits: duktape::Limits,
+    format: duktape::OutputFormat,
+) -> io::Result<duktape::Output> {
+    let args = match encoding {
+        codec::Encoding::Json => duktape::Args::Json(std::str::from_utf8(args).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot extract arguments from request body",
+            )
+        })?),
+
+        codec::Encoding::Cbor => duktape::Args::Cbor(args),
+
+        codec::Encoding::MsgPack => duktape::Args::MsgPack(args),
+    };
+
+    with_context(|c| c.evaluate_as(code, args, limits, None, format))
+}
+
+/// Extracts the arguments from a request body, in the encoding that its
+/// `Content-Type` specifies. JSON-encoded arguments must be valid UTF-8.
+fn request_args(headers: &hyper::HeaderMap, body: Bytes) -> Option<(codec::Encoding, Vec<u8>)> {
+    let encoding = negotiation::args_encoding(header_value(headers, hyper::header::CONTENT_TYPE));
+    let args = body.to_vec();
+
+    if encoding == codec::Encoding::Json && std::str::from_utf8(&args).is_err() {
+        None
+    } else {
+        Some((encoding, args))
+    }
+}
+
+/// Builds the response for the result of executing a script, represented
+/// as negotiated with the client. If the client doesn't accept the result's
+/// representation, the response has status 406, and if the script returned
+/// `undefined`, the response has status 204.
+fn output_response(
+    builder: &mut hyper::http::response::Builder,
+    negotiation: &negotiation::Negotiation,
+    result: io::Result<duktape::Output>,
+) -> Option<Response<Body>> {
+    let response = match result {
+        Ok(duktape::Output::Undefined) => builder.status(204).body(Body::empty()),
+
+        Ok(output) => match negotiation.respond(output) {
+            Some((content_type, body)) => builder
+                .header("Content-Type", content_type.as_str())
+                .body(Body::from(body)),
+
+            None => builder
+                .status(406)
+                .body(Body::from("cannot represent result as an acceptable type")),
+        },
+
+        Err(e) => builder.status(400).body(Body::from(e.to_string())),
+    };
+
+    response.ok()
+}
+
+/// Builds the response for a script that has been defined, or for a
+/// content-addressed script that was already defined.
+fn script_created(id: script_registry::ScriptId, created: bool) -> Option<Response<Body>> {
+    let response_body =
+        serde_json::to_string(&ResponseCreated { id: id.to_string() }).unwrap_or_default();
+
+    let response = Response::builder()
+        .status(if created { 201 } else { 200 })
+        .header("Content-Type", "application/json")
+        .header("Location", format!("/scripts/{}", id))
+        .body(Body::from(response_body));
+
+    response.ok()
+}
+
+/// Builds the response for a script that cannot be stored because the
+/// registry is full, because its store failed to persist it, or because a
+/// content-addressed script is already defined with other options.
+fn not_stored(e: &io::Error) -> Option<Response<Body>> {
+    let status = if store::StoreError::is(e) {
+        500
+    } else if e.kind() == io::ErrorKind::AlreadyExists {
+        409
+    } else {
+        507
+    };
+
+    let response = Response::builder()
+        .status(status)
+        .body(Body::from(e.to_string()));
+
+    response.ok()
+}
+
+/// Builds the response for a request whose body exceeds its limit.
+fn payload_too_large(limit: Option<usize>) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!(
+        "request body exceeds {} bytes",
+        limit.unwrap_or_default()
+    )));
+
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+
+    response
+}
+
+/// Stores a script as its definition describes, and builds the response
+/// for it.
+fn store_script(
+    registry: &mut script_registry::ScriptRegistry,
+    script: script_registry::Script,
+    definition: Definition,
+) -> Option<Response<Body>> {
+    let name = match definition {
+        Definition::New => {
+            let existing = script.content_addressed
+                && registry.contains(&script_registry::ScriptId::for_code(&script.code));
+
+            return match registry.store(script) {
+                Ok(id) => script_created(id, !existing),
+                Err(e) => not_stored(&e),
+            };
+        }
+
+        Definition::Version(name) => name,
+
+        Definition::Replacement(id, if_match) => {
+            // The precondition is checked now, as the script may have been
+            // changed while its replacement was being checked
+
+            let current = registry.get(&id).map(|s| conditional::etag(&s));
+
+            let response = if current.is_none() {
+                Response::builder()
+                    .status(404)
+                    .body(Body::from("cannot find script"))
+            } else if !conditional::if_match(
+                if_match.as_ref().map(String::as_str),
+                current.as_ref().map(String::as_str),
+            ) {
+                Response::builder()
+                    .status(412)
+                    .body(Body::from("script has been changed"))
+            } else {
+                let etag = conditional::etag(&script);
+
+                match registry.replace(&id, script) {
+                    Ok(_) => Response::builder()
+                        .status(204)
+                        .header("ETag", etag)
+                        .body(Body::empty()),
+
+                    Err(e) => return not_stored(&e),
+                }
+            };
+
+            return response.ok();
+        }
+    };
+
+    let (version, id) = match registry.store_version(&name, script) {
+        Ok(stored) => stored,
+        Err(e) => return not_stored(&e),
+    };
+
+    let response_body = serde_json::to_string(&ResponseVersionCreated {
+        id: id.to_string(),
+        name: name.clone(),
+        version,
+    })
+    .unwrap_or_default();
+
+    let response = Response::builder()
+        .status(201)
+        .header("Content-Type", "application/json")
+        .header("Location", format!("/scripts/by-name/{}@{}", name, version))
+        .body(Body::from(response_body));
+
+    response.ok()
+}
+
+/// Defines a script and replies with where it can be found. Scripts with
+/// tests are only stored once they pass, and replacements once they compile,
+/// which is determined on the pool.
+#[allow(clippy::too_many_arguments)]
+fn define_script<R>(
+    script: script_registry::Script,
+    definition: Definition,
+    limits: io::Result<duktape::Limits>,
+    registry: &mut script_registry::ScriptRegistry,
+    pool: &ThreadPool,
+    tx: &mpsc::UnboundedSender<Message>,
+    namespace: &str,
+    reply: R,
+) where
+    R: FnOnce(Option<Response<Body>>) + Send + 'static,
+{
+    let compile = match definition {
+        Definition::Replacement(..) => true,
+        _ => false,
+    };
+
+    if script.tests.is_empty() && !compile {
+        reply(store_script(registry, script, definition));
+
+        return;
+    }
+
+    let limits = match limits {
+        Ok(limits) => limits,
+
+        Err(e) => {
+            let response = Response::builder()
+                .status(400)
+                .body(Body::from(e.to_string()));
+
+            reply(response.ok());
+
+            return;
+        }
+    };
+
+    let tx = tx.clone();
+    let namespace = namespace.to_string();
+
+    pool.spawn(lazy(move || {
+        if compile {
+            if let Err(e) = with_context(|c| c.compile(&script.code, limits)) {
+                let response = Response::builder()
+                    .status(400)
+                    .body(Body::from(e.to_string()));
+
+                reply(response.ok());
+
+                return Either::B(futures::finished(()));
+            }
+        }
+
+        let report = self_test::run(&script.tests, |args| {
+            json_eval(&script.code, args, limits, None)
+        });
+
+        if report.passed {
+            let (stored_sender, stored) = oneshot::channel();
+
+            let _ = tx.unbounded_send(Message::ScriptTested(
+                namespace,
+                script,
+                definition,
+                stored_sender,
+            ));
+
+            Either::A(stored.then(move |response| {
+                reply(response.ok().and_then(|r| r));
+
+                Ok(())
+            }))
+        } else {
+            reply(test_report_response(422, &report));
+
+            Either::B(futures::finished(()))
+        }
+    }));
+}
+
+/// Builds the response for the outcome of running a script's tests.
+fn test_report_response(status: u16, report: &self_test::Report) -> Option<Response<Body>> {
+    let response = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(report).unwrap_or_default(),
+        ));
+
+    response.ok()
+}
+
+/// Returns the value of a request's header, if it has one.
+fn header_value(headers: &hyper::HeaderMap, name: hyper::header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Invokes the provided function with this thread's Duktape context,
+/// creating the context if necessary.
+fn with_context<T, F>(f: F) -> io::Result<T>
+where
+    F: FnOnce(&mut duktape::Context) -> io::Result<T>,
+{
     thread_local! {
         static CONTEXT: RefCell<io::Result<duktape::Context>> = {
             RefCell::new(duktape::Context::new())
@@ -89,11 +476,79 @@ fn json_eval(code: &str, args: &str, limit: Duration) -> io::Result<String> {
         match *ctx.borrow_mut() {
             Err(ref e) => Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
 
-            Ok(ref mut c) => c.evaluate(code, args, limit),
+            Ok(ref mut c) => f(c),
         }
     })
 }
 
+/// Evaluates the provided JavaScript code once for every line of a
+/// newline-delimited JSON body, where each line is a JSON-encoded array
+/// of arguments. One result line is streamed back per input line, in
+/// the same order.
+///
+/// Lines are evaluated one at a time on the thread pool, and the body is
+/// only read as fast as results are consumed by the client. Thus, memory
+/// use is bounded by `max_line_length` regardless of the body's size. A body
+/// that exceeds `max_body_length` ends with an error line, and lines are
+/// limited to it too.
+fn ndjson_eval(
+    script: String,
+    body: Body,
+    executor: tokio_threadpool::Sender,
+    max_line_length: usize,
+    max_body_length: Option<usize>,
+    limits: duktape::Limits,
+) -> Body {
+    let chunks = body::Limited::new(
+        body.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())),
+        max_body_length,
+    );
+
+    let max_line_length = max_body_length.map_or(max_line_length, |max_body_length| {
+        cmp::min(max_line_length, max_body_length)
+    });
+
+    let results = ndjson::Lines::new(chunks, max_line_length)
+        .then(Ok::<_, io::Error>)
+        .and_then(move |line| match line {
+            Ok(line) => {
+                let script = script.clone();
+                let (sender, receiver) = oneshot::channel();
+
+                let spawned = executor.spawn(lazy(move || {
+                    let result = std::str::from_utf8(&line)
+                        .map_err(|_| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "cannot extract arguments from line",
+                            )
+                        })
+                        .and_then(|args| json_eval(&script, args, limits, None));
+
+                    let _ = sender.send(result);
+
+                    futures::finished(())
+                }));
+
+                Either::A(match spawned {
+                    Ok(()) => Either::A(
+                        receiver.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())),
+                    ),
+
+                    Err(e) => Either::B(futures::failed(io::Error::new(
+                        io::ErrorKind::Other,
+                        e.to_string(),
+                    ))),
+                })
+            }
+
+            Err(e) => Either::B(futures::finished(Err(e))),
+        })
+        .map(ndjson::result_line);
+
+    Body::wrap_stream(results)
+}
+
 /// Handle the request, which means parsing it to determine
 /// what to do.
 ///
@@ -103,25 +558,222 @@ fn json_eval(code: &str, args: &str, limit: Duration) -> io::Result<String> {
 /// If it's a request to define a function, we simply store it
 /// locally in a synchronous fashion and send the reply.
 fn request_handler(
-    rx: mpsc::UnboundedReceiver<RequestWithSender>,
-    js_thread_pool_size: usize,
-    registry_script_ttl: Duration,
-    script_execution_completion_time: Duration,
-) -> Box<Future<Item = (), Error = ()> + Send> {
-    let registry = script_registry::ScriptRegistry::new(registry_script_ttl);
+    rx: mpsc::UnboundedReceiver<Message>,
+    tx: mpsc::UnboundedSender<Message>,
+    settings: &settings::Settings,
+) -> io::Result<Box<Future<Item = (), Error = ()> + Send>> {
+    let ndjson_max_line_length = settings.ndjson_max_line_length;
+    let body_limits = body::BodyLimits::new(settings);
+    let ndjson_max_body_length = body_limits.batch;
+
+    // The default namespace has the server's settings, and others override
+    // some of them
+
+    let mut namespaces = HashMap::new();
+
+    namespaces.insert(
+        namespace::DEFAULT.to_string(),
+        namespace::Namespace::new(namespace::DEFAULT, settings)?,
+    );
+
+    for (name, overrides) in &settings.namespaces {
+        namespace::validate_name(name)?;
+
+        namespaces.insert(
+            name.clone(),
+            namespace::Namespace::new(name, &overrides.apply(settings))?,
+        );
+    }
+
+    let pool = Builder::new()
+        .pool_size(settings.script_execution_thread_pool_size)
+        .build();
+
+    let state = State { namespaces, pool };
+
+    let replicator = replication::Replicator::new(
+        settings.replication_peers.clone(),
+        settings.replication_secret.clone(),
+    );
+
+    // Periodically remove expired scripts, as they're otherwise only removed
+    // when scripts are stored or read. An interval of 0 disables this.
+
+    let sweep_interval = settings.script_definition_sweep_interval;
+
+    let sweeper: Box<Future<Item = (), Error = ()> + Send> = if sweep_interval.as_millis() > 0 {
+        let tx = tx.clone();
+
+        Box::new(
+            Interval::new_interval(sweep_interval)
+                .map_err(|_| ())
+                .for_each(move |_| tx.unbounded_send(Message::Sweep).map_err(|_| ())),
+        )
+    } else {
+        Box::new(futures::finished(()))
+    };
+
+    // Periodically resync with peers, starting right away, so that changes
+    // that were missed while they couldn't be reached are caught up on
+
+    let resync_interval = settings.replication_resync_interval;
+
+    let resyncer: Box<Future<Item = (), Error = ()> + Send> =
+        if replicator.is_enabled() && resync_interval.as_millis() > 0 {
+            let tx = tx.clone();
+
+            Box::new(
+                Interval::new(std::time::Instant::now(), resync_interval)
+                    .map_err(|_| ())
+                    .for_each(move |_| tx.unbounded_send(Message::Resync).map_err(|_| ())),
+            )
+        } else {
+            Box::new(futures::finished(()))
+        };
+
+    let future = rx.fold(state, move |state, message| {
+        let State {
+            mut namespaces,
+            pool,
+        } = state;
+
+        let req_with_sender = match message {
+            Message::Request(req_with_sender) => Some(req_with_sender),
+
+            Message::JobStarted(namespace, id) => {
+                if let Some(namespace) = namespaces.get_mut(&namespace) {
+                    namespace.jobs.start(&id);
+                }
+
+                None
+            }
+
+            Message::JobCompleted(namespace, id, result) => {
+                if let Some(namespace) = namespaces.get_mut(&namespace) {
+                    namespace.jobs.complete(&id, result);
+                }
+
+                None
+            }
+
+            Message::ResultComputed(namespace, key, result) => {
+                if let Some(namespace) = namespaces.get_mut(&namespace) {
+                    namespace.results.insert(key, result);
+                }
+
+                None
+            }
+
+            Message::ScriptTested(namespace, script, definition, sender) => {
+                if let Some(namespace) = namespaces.get_mut(&namespace) {
+                    let response = store_script(&mut namespace.registry, script, definition);
+                    let _ = sender.send(response);
+                }
+
+                None
+            }
+
+            Message::Sweep => {
+                for namespace in namespaces.values_mut() {
+                    namespace.registry.remove_expired();
+                    namespace.jobs.remove_expired();
+                }
+
+                None
+            }
+
+            Message::Resync => {
+                for (name, namespace) in &namespaces {
+                    let tx = tx.clone();
+                    let name = name.clone();
+
+                    replicator.sync(
+                        &namespace::prefix(&name),
+                        &namespace.registry.digest(),
+                        move |changes| {
+                            let _ = tx.unbounded_send(Message::Replicated(name.clone(), changes));
+                        },
+                    );
+                }
+
+                None
+            }
+
+            Message::Replicated(namespace, changes) => {
+                if let Some(namespace) = namespaces.get_mut(&namespace) {
+                    namespace.registry.apply(changes);
+                }
+
+                None
+            }
+        };
+
+        let req_with_sender = match req_with_sender {
+            Some(req_with_sender) => req_with_sender,
+
+            None => {
+                send_changes(&replicator, &mut namespaces);
+                publish_events(&mut namespaces);
 
-    let pool = Builder::new().pool_size(js_thread_pool_size).build();
+                return futures::finished(State { namespaces, pool });
+            }
+        };
 
-    let future = rx.fold((registry, pool), move |state, req_with_sender| {
-        let (mut registry, pool) = state;
         let RequestWithSender {
             req_parts,
             req_body,
+            req_body_stream,
             sender,
         } = req_with_sender;
 
-        let reply = |response: Option<Response<Body>>| match response {
-            Some(r) => {
+        // A request is confined to the namespace that its path names, and is
+        // routed by the rest of its path
+
+        let split = namespace::split(req_parts.uri.path()).and_then(|(name, path)| {
+            namespaces
+                .remove(name)
+                .map(|namespace| (name.to_string(), path, namespace))
+        });
+
+        let (namespace_name, path, namespace) = match split {
+            Some(split) => split,
+
+            None => {
+                let mut response = Response::new(Body::from("cannot find namespace"));
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                let _ = sender.send(response);
+
+                return futures::finished(State { namespaces, pool });
+            }
+        };
+
+        let namespace::Namespace {
+            mut registry,
+            mut pipelines,
+            mut jobs,
+            mut events,
+            webhook,
+            mut results,
+            limits: limit_settings,
+            job_limits,
+            requests,
+        } = namespace;
+
+        let requests = requests + 1;
+        let prefix = namespace::prefix(&namespace_name);
+
+        let reply = move |response: Option<Response<Body>>| match response {
+            Some(mut r) => {
+                // Locations are within the request's namespace
+
+                let location = header_value(r.headers(), hyper::header::LOCATION)
+                    .filter(|_| !prefix.is_empty())
+                    .and_then(|l| HeaderValue::from_str(&format!("{}{}", prefix, l)).ok());
+
+                if let Some(location) = location {
+                    r.headers_mut().insert(hyper::header::LOCATION, location);
+                }
+
                 let _ = sender.send(r);
             }
 
@@ -132,16 +784,315 @@ fn request_handler(
             }
         };
 
-        match (req_parts.method, req_parts.uri.path()) {
-            (Method::POST, "/execute") => match String::from_utf8(req_body.into_buf().collect()) {
-                Ok(script) => {
-                    pool.spawn(lazy(move || {
-                        let result = json_eval(&script, "[]", script_execution_completion_time);
+        let limits = limit_settings.for_request(&req_parts.headers);
+
+        match (req_parts.method, path) {
+            (Method::POST, "/execute") => {
+                let execution = execution::Execution::parse(
+                    header_value(&req_parts.headers, hyper::header::CONTENT_TYPE),
+                    &req_body,
+                );
+
+                // The body as a whole is limited by the sum of the limits of
+                // the code and the arguments, which are limited separately
+
+                let exceeded = execution.as_ref().ok().and_then(|execution| {
+                    body_limits
+                        .exceeded(body::Kind::Script, execution.code.len())
+                        .or_else(|| {
+                            body_limits.exceeded(body::Kind::Arguments, execution.args.len())
+                        })
+                });
+
+                match (execution, limits) {
+                    _ if exceeded.is_some() => {
+                        reply(Some(payload_too_large(exceeded)));
+                    }
+
+                    (Ok(execution), Ok(limits)) => {
+                        let negotiation = negotiation::Negotiation::new(
+                            header_value(&req_parts.headers, hyper::header::ACCEPT),
+                            None,
+                        );
+
+                        pool.spawn(lazy(move || {
+                            let result = output_eval(
+                                &execution.code,
+                                execution.encoding,
+                                &execution.args,
+                                limits,
+                                negotiation.format(),
+                            );
+
+                            let mut builder = Response::builder();
+
+                            limits::echo(&mut builder, &limits);
+
+                            reply(output_response(&mut builder, &negotiation, result));
+
+                            futures::finished(())
+                        }));
+                    }
+
+                    (Err(e), _) | (_, Err(e)) => {
+                        let response = Response::builder()
+                            .status(400)
+                            .body(Body::from(e.to_string()));
+
+                        reply(response.ok());
+                    }
+                }
+            }
+
+            (Method::POST, "/scripts/lint") => {
+                match (script_registry::Script::parse(&req_body), limits) {
+                    (Ok(script), Ok(limits)) => {
+                        // Scripts are compiled on the pool, as they may be large
+
+                        pool.spawn(lazy(move || {
+                            let report = with_context(|c| {
+                                let compiled = c.compile(&script.code, limits);
+                                let globals = c.global_names()?;
+
+                                Ok(lint::lint(&script.code, compiled, &globals))
+                            });
+
+                            let response = match report {
+                                Ok(report) => Response::builder()
+                                    .header("Content-Type", "application/json")
+                                    .body(Body::from(
+                                        serde_json::to_string(&report).unwrap_or_default(),
+                                    )),
+
+                                Err(e) => Response::builder()
+                                    .status(500)
+                                    .body(Body::from(e.to_string())),
+                            };
+
+                            reply(response.ok());
+
+                            futures::finished(())
+                        }));
+                    }
+
+                    (Err(e), _) | (_, Err(e)) => {
+                        let response = Response::builder()
+                            .status(400)
+                            .body(Body::from(e.to_string()));
+
+                        reply(response.ok());
+                    }
+                }
+            }
+
+            (Method::POST, path)
+                if path.starts_with("/scripts/") && path.ends_with("/jobs") && path.len() > 14 =>
+            {
+                let maybe_script = registry
+                    .lookup(&path[9..path.len() - 5])
+                    .map(|(id, s)| (id, s.code));
+
+                match (
+                    maybe_script,
+                    String::from_utf8(req_body.into_buf().collect()),
+                ) {
+                    (Some((script_id, script)), Ok(args)) => {
+                        match jobs.create(script_id.clone()) {
+                            Ok((id, cancelled)) => {
+                                registry.executed(&script_id);
+
+                                let tx = tx.clone();
+                                let namespace = namespace_name.clone();
+
+                                pool.spawn(lazy(move || {
+                                    if !cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+                                        let _ = tx.unbounded_send(Message::JobStarted(
+                                            namespace.clone(),
+                                            id,
+                                        ));
+
+                                        let result =
+                                            json_eval(&script, &args, job_limits, Some(cancelled));
+
+                                        let _ = tx.unbounded_send(Message::JobCompleted(
+                                            namespace, id, result,
+                                        ));
+                                    }
+
+                                    futures::finished(())
+                                }));
+
+                                let response_body =
+                                    serde_json::to_string(&ResponseCreated { id: id.to_string() })
+                                        .unwrap_or_default();
+
+                                let response = Response::builder()
+                                    .status(202)
+                                    .header("Content-Type", "application/json")
+                                    .header("Location", format!("/jobs/{}", id))
+                                    .body(Body::from(response_body));
+
+                                reply(response.ok());
+                            }
+
+                            // Clients should retry once some of the jobs have finished
+                            Err(e) => {
+                                let response = Response::builder()
+                                    .status(503)
+                                    .body(Body::from(e.to_string()));
+
+                                reply(response.ok());
+                            }
+                        }
+                    }
+
+                    (None, _) => {
+                        let response = Response::builder()
+                            .status(404)
+                            .body(Body::from("cannot find script"));
+
+                        reply(response.ok());
+                    }
+
+                    (_, Err(_)) => {
+                        let response = Response::builder()
+                            .status(400)
+                            .body(Body::from("cannot extract arguments from request body"));
+
+                        reply(response.ok());
+                    }
+                }
+            }
+
+            (Method::POST, path)
+                if path.starts_with("/scripts/") && path.ends_with("/test") && path.len() > 14 =>
+            {
+                let maybe_script = registry.lookup(&path[9..path.len() - 5]).map(|(_, s)| s);
+
+                match (maybe_script, limits) {
+                    (Some(script), Ok(limits)) => {
+                        pool.spawn(lazy(move || {
+                            let report = self_test::run(&script.tests, |args| {
+                                json_eval(&script.code, args, limits, None)
+                            });
+
+                            reply(test_report_response(200, &report));
+
+                            futures::finished(())
+                        }));
+                    }
+
+                    (None, _) => {
+                        let response = Response::builder()
+                            .status(404)
+                            .body(Body::from("cannot find script"));
+
+                        reply(response.ok());
+                    }
+
+                    (_, Err(e)) => {
+                        let response = Response::builder()
+                            .status(400)
+                            .body(Body::from(e.to_string()));
+
+                        reply(response.ok());
+                    }
+                }
+            }
+
+            (ref method, path)
+                if path.starts_with("/jobs/")
+                    && (method == Method::GET || method == Method::DELETE) =>
+            {
+                let id = Uuid::parse_str(&path[6..]).ok();
+
+                let response = match (method, id) {
+                    (&Method::GET, Some(id)) => match jobs.get(&id) {
+                        Some(job) => Response::builder()
+                            .header("Content-Type", "application/json")
+                            .body(Body::from(
+                                serde_json::to_string(&job.view(&id)).unwrap_or_default(),
+                            )),
+
+                        None => Response::builder()
+                            .status(404)
+                            .body(Body::from("cannot find job")),
+                    },
+
+                    (&Method::DELETE, Some(id)) if jobs.cancel(&id) => {
+                        Response::builder().status(204).body(Body::empty())
+                    }
+
+                    _ => Response::builder()
+                        .status(404)
+                        .body(Body::from("cannot find job")),
+                };
+
+                reply(response.ok());
+            }
+
+            (ref method, path)
+                if path.starts_with("/scripts/by-name/")
+                    && (method == Method::PUT
+                        || ((method == Method::GET || method == Method::DELETE)
+                            && !path.contains('@'))) =>
+            {
+                let segments = path[17..].split('/').collect::<Vec<_>>();
+
+                match (method, segments.as_slice()) {
+                    (&Method::PUT, [name]) => {
+                        let script = script_registry::validate_name(name)
+                            .and_then(|_| script_registry::Script::parse(&req_body));
+
+                        match script {
+                            Ok(script) => define_script(
+                                script,
+                                Definition::Version(name.to_string()),
+                                limits,
+                                &mut registry,
+                                &pool,
+                                &tx,
+                                &namespace_name,
+                                reply,
+                            ),
+
+                            Err(e) => {
+                                let response = Response::builder()
+                                    .status(400)
+                                    .body(Body::from(e.to_string()));
+
+                                reply(response.ok());
+                            }
+                        }
+                    }
+
+                    (&Method::PUT, [name, "aliases", alias]) => {
+                        let result = script_registry::validate_alias(alias)
+                            .and_then(|_| {
+                                serde_json::from_slice::<AliasDefinition>(&req_body)
+                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                            })
+                            .and_then(|definition| {
+                                registry.set_alias(name, alias, definition.version)
+                            });
 
                         let response = match result {
-                            Ok(json_body) => Response::builder()
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(json_body)),
+                            Ok(true) => Response::builder()
+                                .status(201)
+                                .header("Location", format!("/scripts/by-name/{}@{}", name, alias))
+                                .body(Body::empty()),
+
+                            Ok(false) => Response::builder().status(204).body(Body::empty()),
+
+                            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                                Response::builder()
+                                    .status(404)
+                                    .body(Body::from(e.to_string()))
+                            }
+
+                            Err(ref e) if store::StoreError::is(e) => Response::builder()
+                                .status(500)
+                                .body(Body::from(e.to_string())),
 
                             Err(e) => Response::builder()
                                 .status(400)
@@ -149,83 +1100,286 @@ fn request_handler(
                         };
 
                         reply(response.ok());
+                    }
 
-                        futures::finished(())
-                    }));
-                }
+                    (&Method::GET, [name]) => {
+                        let response = match registry.named(name) {
+                            Some(named) => Response::builder()
+                                .header("Content-Type", "application/json")
+                                .body(Body::from(serde_json::to_string(named).unwrap_or_default())),
 
-                Err(_) => {
-                    let response = Response::builder()
-                        .status(400)
-                        .body(Body::from("cannot extract script from request body"));
+                            None => Response::builder()
+                                .status(404)
+                                .body(Body::from("cannot find script")),
+                        };
+
+                        reply(response.ok());
+                    }
+
+                    (&Method::DELETE, [name]) => {
+                        let response = match registry.remove_name(name) {
+                            Ok(true) => Response::builder().status(204).body(Body::empty()),
+
+                            Ok(false) => Response::builder()
+                                .status(404)
+                                .body(Body::from("cannot find script")),
+
+                            Err(e) => Response::builder()
+                                .status(500)
+                                .body(Body::from(e.to_string())),
+                        };
+
+                        reply(response.ok());
+                    }
+
+                    _ => {
+                        let response = Response::builder()
+                            .status(404)
+                            .body(Body::from("cannot find route"));
 
-                    reply(response.ok());
+                        reply(response.ok());
+                    }
                 }
-            },
+            }
 
             (ref method, path)
                 if path.starts_with("/scripts/")
                     && path.len() > 9
                     && (method == Method::POST
+                        || method == Method::PUT
                         || method == Method::DELETE
                         || method == Method::GET) =>
             {
-                let maybe_script = Uuid::parse_str(&path[9..])
-                    .ok()
-                    .and_then(|id| registry.get(&id).map(|s| (id, s)));
+                let maybe_script = registry.lookup(&path[9..]);
 
                 match maybe_script {
                     Some((id, script)) => {
                         match *method {
-                            Method::POST => {
-                                match String::from_utf8(req_body.into_buf().collect()) {
-                                    Ok(args) => {
-                                        pool.spawn(lazy(move || {
-                                            let result = json_eval(
-                                                &script,
-                                                &args,
-                                                script_execution_completion_time,
-                                            );
+                            Method::POST => match limits {
+                                Err(e) => {
+                                    let response = Response::builder()
+                                        .status(400)
+                                        .body(Body::from(e.to_string()));
+
+                                    reply(response.ok());
+                                }
 
-                                            let response = match result {
-                                                Ok(json_body) => Response::builder()
-                                                    .header("Content-Type", "application/json")
-                                                    .body(Body::from(json_body)),
+                                Ok(limits) if req_body_stream.is_some() => {
+                                    registry.executed(&id);
 
-                                                Err(e) => Response::builder()
-                                                    .status(400)
-                                                    .body(Body::from(e.to_string())),
+                                    let body = ndjson_eval(
+                                        script.code,
+                                        req_body_stream.unwrap_or_default(),
+                                        pool.sender().clone(),
+                                        ndjson_max_line_length,
+                                        ndjson_max_body_length,
+                                        limits,
+                                    );
+
+                                    let mut builder = Response::builder();
+
+                                    limits::echo(&mut builder, &limits);
+
+                                    let response = builder
+                                        .header("Content-Type", ndjson::CONTENT_TYPE_NDJSON)
+                                        .body(body);
+
+                                    reply(response.ok());
+                                }
+
+                                Ok(limits) => {
+                                    registry.executed(&id);
+
+                                    match request_args(&req_parts.headers, req_body) {
+                                        Some((encoding, args)) => {
+                                            let negotiation = negotiation::Negotiation::new(
+                                                header_value(
+                                                    &req_parts.headers,
+                                                    hyper::header::ACCEPT,
+                                                ),
+                                                script.output.as_ref().map(String::as_str),
+                                            );
+
+                                            let format = negotiation.format();
+
+                                            // Results of deterministic scripts are cached, keyed
+                                            // by the script's content, the limits that it's
+                                            // executed within, and its canonical arguments.
+                                            // Only results for JSON arguments and in JSON are cached.
+
+                                            let cache_key = if script.deterministic
+                                                && encoding == codec::Encoding::Json
+                                                && format == duktape::OutputFormat::default()
+                                            {
+                                                std::str::from_utf8(&args).ok().and_then(|a| {
+                                                    result_cache::key(&script.hash, &limits, a)
+                                                })
+                                            } else {
+                                                None
                                             };
 
+                                            let cached =
+                                                cache_key.as_ref().and_then(|k| results.get(k));
+
+                                            match cached {
+                                                Some(json_body) => {
+                                                    let mut builder = Response::builder();
+
+                                                    limits::echo(&mut builder, &limits);
+
+                                                    builder.header("X-Jsaas-Cache", "hit");
+
+                                                    reply(output_response(
+                                                        &mut builder,
+                                                        &negotiation,
+                                                        Ok(duktape::Output::Json(json_body)),
+                                                    ));
+                                                }
+
+                                                None => {
+                                                    let tx = tx.clone();
+                                                    let namespace = namespace_name.clone();
+
+                                                    pool.spawn(lazy(move || {
+                                                        let result = output_eval(
+                                                            &script.code,
+                                                            encoding,
+                                                            &args,
+                                                            limits,
+                                                            format,
+                                                        );
+
+                                                        let mut builder = Response::builder();
+
+                                                        limits::echo(&mut builder, &limits);
+
+                                                        if let Some(key) = cache_key {
+                                                            builder.header("X-Jsaas-Cache", "miss");
+
+                                                            if let Ok(duktape::Output::Json(
+                                                                ref json_body,
+                                                            )) = result
+                                                            {
+                                                                let _ = tx.unbounded_send(
+                                                                    Message::ResultComputed(
+                                                                        namespace,
+                                                                        key,
+                                                                        json_body.clone(),
+                                                                    ),
+                                                                );
+                                                            }
+                                                        }
+
+                                                        reply(output_response(
+                                                            &mut builder,
+                                                            &negotiation,
+                                                            result,
+                                                        ));
+
+                                                        futures::finished(())
+                                                    }));
+                                                }
+                                            }
+                                        }
+
+                                        None => {
+                                            let response =
+                                                Response::builder().status(400).body(Body::from(
+                                                    "cannot extract arguments from request body",
+                                                ));
+
                                             reply(response.ok());
+                                        }
+                                    }
+                                }
+                            },
+
+                            Method::GET => {
+                                let etag = conditional::etag(&script);
+
+                                let unchanged = conditional::if_none_match(
+                                    header_value(&req_parts.headers, hyper::header::IF_NONE_MATCH),
+                                    &etag,
+                                );
+
+                                let response = if unchanged {
+                                    Response::builder()
+                                        .status(304)
+                                        .header("ETag", etag)
+                                        .body(Body::empty())
+                                } else {
+                                    Response::builder()
+                                        .header("Content-Type", "application/json")
+                                        .header("ETag", etag)
+                                        .body(Body::from(script.code))
+                                };
 
-                                            futures::finished(())
-                                        }));
+                                reply(response.ok());
+                            }
+
+                            Method::PUT => {
+                                // Content-addressed scripts and versions of named
+                                // scripts are immutable
+
+                                let immutable = match id {
+                                    script_registry::ScriptId::Sha256(_) => true,
+                                    _ => registry.is_version(&id),
+                                };
+
+                                match script_registry::Script::parse(&req_body) {
+                                    _ if immutable => {
+                                        let response = Response::builder()
+                                            .status(409)
+                                            .body(Body::from("cannot change an immutable script"));
+
+                                        reply(response.ok());
+                                    }
+
+                                    Ok(replacement) => {
+                                        let if_match = header_value(
+                                            &req_parts.headers,
+                                            hyper::header::IF_MATCH,
+                                        )
+                                        .map(String::from);
+
+                                        define_script(
+                                            replacement,
+                                            Definition::Replacement(id, if_match),
+                                            limits,
+                                            &mut registry,
+                                            &pool,
+                                            &tx,
+                                            &namespace_name,
+                                            reply,
+                                        );
                                     }
 
-                                    Err(_) => {
-                                        let response =
-                                            Response::builder().status(400).body(Body::from(
-                                                "cannot extract arguments from request body",
-                                            ));
+                                    Err(e) => {
+                                        let response = Response::builder()
+                                            .status(400)
+                                            .body(Body::from(e.to_string()));
 
                                         reply(response.ok());
                                     }
                                 }
                             }
 
-                            Method::GET => {
+                            Method::DELETE if registry.is_version(&id) => {
                                 let response = Response::builder()
-                                    .header("Content-Type", "application/json")
-                                    .body(Body::from(script));
+                                    .status(409)
+                                    .body(Body::from("cannot remove a version of a named script"));
 
                                 reply(response.ok());
                             }
 
                             Method::DELETE => {
-                                registry.remove(&id);
+                                let response = match registry.remove(&id) {
+                                    Ok(()) => Response::builder().status(204).body(Body::empty()),
 
-                                let response = Response::builder().status(204).body(Body::empty());
+                                    Err(e) => Response::builder()
+                                        .status(500)
+                                        .body(Body::from(e.to_string())),
+                                };
 
                                 reply(response.ok());
                             }
@@ -246,34 +1400,432 @@ fn request_handler(
                 }
             }
 
-            (Method::POST, "/scripts") | (Method::POST, "/scripts/") => {
-                match String::from_utf8(req_body.into_buf().collect()) {
-                    Ok(script) => {
-                        let id = registry.store(script);
+            (Method::GET, "/scripts") | (Method::GET, "/scripts/") => {
+                let response = match listing::Query::parse(req_parts.uri.query()) {
+                    Ok(query) => Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&registry.list(&query)).unwrap_or_default(),
+                        )),
 
-                        let response_body =
-                            serde_json::to_string(&ResponseCreated { id: id.to_string() })
-                                .unwrap_or_default();
+                    Err(e) => Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                };
+
+                reply(response.ok());
+            }
 
+            (Method::POST, "/scripts") | (Method::POST, "/scripts/") => {
+                match script_registry::Script::parse(&req_body) {
+                    Ok(script) => define_script(
+                        script,
+                        Definition::New,
+                        limits,
+                        &mut registry,
+                        &pool,
+                        &tx,
+                        &namespace_name,
+                        reply,
+                    ),
+
+                    Err(e) => {
                         let response = Response::builder()
-                            .status(201)
-                            .header("Content-Type", "application/json")
-                            .header("Location", format!("/scripts/{}", id))
-                            .body(Body::from(response_body));
+                            .status(400)
+                            .body(Body::from(e.to_string()));
 
                         reply(response.ok());
                     }
+                }
+            }
 
-                    Err(_) => {
-                        let response = Response::builder()
-                            .status(400)
-                            .body(Body::from("cannot extract script from request body"));
+            (ref method, path)
+                if path.starts_with("/pipelines/")
+                    && path.len() > 11
+                    && !path[11..].contains('/')
+                    && (method == Method::PUT
+                        || method == Method::POST
+                        || method == Method::DELETE
+                        || method == Method::GET) =>
+            {
+                let name = &path[11..];
+
+                match *method {
+                    Method::PUT => {
+                        let definition = serde_json::from_slice::<pipeline::Pipeline>(&req_body)
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                            .and_then(|p| p.validate().map(|_| p))
+                            .and_then(|p| {
+                                match p.stages.iter().find(|s| !registry.contains(&s.script)) {
+                                    Some(s) => Err(io::Error::new(
+                                        io::ErrorKind::NotFound,
+                                        format!("cannot find script {}", s.script),
+                                    )),
+
+                                    None => Ok(p),
+                                }
+                            });
+
+                        let response = match definition {
+                            Ok(definition) => {
+                                if pipelines.store(name.to_string(), definition) {
+                                    Response::builder()
+                                        .status(201)
+                                        .header("Location", format!("/pipelines/{}", name))
+                                        .body(Body::empty())
+                                } else {
+                                    Response::builder().status(204).body(Body::empty())
+                                }
+                            }
+
+                            Err(e) => Response::builder()
+                                .status(400)
+                                .body(Body::from(e.to_string())),
+                        };
+
+                        reply(response.ok());
+                    }
+
+                    Method::GET => {
+                        let response = match pipelines.get(name) {
+                            Some(definition) => Response::builder()
+                                .header("Content-Type", "application/json")
+                                .body(Body::from(
+                                    serde_json::to_string(definition).unwrap_or_default(),
+                                )),
+
+                            None => Response::builder()
+                                .status(404)
+                                .body(Body::from("cannot find pipeline")),
+                        };
+
+                        reply(response.ok());
+                    }
+
+                    Method::DELETE => {
+                        let response = if pipelines.remove(name) {
+                            Response::builder().status(204).body(Body::empty())
+                        } else {
+                            Response::builder()
+                                .status(404)
+                                .body(Body::from("cannot find pipeline"))
+                        };
 
                         reply(response.ok());
                     }
+
+                    _ => {
+                        let mut missing = None;
+                        let mut stages = vec![];
+
+                        for (i, stage) in pipelines
+                            .get(name)
+                            .map(|p| p.stages.as_slice())
+                            .unwrap_or_default()
+                            .iter()
+                            .enumerate()
+                        {
+                            match registry.get(&stage.script) {
+                                Some(script) => stages.push((
+                                    stage.script.clone(),
+                                    script.code,
+                                    stage.adapter.clone(),
+                                )),
+
+                                None => {
+                                    missing = Some(format!(
+                                        "cannot find script {} for stage {}",
+                                        stage.script, i
+                                    ));
+
+                                    break;
+                                }
+                            }
+                        }
+
+                        match (
+                            missing,
+                            String::from_utf8(req_body.into_buf().collect()),
+                            limits,
+                        ) {
+                            (Some(missing), _, _) => {
+                                let response =
+                                    Response::builder().status(404).body(Body::from(missing));
+
+                                reply(response.ok());
+                            }
+
+                            (None, _, _) if stages.is_empty() => {
+                                let response = Response::builder()
+                                    .status(404)
+                                    .body(Body::from("cannot find pipeline"));
+
+                                reply(response.ok());
+                            }
+
+                            (None, Ok(args), Ok(limits)) => {
+                                for (id, _, _) in &stages {
+                                    registry.executed(id);
+                                }
+
+                                pool.spawn(lazy(move || {
+                                    let result = pipeline::run(
+                                        &stages,
+                                        args,
+                                        limits,
+                                        |code, args, limits| json_eval(code, args, limits, None),
+                                    );
+
+                                    let mut builder = Response::builder();
+
+                                    limits::echo(&mut builder, &limits);
+
+                                    let response = match result {
+                                        Ok(Some(json_body)) => builder
+                                            .header("Content-Type", "application/json")
+                                            .body(Body::from(json_body)),
+
+                                        Ok(None) => builder.status(204).body(Body::empty()),
+
+                                        Err(failure) => builder
+                                            .status(400)
+                                            .header("Content-Type", "application/json")
+                                            .body(Body::from(
+                                                serde_json::to_string(&failure).unwrap_or_default(),
+                                            )),
+                                    };
+
+                                    reply(response.ok());
+
+                                    futures::finished(())
+                                }));
+                            }
+
+                            (None, Err(_), _) => {
+                                let response = Response::builder()
+                                    .status(400)
+                                    .body(Body::from("cannot extract arguments from request body"));
+
+                                reply(response.ok());
+                            }
+
+                            (None, _, Err(e)) => {
+                                let response = Response::builder()
+                                    .status(400)
+                                    .body(Body::from(e.to_string()));
+
+                                reply(response.ok());
+                            }
+                        }
+                    }
                 }
             }
 
+            (Method::GET, "/admin/export") => {
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&registry.export()).unwrap_or_default(),
+                    ));
+
+                reply(response.ok());
+            }
+
+            (Method::POST, "/admin/import") => {
+                let imported = bundle::Mode::parse(req_parts.uri.query()).and_then(|mode| {
+                    serde_json::from_slice::<bundle::Bundle>(&req_body)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                        .and_then(|bundle| registry.import(bundle, mode))
+                });
+
+                let response = match imported {
+                    Ok(imported) => Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&imported).unwrap_or_default(),
+                        )),
+
+                    Err(ref e) if store::StoreError::is(e) => Response::builder()
+                        .status(500)
+                        .body(Body::from(e.to_string())),
+
+                    Err(ref e) if e.kind() == io::ErrorKind::Other => Response::builder()
+                        .status(507)
+                        .body(Body::from(e.to_string())),
+
+                    Err(e) => Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                };
+
+                reply(response.ok());
+            }
+
+            (Method::GET, "/metrics") => {
+                let stats = results.stats();
+                let mut metrics = metrics::Metrics::new();
+
+                metrics.counter(
+                    "jsaas_result_cache_hits_total",
+                    "Executions of deterministic scripts that were served from the cache.",
+                    stats.hits,
+                );
+                metrics.counter(
+                    "jsaas_result_cache_misses_total",
+                    "Executions of deterministic scripts that were not found in the cache.",
+                    stats.misses,
+                );
+                metrics.counter(
+                    "jsaas_result_cache_evictions_total",
+                    "Cached results that were evicted to keep the cache within its size.",
+                    stats.evictions,
+                );
+                metrics.gauge(
+                    "jsaas_result_cache_entries",
+                    "Number of results in the cache.",
+                    stats.entries,
+                );
+                metrics.gauge(
+                    "jsaas_result_cache_bytes",
+                    "Size of the results in the cache.",
+                    stats.bytes,
+                );
+
+                let stats = registry.stats();
+
+                metrics.counter(
+                    "jsaas_script_registry_expirations_total",
+                    "Scripts that were removed because they went unused for too long.",
+                    stats.expirations,
+                );
+                metrics.counter(
+                    "jsaas_script_registry_evictions_total",
+                    "Scripts that were evicted to keep the registry within its capacity.",
+                    stats.evictions,
+                );
+                metrics.gauge(
+                    "jsaas_script_registry_entries",
+                    "Number of scripts in the registry.",
+                    stats.entries,
+                );
+                metrics.gauge(
+                    "jsaas_script_registry_bytes",
+                    "Size of the code of the scripts in the registry.",
+                    stats.bytes,
+                );
+
+                let response = Response::builder()
+                    .header("Content-Type", metrics::CONTENT_TYPE_METRICS)
+                    .body(Body::from(metrics.into_string()));
+
+                reply(response.ok());
+            }
+
+            (Method::POST, replication::CHANGES_PATH) | (Method::POST, replication::SYNC_PATH)
+                if !replicator.is_enabled() =>
+            {
+                let response = Response::builder()
+                    .status(404)
+                    .body(Body::from("replication is disabled"));
+
+                reply(response.ok());
+            }
+
+            (Method::POST, replication::CHANGES_PATH) | (Method::POST, replication::SYNC_PATH)
+                if !replicator.is_authorized(&req_parts.headers) =>
+            {
+                let response = Response::builder()
+                    .status(403)
+                    .body(Body::from("replication is only open to peers"));
+
+                reply(response.ok());
+            }
+
+            (Method::POST, replication::CHANGES_PATH) => {
+                let response = match serde_json::from_slice::<Vec<replication::Change>>(&req_body) {
+                    Ok(changes) => {
+                        registry.apply(changes);
+
+                        Response::builder().status(204).body(Body::empty())
+                    }
+
+                    Err(e) => Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                };
+
+                reply(response.ok());
+            }
+
+            (Method::POST, replication::SYNC_PATH) => {
+                let response = match serde_json::from_slice::<replication::Digest>(&req_body) {
+                    Ok(digest) => Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&registry.changes_for(&digest))
+                                .unwrap_or_default(),
+                        )),
+
+                    Err(e) => Response::builder()
+                        .status(400)
+                        .body(Body::from(e.to_string())),
+                };
+
+                reply(response.ok());
+            }
+
+            (Method::GET, "/events") => {
+                // Clients resume after the last event that they received,
+                // which EventSource provides as a header when reconnecting
+
+                let after = req_parts
+                    .uri
+                    .query()
+                    .unwrap_or_default()
+                    .split('&')
+                    .find(|param| param.starts_with("after="))
+                    .map(|param| &param[6..]);
+
+                let last = header_value(
+                    &req_parts.headers,
+                    hyper::header::HeaderName::from_static("last-event-id"),
+                )
+                .or(after)
+                .map(|last| last.trim().parse::<u64>());
+
+                let response = match last {
+                    Some(Err(_)) => Response::builder()
+                        .status(400)
+                        .body(Body::from("invalid last event id")),
+
+                    last => {
+                        let stream = events
+                            .subscribe(last.and_then(Result::ok))
+                            .map(|event| event.to_sse())
+                            .map_err(|_| io::Error::new(io::ErrorKind::Other, "events ended"));
+
+                        Response::builder()
+                            .header("Content-Type", "text/event-stream")
+                            .header("Cache-Control", "no-cache")
+                            .body(Body::wrap_stream(stream))
+                    }
+                };
+
+                reply(response.ok());
+            }
+
+            (Method::GET, "/usage") => {
+                let usage = namespace::Usage::new(requests, &registry.stats());
+
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&usage).unwrap_or_default(),
+                    ));
+
+                reply(response.ok());
+            }
+
             (Method::GET, "/ping") => {
                 let response = Response::new(Body::from("pong!"));
 
@@ -288,10 +1840,50 @@ fn request_handler(
             }
         }
 
-        futures::finished((registry, pool))
+        namespaces.insert(
+            namespace_name,
+            namespace::Namespace {
+                registry,
+                pipelines,
+                jobs,
+                events,
+                webhook,
+                results,
+                limits: limit_settings,
+                job_limits,
+                requests,
+            },
+        );
+
+        send_changes(&replicator, &mut namespaces);
+        publish_events(&mut namespaces);
+
+        futures::finished(State { namespaces, pool })
     });
 
-    Box::new(future.map(|_| ()))
+    Ok(Box::new(future.join3(sweeper, resyncer).map(|_| ())))
+}
+
+/// Sends the changes that have been made in each namespace to peers.
+fn send_changes(
+    replicator: &replication::Replicator,
+    namespaces: &mut HashMap<String, namespace::Namespace>,
+) {
+    for (name, namespace) in namespaces.iter_mut() {
+        let changes = namespace.registry.take_changes();
+
+        replicator.send(&namespace::prefix(name), &changes);
+    }
+}
+
+/// Publishes the events that have happened in each namespace to its
+/// subscribers, and sends them to its webhook, if any.
+fn publish_events(namespaces: &mut HashMap<String, namespace::Namespace>) {
+    for namespace in namespaces.values_mut() {
+        let events = namespace.events.publish(namespace.registry.take_events());
+
+        namespace.webhook.send(&events);
+    }
 }
 
 /// Creates a TLS certificate (`Identity`) given PEM formatted public certificate and private key.
@@ -352,9 +1944,34 @@ fn create_tls_cert(
 fn main() -> io::Result<()> {
     let settings = settings::Settings::new(
         "JSAAS_BIND_ADDR",
+        "JSAAS_DATA_DIR",
+        "JSAAS_DATA_SNAPSHOT_RECORDS",
+        "JSAAS_DATA_STORE",
+        "JSAAS_EVENTS_HISTORY",
+        "JSAAS_EVENTS_WEBHOOK_URL",
+        "JSAAS_JOB_EXECUTION_COMPLETION_TIME",
+        "JSAAS_JOB_MAX_PENDING",
+        "JSAAS_JOB_RETENTION_TIME",
+        "JSAAS_NAMESPACES",
+        "JSAAS_NDJSON_MAX_LINE_LENGTH",
+        "JSAAS_REPLICATION_PEERS",
+        "JSAAS_REPLICATION_RESYNC_INTERVAL",
+        "JSAAS_REPLICATION_SECRET",
+        "JSAAS_REQUEST_MAX_ARGUMENTS_BYTES",
+        "JSAAS_REQUEST_MAX_BATCH_BYTES",
+        "JSAAS_REQUEST_MAX_SCRIPT_BYTES",
+        "JSAAS_RESULT_CACHE_MAX_BYTES",
+        "JSAAS_RESULT_CACHE_TTL",
         "JSAAS_SCRIPT_DEFINITION_EXPIRATION_TIME",
+        "JSAAS_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME",
+        "JSAAS_SCRIPT_DEFINITION_MAX_COUNT",
+        "JSAAS_SCRIPT_DEFINITION_MAX_BYTES",
+        "JSAAS_SCRIPT_DEFINITION_SWEEP_INTERVAL",
         "JSAAS_SCRIPT_EXECUTION_THREAD_POOL_SIZE",
         "JSAAS_SCRIPT_EXECUTION_COMPLETION_TIME",
+        "JSAAS_SCRIPT_EXECUTION_MAX_COMPLETION_TIME",
+        "JSAAS_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT",
+        "JSAAS_SCRIPT_EXECUTION_MEMORY_LIMIT",
         "JSAAS_TLS_BIND_ADDR",
         "JSAAS_TLS_PUBLIC_CERTIFICATE_PATH",
         "JSAAS_TLS_PRIVATE_KEY_PATH",
@@ -388,6 +2005,8 @@ fn main() -> io::Result<()> {
 
     let http_proto = Http::new();
 
+    let body_limits = body::BodyLimits::new(&settings);
+
     let setup_http_server = |bind_addr: &net::SocketAddr,
                              tls_identity: Option<native_tls::Identity>|
      -> io::Result<Box<Future<Item = (), Error = _> + Send>> {
@@ -412,26 +2031,76 @@ fn main() -> io::Result<()> {
             service_fn(move |req: Request<Body>| {
                 let (req_parts, req_raw_body) = req.into_parts();
                 let tx = tx.clone();
-                Box::new(
-                    req_raw_body
+                let (sender, c) = oneshot::channel::<Response<Body>>();
+
+                let response =
+                    c.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+
+                let path = namespace::split(req_parts.uri.path())
+                    .map(|(_, path)| path)
+                    .unwrap_or_default();
+
+                let stream = ndjson::is_stream(&req_parts.method, path, &req_parts.headers);
+
+                let limit = body_limits.get(body::kind(&req_parts.method, path, stream));
+
+                // Bodies that are declared to exceed their limit are refused
+                // before any of them is read
+
+                let declared = body::declared_length(&req_parts.headers);
+
+                if let (Some(length), Some(limit)) = (declared, limit) {
+                    if length > limit as u64 {
+                        let _ = sender.send(payload_too_large(Some(limit)));
+
+                        return Either::A(response);
+                    }
+                }
+
+                // Newline-delimited JSON is evaluated as it streams in, so its
+                // body is handed over unread instead of being buffered first.
+
+                if stream {
+                    tx.unbounded_send(Message::Request(RequestWithSender {
+                        req_parts,
+                        req_body: Bytes::new(),
+                        req_body_stream: Some(req_raw_body),
+                        sender,
+                    }))
+                    .expect("request_handler has stopped");
+
+                    return Either::A(response);
+                }
+
+                // Other bodies are buffered, but only up to their limit
+
+                let chunks = req_raw_body
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+
+                Either::B(
+                    body::Limited::new(chunks, limit)
                         .concat2()
-                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-                        .and_then(move |chunks| {
-                            let req_body = chunks.into_bytes();
-                            let tx = tx.clone();
-                            let (sender, c) = oneshot::channel::<Response<Body>>();
-
-                            tx.unbounded_send(RequestWithSender {
-                                req_parts,
-                                req_body,
-                                sender,
-                            })
-                            .expect("request_handler has stopped");
+                        .then(move |result| {
+                            match result {
+                                Ok(chunks) => tx
+                                    .unbounded_send(Message::Request(RequestWithSender {
+                                        req_parts,
+                                        req_body: chunks.into_bytes(),
+                                        req_body_stream: None,
+                                        sender,
+                                    }))
+                                    .expect("request_handler has stopped"),
+
+                                Err(ref e) if body::is_too_large(e) => {
+                                    let _ = sender.send(payload_too_large(limit));
+                                }
 
-                            c.map_err(|e| {
-                                std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                            })
-                        }),
+                                Err(e) => return Err(e),
+                            }
+
+                            Ok(())
+                        })
+                        .and_then(|_| response),
                 )
             })
         };
@@ -506,12 +2175,7 @@ fn main() -> io::Result<()> {
 
     // Get a request handler that holds state and completes requests
 
-    let request_handler = request_handler(
-        rx,
-        settings.script_execution_thread_pool_size,
-        settings.script_definition_expiration_time,
-        settings.script_execution_completion_time,
-    );
+    let request_handler = request_handler(rx, tx.clone(), &settings)?;
 
     let tls_cert = match (tls_private_key_path, tls_public_certificate_path) {
         (Some(private), Some(public)) => Some(create_tls_cert(private, public)?),
@@ -0,0 +1,57 @@
+use std::fmt::{Display, Write};
+
+pub(crate) const CONTENT_TYPE_METRICS: &str = "text/plain; version=0.0.4";
+
+/// Accumulates metrics in the Prometheus text exposition format.
+pub(crate) struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            text: String::new(),
+        }
+    }
+
+    pub(crate) fn counter<V: Display>(&mut self, name: &str, help: &str, value: V) {
+        self.metric(name, "counter", help, value);
+    }
+
+    pub(crate) fn gauge<V: Display>(&mut self, name: &str, help: &str, value: V) {
+        self.metric(name, "gauge", help, value);
+    }
+
+    pub(crate) fn into_string(self) -> String {
+        self.text
+    }
+
+    fn metric<V: Display>(&mut self, name: &str, kind: &str, help: &str, value: V) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.text, "{} {}", name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_text() {
+        let mut metrics = Metrics::new();
+
+        metrics.counter("jsaas_test_total", "A test counter.", 3);
+        metrics.gauge("jsaas_test_bytes", "A test gauge.", 12);
+
+        assert_eq!(
+            metrics.into_string(),
+            "# HELP jsaas_test_total A test counter.\n\
+             # TYPE jsaas_test_total counter\n\
+             jsaas_test_total 3\n\
+             # HELP jsaas_test_bytes A test gauge.\n\
+             # TYPE jsaas_test_bytes gauge\n\
+             jsaas_test_bytes 12\n"
+        );
+    }
+}
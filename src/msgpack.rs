@@ -0,0 +1,334 @@
+use crate::codec::{self, invalid_data, Builder, Encoder, Reader};
+use std::io;
+
+pub(crate) const CONTENT_TYPE_MSGPACK: &str = "application/msgpack";
+
+/// Decodes a single MessagePack value, supplying its values to `builder`.
+/// Extension types are decoded as their data.
+pub(crate) fn decode<B: Builder>(data: &[u8], builder: &mut B) -> io::Result<()> {
+    let mut reader = Reader::new(data);
+
+    decode_value(&mut reader, builder, 0)?;
+
+    if reader.is_empty() {
+        Ok(())
+    } else {
+        invalid_data("unexpected data after MessagePack value")
+    }
+}
+
+/// Reads a length of `size` bytes, which must fit within the remaining data
+/// given that each element occupies at least one byte.
+fn length(reader: &mut Reader, size: usize) -> io::Result<usize> {
+    let len = reader.uint(size)?;
+
+    if len > reader.remaining() as u64 {
+        invalid_data("unexpected end of data")
+    } else {
+        Ok(len as usize)
+    }
+}
+
+fn decode_array<B: Builder>(
+    reader: &mut Reader,
+    builder: &mut B,
+    len: usize,
+    depth: usize,
+) -> io::Result<()> {
+    builder.start_array()?;
+
+    for index in 0..len {
+        decode_value(reader, builder, depth + 1)?;
+        builder.array_item(index)?;
+    }
+
+    Ok(())
+}
+
+fn decode_map<B: Builder>(
+    reader: &mut Reader,
+    builder: &mut B,
+    len: usize,
+    depth: usize,
+) -> io::Result<()> {
+    builder.start_map()?;
+
+    for _ in 0..len {
+        decode_value(reader, builder, depth + 1)?;
+        decode_value(reader, builder, depth + 1)?;
+        builder.map_entry()?;
+    }
+
+    Ok(())
+}
+
+fn decode_value<B: Builder>(reader: &mut Reader, builder: &mut B, depth: usize) -> io::Result<()> {
+    if depth > codec::MAX_DEPTH {
+        return invalid_data("value is nested too deeply");
+    }
+
+    let marker = reader.byte()?;
+
+    match marker {
+        0x00..=0x7f => builder.number(f64::from(marker)),
+        0x80..=0x8f => decode_map(reader, builder, usize::from(marker & 0x0f), depth),
+        0x90..=0x9f => decode_array(reader, builder, usize::from(marker & 0x0f), depth),
+        0xa0..=0xbf => builder.string(reader.string(usize::from(marker & 0x1f))?),
+        0xc0 => builder.null(),
+        0xc2 => builder.boolean(false),
+        0xc3 => builder.boolean(true),
+
+        0xc4..=0xc6 => {
+            let len = length(reader, 1 << (marker - 0xc4))?;
+
+            builder.bytes(reader.take(len)?)
+        }
+
+        0xc7..=0xc9 => {
+            let len = length(reader, 1 << (marker - 0xc7))?;
+
+            reader.byte()?;
+
+            builder.bytes(reader.take(len)?)
+        }
+
+        0xca => builder.number(f64::from(f32::from_bits(reader.uint(4)? as u32))),
+        0xcb => builder.number(f64::from_bits(reader.uint(8)?)),
+        0xcc..=0xcf => builder.number(reader.uint(1 << (marker - 0xcc))? as f64),
+
+        0xd0..=0xd3 => {
+            let size = 1 << (marker - 0xd0);
+            let value = reader.uint(size)?;
+
+            // sign-extend the value from its size to 64 bits
+            let shift = 64 - size * 8;
+
+            builder.number((((value << shift) as i64) >> shift) as f64)
+        }
+
+        0xd4..=0xd8 => {
+            reader.byte()?;
+
+            builder.bytes(reader.take(1 << (marker - 0xd4))?)
+        }
+
+        0xd9..=0xdb => {
+            let len = length(reader, 1 << (marker - 0xd9))?;
+
+            builder.string(reader.string(len)?)
+        }
+
+        0xdc | 0xdd => {
+            let len = length(reader, 2 << (marker - 0xdc))?;
+
+            decode_array(reader, builder, len, depth)
+        }
+
+        0xde | 0xdf => {
+            let len = length(reader, 2 << (marker - 0xde))?;
+
+            decode_map(reader, builder, len, depth)
+        }
+
+        0xe0..=0xff => builder.number(f64::from(marker as i8)),
+
+        _ => invalid_data("invalid MessagePack marker"),
+    }
+}
+
+/// Encodes values as MessagePack, using the shortest form for integers and
+/// lengths. `undefined` has no representation, so it is encoded as nil.
+pub(crate) struct MsgPackEncoder {
+    bytes: Vec<u8>,
+}
+
+impl MsgPackEncoder {
+    pub(crate) fn new() -> Self {
+        Self { bytes: vec![] }
+    }
+
+    /// Writes the header for a length, given the marker and maximum length
+    /// of its fixed form and the marker of its 8-bit form (if it has them),
+    /// and the markers of its 16 and 32-bit forms.
+    fn header(
+        &mut self,
+        len: usize,
+        fixed: Option<(u8, usize)>,
+        marker8: Option<u8>,
+        marker16: u8,
+        marker32: u8,
+    ) {
+        match (fixed, marker8) {
+            (Some((marker, max)), _) if len <= max => self.bytes.push(marker | len as u8),
+
+            (_, Some(marker)) if len <= 0xff => {
+                self.bytes.push(marker);
+                self.bytes.push(len as u8);
+            }
+
+            _ if len <= 0xffff => {
+                self.bytes.push(marker16);
+                self.bytes.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+
+            _ => {
+                self.bytes.push(marker32);
+                self.bytes.extend_from_slice(&(len as u32).to_be_bytes());
+            }
+        }
+    }
+}
+
+impl Encoder for MsgPackEncoder {
+    fn null(&mut self) {
+        self.bytes.push(0xc0);
+    }
+
+    fn undefined(&mut self) {
+        self.bytes.push(0xc0);
+    }
+
+    fn boolean(&mut self, value: bool) {
+        self.bytes.push(if value { 0xc3 } else { 0xc2 });
+    }
+
+    fn number(&mut self, value: f64) {
+        match codec::as_integer(value) {
+            Some(n) if n >= 0 && n <= 0x7f => self.bytes.push(n as u8),
+            Some(n) if n < 0 && n >= -32 => self.bytes.push(n as i8 as u8),
+
+            Some(n) if n >= 0 && n <= 0xff => {
+                self.bytes.push(0xcc);
+                self.bytes.push(n as u8);
+            }
+
+            Some(n) if n >= 0 && n <= 0xffff => {
+                self.bytes.push(0xcd);
+                self.bytes.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+
+            Some(n) if n >= 0 && n <= 0xffff_ffff => {
+                self.bytes.push(0xce);
+                self.bytes.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+
+            Some(n) if n >= 0 => {
+                self.bytes.push(0xcf);
+                self.bytes.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+
+            Some(n) if n >= -0x80 => {
+                self.bytes.push(0xd0);
+                self.bytes.push(n as i8 as u8);
+            }
+
+            Some(n) if n >= -0x8000 => {
+                self.bytes.push(0xd1);
+                self.bytes.extend_from_slice(&(n as i16).to_be_bytes());
+            }
+
+            Some(n) if n >= -0x8000_0000 => {
+                self.bytes.push(0xd2);
+                self.bytes.extend_from_slice(&(n as i32).to_be_bytes());
+            }
+
+            Some(n) => {
+                self.bytes.push(0xd3);
+                self.bytes.extend_from_slice(&n.to_be_bytes());
+            }
+
+            None => {
+                self.bytes.push(0xcb);
+                self.bytes.extend_from_slice(&value.to_bits().to_be_bytes());
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.header(value.len(), Some((0xa0, 31)), Some(0xd9), 0xda, 0xdb);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.header(value.len(), None, Some(0xc4), 0xc5, 0xc6);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn array(&mut self, len: usize) {
+        self.header(len, Some((0x90, 15)), None, 0xdc, 0xdd);
+    }
+
+    fn map(&mut self, len: usize) {
+        self.header(len, Some((0x80, 15)), None, 0xde, 0xdf);
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::tests::Recorder;
+
+    fn decoded(data: &[u8]) -> io::Result<Vec<String>> {
+        let mut recorder = Recorder(vec![]);
+
+        decode(data, &mut recorder).map(|_| recorder.0)
+    }
+
+    #[test]
+    fn test_msgpack_decode() {
+        // [1, -2, "a", bin(1, 2), {"k": true}, nil, 1.5, -300]
+        let data = [
+            0x98, 0x01, 0xfe, 0xa1, 0x61, 0xc4, 0x02, 0x01, 0x02, 0x81, 0xa1, 0x6b, 0xc3, 0xc0,
+            0xca, 0x3f, 0xc0, 0x00, 0x00, 0xd1, 0xfe, 0xd4,
+        ];
+
+        assert_eq!(
+            decoded(&data).unwrap(),
+            vec![
+                "[", "1", "#0", "-2", "#1", "\"a\"", "#2", "[1, 2]", "#3", "{", "\"k\"", "true",
+                ":", "#4", "null", "#5", "1.5", "#6", "-300", "#7"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_msgpack_decode_invalid() {
+        assert!(decoded(&[0x92, 0x01]).is_err());
+        assert!(decoded(&[0x01, 0x02]).is_err());
+        assert!(decoded(&[0xc1]).is_err());
+        assert!(decoded(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decoded(&[0xa2, 0xff, 0xfe]).is_err());
+        assert!(decoded(&vec![0x91; 1000]).is_err());
+    }
+
+    #[test]
+    fn test_msgpack_encode() {
+        let mut encoder = MsgPackEncoder::new();
+
+        encoder.map(2);
+        encoder.string("a");
+        encoder.array(4);
+        encoder.number(1.0);
+        encoder.number(-500.0);
+        encoder.number(200.0);
+        encoder.number(1.5);
+        encoder.string("b");
+        encoder.bytes(&[1, 2]);
+
+        assert_eq!(
+            encoder.into_bytes(),
+            vec![
+                0x82, 0xa1, 0x61, 0x94, 0x01, 0xd1, 0xfe, 0x0c, 0xcc, 0xc8, 0xcb, 0x3f, 0xf8, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0xa1, 0x62, 0xc4, 0x02, 0x01, 0x02
+            ]
+        );
+    }
+}
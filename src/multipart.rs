@@ -0,0 +1,206 @@
+use std::io;
+
+pub(crate) const CONTENT_TYPE_MULTIPART: &str = "multipart/form-data";
+
+/// One part of a `multipart/form-data` body (RFC 7578).
+#[derive(Debug, PartialEq)]
+pub(crate) struct Part<'a> {
+    pub(crate) name: Option<String>,
+    pub(crate) content_type: Option<String>,
+    pub(crate) body: &'a [u8],
+}
+
+/// Returns the boundary of a `multipart/form-data` content type, or `None`
+/// if the content type is something else.
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+
+    let media_type = params.next()?.trim();
+
+    if !media_type.eq_ignore_ascii_case(CONTENT_TYPE_MULTIPART) {
+        return None;
+    }
+
+    params.filter_map(param).find_map(|(name, value)| {
+        if name.eq_ignore_ascii_case("boundary") && !value.is_empty() {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Splits a multipart body into its parts. The preamble before the first
+/// boundary and the epilogue after the last are ignored.
+pub(crate) fn parse<'a>(body: &'a [u8], boundary: &str) -> io::Result<Vec<Part<'a>>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let separator = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut position = match find(body, &delimiter) {
+        Some(p) => p + delimiter.len(),
+        None => return invalid("cannot find multipart boundary"),
+    };
+
+    let mut parts = vec![];
+
+    loop {
+        let rest = &body[position..];
+
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        // Transport padding may follow the boundary before its line ends
+
+        let line_end = match find(rest, b"\r\n") {
+            Some(p) if rest[..p].iter().all(|b| *b == b' ' || *b == b'\t') => p + 2,
+            _ => return invalid("invalid multipart boundary"),
+        };
+
+        let rest = &rest[line_end..];
+
+        let end = match find(rest, &separator) {
+            Some(p) => p,
+            None => return invalid("cannot find end of multipart part"),
+        };
+
+        parts.push(parse_part(&rest[..end])?);
+
+        position += line_end + end + separator.len();
+    }
+}
+
+fn parse_part(data: &[u8]) -> io::Result<Part> {
+    let (headers, body) = if data.starts_with(b"\r\n") {
+        (&data[..0], &data[2..])
+    } else {
+        match find(data, b"\r\n\r\n") {
+            Some(p) => (&data[..p], &data[p + 4..]),
+            None => return invalid("cannot find end of multipart headers"),
+        }
+    };
+
+    let headers = match std::str::from_utf8(headers) {
+        Ok(headers) => headers,
+        Err(_) => return invalid("multipart headers are not valid UTF-8"),
+    };
+
+    let mut part = Part {
+        name: None,
+        content_type: None,
+        body,
+    };
+
+    for line in headers.split("\r\n") {
+        let mut fields = line.splitn(2, ':');
+        let header = fields.next().unwrap_or_default().trim();
+        let value = fields.next().unwrap_or_default().trim();
+
+        if header.eq_ignore_ascii_case("content-disposition") {
+            part.name = value
+                .split(';')
+                .skip(1)
+                .filter_map(param)
+                .find(|(name, _)| name.eq_ignore_ascii_case("name"))
+                .map(|(_, value)| value);
+        } else if header.eq_ignore_ascii_case("content-type") {
+            part.content_type = Some(value.to_string());
+        }
+    }
+
+    Ok(part)
+}
+
+/// Parses a `name=value` parameter, where the value may be quoted.
+fn param(param: &str) -> Option<(String, String)> {
+    let mut fields = param.splitn(2, '=');
+    let name = fields.next()?.trim();
+    let value = fields.next()?.trim();
+
+    let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        value[1..value.len() - 1].replace("\\\"", "\"")
+    } else {
+        value.to_string()
+    };
+
+    Some((name.to_string(), value))
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid<T>(message: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc"),
+            Some("abc".to_string())
+        );
+
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\""),
+            Some("a b".to_string())
+        );
+
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("application/json; boundary=abc"), None);
+    }
+
+    #[test]
+    fn test_multipart_parse() {
+        let body = b"preamble\r\n--xyz\r\n\
+            Content-Disposition: form-data; name=\"code\"\r\n\
+            \r\n\
+            function(a) { return a; }\r\n\
+            --xyz  \r\n\
+            content-disposition: form-data; name=args; filename=\"args.cbor\"\r\n\
+            Content-Type: application/cbor\r\n\
+            \r\n\
+            \x81\x01\r\n\
+            --xyz\r\n\
+            \r\n\
+            \r\n\
+            --xyz--\r\nepilogue";
+
+        assert_eq!(
+            parse(body, "xyz").unwrap(),
+            vec![
+                Part {
+                    name: Some("code".to_string()),
+                    content_type: None,
+                    body: b"function(a) { return a; }",
+                },
+                Part {
+                    name: Some("args".to_string()),
+                    content_type: Some("application/cbor".to_string()),
+                    body: b"\x81\x01",
+                },
+                Part {
+                    name: None,
+                    content_type: None,
+                    body: b"",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multipart_parse_invalid() {
+        assert!(parse(b"no boundary here", "xyz").is_err());
+        assert!(parse(
+            b"--xyz\r\nContent-Type: text/plain\r\n\r\nunterminated",
+            "xyz"
+        )
+        .is_err());
+        assert!(parse(b"--xyz\r\nno headers end\r\n--xyz--", "xyz").is_err());
+        assert!(parse(b"--xyzabc\r\n\r\n\r\n--xyz--", "xyz").is_err());
+    }
+}
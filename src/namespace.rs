@@ -0,0 +1,223 @@
+use crate::duktape::Limits;
+use crate::events::{Feed, Webhook};
+use crate::jobs::JobRegistry;
+use crate::limits::LimitSettings;
+use crate::pipeline::PipelineRegistry;
+use crate::result_cache::ResultCache;
+use crate::script_registry::{Capacity, Expiration, RegistryStats, ScriptRegistry};
+use crate::settings::Settings;
+use crate::store;
+use std::io;
+
+/// The namespace of requests whose paths don't name one.
+pub(crate) const DEFAULT: &str = "";
+
+const PREFIX: &str = "/ns/";
+
+/// The directory, within the data directory, that holds the scripts of
+/// each namespace other than the default.
+const DIR: &str = "namespaces";
+
+/// A partition of the server with its own scripts, pipelines and jobs, its
+/// own feed of events and webhook, cache of results, and limits. Requests
+/// can only reach the namespace that their path names, e.g.
+/// `/ns/billing/scripts`.
+pub(crate) struct Namespace {
+    pub(crate) registry: ScriptRegistry,
+    pub(crate) pipelines: PipelineRegistry,
+    pub(crate) jobs: JobRegistry,
+    pub(crate) events: Feed,
+    pub(crate) webhook: Webhook,
+    pub(crate) results: ResultCache,
+    pub(crate) limits: LimitSettings,
+    pub(crate) job_limits: Limits,
+    pub(crate) requests: u64,
+}
+
+/// Describes how a namespace has been used.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Usage {
+    pub(crate) requests: u64,
+    pub(crate) executions: u64,
+    pub(crate) scripts: usize,
+    pub(crate) bytes: usize,
+    pub(crate) expirations: u64,
+    pub(crate) evictions: u64,
+}
+
+impl Namespace {
+    /// Creates a namespace with the provided settings. Its scripts are kept
+    /// in the configured store, which is in the data directory, if there is
+    /// one, and in the case of a namespace other than the default, in a
+    /// directory of its own.
+    pub(crate) fn new(name: &str, settings: &Settings) -> io::Result<Self> {
+        // A memory limit of 0 signifies that memory isn't limited
+
+        let memory_limit = |limit: usize| if limit == 0 { None } else { Some(limit) };
+
+        let limits = LimitSettings {
+            default: Limits {
+                time: settings.script_execution_completion_time,
+                memory: memory_limit(settings.script_execution_memory_limit),
+            },
+            maximum: Limits {
+                time: settings.script_execution_max_completion_time,
+                memory: memory_limit(settings.script_execution_max_memory_limit),
+            },
+        };
+
+        let job_limits = Limits {
+            time: settings.job_execution_completion_time,
+            ..limits.default
+        };
+
+        let expiration = Expiration {
+            default: settings.script_definition_expiration_time,
+            maximum: Some(settings.script_definition_max_expiration_time)
+                .filter(|maximum| maximum.as_millis() > 0),
+        };
+
+        // Likewise, a maximum of 0 signifies that the registry isn't bounded
+
+        let maximum = |max: usize| if max == 0 { None } else { Some(max) };
+
+        let capacity = Capacity {
+            entries: maximum(settings.script_definition_max_count),
+            bytes: maximum(settings.script_definition_max_bytes),
+        };
+
+        let dir = match settings.data_dir {
+            Some(ref dir) if name == DEFAULT => Some(dir.clone()),
+            Some(ref dir) => Some(dir.join(DIR).join(name)),
+            None => None,
+        };
+
+        let store = store::open(
+            settings.data_store,
+            dir.clone(),
+            settings.data_snapshot_records,
+        )?;
+
+        // Numbering of events only continues across restarts when scripts
+        // are persisted, as otherwise the scripts they were about are gone
+
+        let events = match dir {
+            Some(ref dir) if settings.data_store != store::Backend::Memory => {
+                Feed::open(name, settings.events_history, dir)?
+            }
+
+            _ => Feed::new(name, settings.events_history),
+        };
+
+        let mut registry = ScriptRegistry::open(expiration, capacity, store)?;
+
+        if !settings.replication_peers.is_empty() {
+            registry.replicate();
+        }
+
+        Ok(Self {
+            registry,
+            pipelines: PipelineRegistry::new(),
+            jobs: JobRegistry::new(settings.job_retention_time, settings.job_max_pending),
+            events,
+            webhook: Webhook::new(settings.events_webhook_url.clone()),
+            results: ResultCache::new(settings.result_cache_ttl, settings.result_cache_max_bytes),
+            limits,
+            job_limits,
+            requests: 0,
+        })
+    }
+}
+
+impl Usage {
+    pub(crate) fn new(requests: u64, stats: &RegistryStats) -> Self {
+        Self {
+            requests,
+            executions: stats.executions,
+            scripts: stats.entries,
+            bytes: stats.bytes,
+            expirations: stats.expirations,
+            evictions: stats.evictions,
+        }
+    }
+}
+
+/// Checks that the name of a namespace consists only of ASCII letters,
+/// digits, '-' and '_', given it's also the name of a directory.
+pub(crate) fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "namespace {} must consist of letters, digits, '-' and '_'",
+                name
+            ),
+        ))
+    }
+}
+
+/// Splits a request's path into the namespace that it names, and the path
+/// within the namespace, e.g. `/ns/billing/scripts` into `billing` and
+/// `/scripts`. Paths that don't name a namespace are in the default one.
+pub(crate) fn split(path: &str) -> Option<(&str, &str)> {
+    if !path.starts_with(PREFIX) {
+        return Some((DEFAULT, path));
+    }
+
+    let rest = &path[PREFIX.len()..];
+    let end = rest.find('/').unwrap_or(rest.len());
+
+    match (&rest[..end], &rest[end..]) {
+        ("", _) => None,
+        (name, "") => Some((name, "/")),
+        split => Some(split),
+    }
+}
+
+/// Returns the prefix of paths in a namespace.
+pub(crate) fn prefix(name: &str) -> String {
+    if name == DEFAULT {
+        String::new()
+    } else {
+        format!("{}{}", PREFIX, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_split() {
+        assert_eq!(split("/scripts/abc"), Some((DEFAULT, "/scripts/abc")));
+        assert_eq!(split("/ns"), Some((DEFAULT, "/ns")));
+        assert_eq!(
+            split("/ns/billing/scripts/abc"),
+            Some(("billing", "/scripts/abc"))
+        );
+        assert_eq!(split("/ns/billing"), Some(("billing", "/")));
+        assert_eq!(split("/ns/"), None);
+        assert_eq!(split("/ns//scripts"), None);
+
+        assert_eq!(prefix(DEFAULT), "");
+        assert_eq!(prefix("billing"), "/ns/billing");
+    }
+
+    #[test]
+    fn test_namespace_validate_name() {
+        assert!(validate_name("billing").is_ok());
+        assert!(validate_name("team-a_2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name(&"a".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::script_registry;
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Poll, Stream};
+use hyper::header::CONTENT_TYPE;
+use hyper::{HeaderMap, Method};
+use std::io;
+
+pub(crate) const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+
+/// Determines if the provided headers declare a newline-delimited JSON body.
+pub(crate) fn is_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| {
+            let v = v.trim();
+
+            v.eq_ignore_ascii_case(CONTENT_TYPE_NDJSON)
+                || v.eq_ignore_ascii_case("application/ndjson")
+        })
+        .unwrap_or(false)
+}
+
+/// Determines if a request, given its path within a namespace, executes a
+/// script once for every line of a newline-delimited JSON body, i.e. it's a
+/// `POST` of such a body to `/scripts/{id}` or `/scripts/by-name/{name}`.
+/// Other requests with that content type, e.g. for linting, aren't streamed.
+pub(crate) fn is_stream(method: &Method, path: &str, headers: &HeaderMap) -> bool {
+    method == Method::POST
+        && path.starts_with("/scripts/")
+        && script_registry::is_reference(&path[9..])
+        && is_ndjson(headers)
+}
+
+/// Formats the outcome of evaluating one input line as a single output line.
+///
+/// Successful results are wrapped as `{"result":...}` and failures as
+/// `{"error":"..."}` so that callers can distinguish them regardless of what
+/// the script returns. A result of `undefined` has no JSON representation,
+/// so it's given as `null`.
+pub(crate) fn result_line(result: io::Result<Option<String>>) -> String {
+    match result {
+        Ok(Some(json)) => format!("{{\"result\":{}}}\n", json),
+
+        Ok(None) => "{\"result\":null}\n".to_string(),
+
+        Err(e) => format!(
+            "{{\"error\":{}}}\n",
+            serde_json::to_string(&e.to_string()).unwrap_or_else(|_| "\"\"".to_string())
+        ),
+    }
+}
+
+/// Splits a stream of chunks into lines, yielding each line as soon as its
+/// terminating newline arrives. Empty lines are skipped, and a trailing line
+/// without a newline is yielded once the underlying stream ends.
+///
+/// At most `max_line_length` bytes are buffered; a longer line yields an
+/// error, after which the stream ends. Each byte is only scanned for a
+/// newline once, however many chunks a line arrives in.
+pub(crate) struct Lines<S> {
+    inner: S,
+    buffer: BytesMut,
+    scanned: usize,
+    max_line_length: usize,
+    done: bool,
+}
+
+impl<S> Lines<S> {
+    pub(crate) fn new(inner: S, max_line_length: usize) -> Self {
+        Self {
+            inner,
+            buffer: BytesMut::new(),
+            scanned: 0,
+            max_line_length,
+            done: false,
+        }
+    }
+
+    /// Returns the position of the first newline in the buffer, scanning
+    /// only the bytes that haven't been scanned yet. Afterwards, `scanned`
+    /// is the length of the pending line.
+    fn newline(&mut self) -> Option<usize> {
+        let found = self.buffer[self.scanned..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|pos| self.scanned + pos);
+
+        self.scanned = found.unwrap_or_else(|| self.buffer.len());
+
+        found
+    }
+
+    fn next_line(&mut self) -> Option<Bytes> {
+        while let Some(pos) = self.newline() {
+            if pos > self.max_line_length {
+                return None;
+            }
+
+            let line = trim_line(self.buffer.split_to(pos + 1).freeze());
+            self.scanned = 0;
+
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+
+        None
+    }
+
+    fn line_too_long(&mut self) -> io::Error {
+        self.done = true;
+        self.buffer.clear();
+        self.scanned = 0;
+
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line exceeds {} bytes", self.max_line_length),
+        )
+    }
+}
+
+impl<S> Stream for Lines<S>
+where
+    S: Stream<Error = io::Error>,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, io::Error> {
+        loop {
+            if let Some(line) = self.next_line() {
+                return Ok(Async::Ready(Some(line)));
+            }
+
+            if self.scanned > self.max_line_length {
+                return Err(self.line_too_long());
+            }
+
+            if self.done {
+                let rest = trim_line(self.buffer.take().freeze());
+                self.scanned = 0;
+
+                return Ok(Async::Ready(if rest.is_empty() {
+                    None
+                } else {
+                    Some(rest)
+                }));
+            }
+
+            match self.inner.poll() {
+                Ok(Async::Ready(Some(chunk))) => self.buffer.extend_from_slice(chunk.as_ref()),
+
+                Ok(Async::Ready(None)) => self.done = true,
+
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+
+                Err(e) => {
+                    self.done = true;
+                    self.buffer.clear();
+                    self.scanned = 0;
+
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn trim_line(mut line: Bytes) -> Bytes {
+    while line.last().map(|b| *b == b'\n' || *b == b'\r') == Some(true) {
+        line.truncate(line.len() - 1);
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{stream, Future};
+
+    fn collect_lines(
+        chunks: Vec<&'static str>,
+        max_line_length: usize,
+    ) -> Vec<Result<String, String>> {
+        let inner = stream::iter_ok::<_, io::Error>(chunks.into_iter().map(|c| c.as_bytes()));
+
+        Lines::new(inner, max_line_length)
+            .then(|r| {
+                Ok::<_, ()>(
+                    r.map(|l| String::from_utf8(l.to_vec()).unwrap())
+                        .map_err(|e| e.to_string()),
+                )
+            })
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lines_split_across_chunks() {
+        assert_eq!(
+            collect_lines(vec!["[1, ", "2]\n[3", "]\r\n\n[4]"], 1024),
+            vec![
+                Ok("[1, 2]".to_string()),
+                Ok("[3]".to_string()),
+                Ok("[4]".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lines_split_into_bytes() {
+        let line = "[\"abcdefghijklmnopqrstuvwxyz\"]\n";
+        let chunks = (0..line.len()).map(|i| &line[i..=i]).collect::<Vec<_>>();
+
+        assert_eq!(
+            collect_lines(chunks.clone(), 1024),
+            vec![Ok("[\"abcdefghijklmnopqrstuvwxyz\"]".to_string())]
+        );
+
+        assert_eq!(
+            collect_lines(chunks, 16),
+            vec![Err("line exceeds 16 bytes".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_lines_too_long() {
+        assert_eq!(
+            collect_lines(vec!["[1]\n", "[1, 2, 3, 4, 5, 6]\n", "[2]\n"], 8),
+            vec![
+                Ok("[1]".to_string()),
+                Err("line exceeds 8 bytes".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_stream() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, CONTENT_TYPE_NDJSON.parse().unwrap());
+
+        let id = script_registry::ScriptId::random();
+
+        assert!(is_stream(
+            &Method::POST,
+            &format!("/scripts/{}", id),
+            &headers
+        ));
+        assert!(is_stream(
+            &Method::POST,
+            "/scripts/by-name/adder@2",
+            &headers
+        ));
+        assert!(!is_stream(&Method::POST, "/scripts/lint", &headers));
+        assert!(!is_stream(&Method::POST, "/scripts/", &headers));
+        assert!(!is_stream(
+            &Method::POST,
+            &format!("/scripts/{}/jobs", id),
+            &headers
+        ));
+        assert!(!is_stream(
+            &Method::PUT,
+            &format!("/scripts/{}", id),
+            &headers
+        ));
+        assert!(!is_stream(
+            &Method::POST,
+            &format!("/scripts/{}", id),
+            &HeaderMap::new()
+        ));
+    }
+
+    #[test]
+    fn test_result_line() {
+        assert_eq!(result_line(Ok(Some("6".to_string()))), "{\"result\":6}\n");
+        assert_eq!(result_line(Ok(None)), "{\"result\":null}\n");
+
+        assert_eq!(
+            result_line(Err(io::Error::new(io::ErrorKind::Other, "oops \"here\""))),
+            "{\"error\":\"oops \\\"here\\\"\"}\n"
+        );
+    }
+}
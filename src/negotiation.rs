@@ -0,0 +1,319 @@
+use crate::cbor::CONTENT_TYPE_CBOR;
+use crate::codec::Encoding;
+use crate::duktape::{Output, OutputFormat};
+use crate::msgpack::CONTENT_TYPE_MSGPACK;
+
+pub(crate) const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_MSGPACK_LEGACY: &str = "application/x-msgpack";
+const CONTENT_TYPE_TEXT: &str = "text/plain";
+const CONTENT_TYPE_HTML: &str = "text/html";
+const CONTENT_TYPE_BINARY: &str = "application/octet-stream";
+
+/// The representations of a script's result that a client will accept.
+/// Results are encoded as JSON, CBOR or MessagePack, with JSON remaining the
+/// default. Strings and buffers are only returned as they are when the client
+/// prefers it, or the script declares an output type.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Negotiation {
+    encoded: Option<(Encoding, &'static str)>,
+    text: Option<String>,
+    binary: Option<String>,
+}
+
+/// Determines the encoding of a request's arguments given its `Content-Type`.
+pub(crate) fn args_encoding(content_type: Option<&str>) -> Encoding {
+    let media_type = content_type
+        .and_then(|c| c.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    match media_type.as_str() {
+        CONTENT_TYPE_CBOR => Encoding::Cbor,
+        CONTENT_TYPE_MSGPACK | CONTENT_TYPE_MSGPACK_LEGACY => Encoding::MsgPack,
+        _ => Encoding::Json,
+    }
+}
+
+impl Negotiation {
+    /// Negotiates given the value of a request's `Accept` header, and the
+    /// output type that the script declares, if any.
+    pub(crate) fn new(accept: Option<&str>, declared: Option<&str>) -> Self {
+        let accepted = parse_accept(accept.unwrap_or("*/*"));
+
+        let encoded = choose(
+            &accepted,
+            &[
+                CONTENT_TYPE_JSON,
+                CONTENT_TYPE_CBOR,
+                CONTENT_TYPE_MSGPACK,
+                CONTENT_TYPE_MSGPACK_LEGACY,
+            ],
+        );
+
+        // Strings and buffers are returned as they are if their type is
+        // preferred over the encoding for other values
+
+        let encoded_type = encoded.unwrap_or(CONTENT_TYPE_JSON);
+
+        let text_candidates = match declared {
+            Some(declared) => vec![declared, encoded_type],
+            None => vec![encoded_type, CONTENT_TYPE_TEXT, CONTENT_TYPE_HTML],
+        };
+
+        let binary_candidates = match declared {
+            Some(declared) => vec![declared, encoded_type],
+            None => vec![encoded_type, CONTENT_TYPE_BINARY],
+        };
+
+        let not_encoded = |t: &&str| Some(*t) != encoded;
+
+        Self {
+            encoded: encoded.map(|content_type| {
+                let encoding = match content_type {
+                    CONTENT_TYPE_CBOR => Encoding::Cbor,
+                    CONTENT_TYPE_MSGPACK | CONTENT_TYPE_MSGPACK_LEGACY => Encoding::MsgPack,
+                    _ => Encoding::Json,
+                };
+
+                (encoding, content_type)
+            }),
+            text: choose(&accepted, &text_candidates)
+                .filter(not_encoded)
+                .map(String::from),
+            binary: choose(&accepted, &binary_candidates)
+                .filter(not_encoded)
+                .map(String::from),
+        }
+    }
+
+    /// The output format to evaluate the script with.
+    pub(crate) fn format(&self) -> OutputFormat {
+        OutputFormat {
+            encoding: self
+                .encoded
+                .map(|(encoding, _)| encoding)
+                .unwrap_or_default(),
+            raw_strings: self.text.is_some(),
+            raw_buffers: self.binary.is_some(),
+        }
+    }
+
+    /// Determines the content type and body of the response for a result, or
+    /// `None` if the client doesn't accept its representation. Undefined results
+    /// have no representation.
+    pub(crate) fn respond(&self, output: Output) -> Option<(String, Vec<u8>)> {
+        let encoded = |encoding: Encoding, body: Vec<u8>| match self.encoded {
+            Some((e, content_type)) if e == encoding => Some((content_type.to_string(), body)),
+            _ => None,
+        };
+
+        match output {
+            Output::Json(json) => encoded(Encoding::Json, json.into_bytes()),
+
+            Output::Cbor(bytes) => encoded(Encoding::Cbor, bytes),
+
+            Output::MsgPack(bytes) => encoded(Encoding::MsgPack, bytes),
+
+            Output::Text(text) => self.text.as_ref().map(|content_type| {
+                let content_type =
+                    if content_type.starts_with("text/") && !content_type.contains(';') {
+                        format!("{}; charset=utf-8", content_type)
+                    } else {
+                        content_type.clone()
+                    };
+
+                (content_type, text.into_bytes())
+            }),
+
+            Output::Binary(bytes) => self
+                .binary
+                .as_ref()
+                .map(|content_type| (content_type.clone(), bytes)),
+
+            Output::Undefined => None,
+        }
+    }
+}
+
+/// Parses an `Accept` header into its media ranges and their quality.
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let media_range = parts.next()?.trim().to_lowercase();
+
+            if media_range.is_empty() {
+                return None;
+            }
+
+            let q = parts
+                .filter_map(|param| {
+                    let param = param.trim();
+
+                    if param.starts_with("q=") {
+                        param[2..].trim().parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+
+            Some((media_range, q))
+        })
+        .collect()
+}
+
+/// Determines the quality of a media type given the accepted media ranges.
+/// The most specific matching range applies, and unmatched types have a
+/// quality of zero.
+fn quality(accepted: &[(String, f32)], media_type: &str) -> f32 {
+    let media_type = media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    let top_level = media_type.split('/').next().unwrap_or_default();
+
+    accepted
+        .iter()
+        .filter_map(|(range, q)| {
+            if *range == media_type {
+                Some((2, *q))
+            } else if range.ends_with("/*") && range[..range.len() - 2] == *top_level {
+                Some((1, *q))
+            } else if range == "*/*" {
+                Some((0, *q))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+        .unwrap_or(0.0)
+}
+
+/// Chooses the acceptable candidate with the highest quality, preferring
+/// earlier candidates when qualities are equal.
+fn choose<'a>(accepted: &[(String, f32)], candidates: &[&'a str]) -> Option<&'a str> {
+    let mut chosen = None;
+    let mut chosen_quality = 0.0;
+
+    for candidate in candidates {
+        let q = quality(accepted, candidate);
+
+        if q > chosen_quality {
+            chosen = Some(*candidate);
+            chosen_quality = q;
+        }
+    }
+
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiation_defaults_to_json() {
+        let negotiation = Negotiation::new(None, None);
+
+        assert_eq!(negotiation.format(), OutputFormat::default());
+
+        assert_eq!(
+            negotiation.respond(Output::Json("\"a\"".to_string())),
+            Some(("application/json".to_string(), b"\"a\"".to_vec()))
+        );
+
+        assert_eq!(
+            Negotiation::new(Some("text/html, */*;q=0.8"), None)
+                .respond(Output::Json("1".to_string())),
+            Some(("application/json".to_string(), b"1".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_negotiation_accept() {
+        let negotiation = Negotiation::new(Some("text/html, application/json;q=0.5"), None);
+
+        assert_eq!(
+            negotiation.format(),
+            OutputFormat {
+                raw_strings: true,
+                ..OutputFormat::default()
+            }
+        );
+
+        assert_eq!(
+            negotiation.respond(Output::Text("<b>hi</b>".to_string())),
+            Some((
+                "text/html; charset=utf-8".to_string(),
+                b"<b>hi</b>".to_vec()
+            ))
+        );
+
+        let negotiation = Negotiation::new(Some("application/octet-stream"), None);
+
+        assert_eq!(
+            negotiation.respond(Output::Binary(vec![1, 2])),
+            Some(("application/octet-stream".to_string(), vec![1, 2]))
+        );
+
+        assert_eq!(negotiation.respond(Output::Json("{}".to_string())), None);
+    }
+
+    #[test]
+    fn test_negotiation_declared_output() {
+        let negotiation = Negotiation::new(Some("*/*"), Some("text/csv"));
+
+        assert_eq!(
+            negotiation.respond(Output::Text("a,b".to_string())),
+            Some(("text/csv; charset=utf-8".to_string(), b"a,b".to_vec()))
+        );
+
+        // A client that only accepts JSON still gets JSON
+
+        let negotiation = Negotiation::new(Some("application/json"), Some("text/csv"));
+
+        assert_eq!(negotiation.format(), OutputFormat::default());
+    }
+
+    #[test]
+    fn test_negotiation_binary_encodings() {
+        let negotiation = Negotiation::new(Some("application/cbor"), None);
+
+        assert_eq!(
+            negotiation.format(),
+            OutputFormat {
+                encoding: Encoding::Cbor,
+                ..OutputFormat::default()
+            }
+        );
+
+        assert_eq!(
+            negotiation.respond(Output::Cbor(vec![0x01])),
+            Some(("application/cbor".to_string(), vec![0x01]))
+        );
+
+        assert_eq!(negotiation.respond(Output::Json("1".to_string())), None);
+
+        let negotiation = Negotiation::new(Some("application/x-msgpack"), None);
+
+        assert_eq!(
+            negotiation.respond(Output::MsgPack(vec![0x01])),
+            Some(("application/x-msgpack".to_string(), vec![0x01]))
+        );
+
+        assert_eq!(args_encoding(None), Encoding::Json);
+        assert_eq!(args_encoding(Some("application/CBOR")), Encoding::Cbor);
+        assert_eq!(
+            args_encoding(Some("application/msgpack; charset=binary")),
+            Encoding::MsgPack
+        );
+    }
+}
@@ -0,0 +1,868 @@
+use crate::script_registry::{Named, Script, ScriptId};
+use crate::store::ScriptStore;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LOG_FILE: &str = "scripts.log";
+const SNAPSHOT_FILE: &str = "scripts.snapshot";
+const SNAPSHOT_TEMP_FILE: &str = "scripts.snapshot.tmp";
+
+/// Accesses of a script are recorded at most this often, so that frequently
+/// executed scripts don't cause a write for every execution. Thus, after a
+/// restart, a script may appear to have been accessed up to this long ago.
+pub const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tracks when the access of each script was last recorded, so that stores
+/// record them at most once per `TOUCH_INTERVAL`.
+#[derive(Default)]
+pub struct TouchThrottle {
+    touched: HashMap<ScriptId, SystemTime>,
+}
+
+impl TouchThrottle {
+    /// Determines if an access of a script at `now` should be recorded, given
+    /// when its last access was recorded. If so, it's taken to be recorded.
+    pub fn should_touch(&mut self, id: &ScriptId, now: SystemTime) -> bool {
+        match self.touched.get(id) {
+            Some(touched) if now < *touched + TOUCH_INTERVAL => false,
+
+            _ => {
+                self.touched.insert(id.clone(), now);
+
+                true
+            }
+        }
+    }
+
+    /// Forgets a script, e.g. once it has been removed.
+    pub fn forget(&mut self, id: &ScriptId) {
+        self.touched.remove(id);
+    }
+}
+
+/// A change to the script registry. Each is written to the log as a line of
+/// JSON, and applying them in order reproduces the registry. Times are
+/// milliseconds since the Unix epoch.
+///
+/// Executions of a script are only counted when its access is recorded, so
+/// a few may be lost after a restart.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Record {
+    Store {
+        id: ScriptId,
+        script: Script,
+        accessed: u64,
+        #[serde(default)]
+        created: Option<u64>,
+        #[serde(default)]
+        executions: u64,
+    },
+    Touch {
+        id: ScriptId,
+        accessed: u64,
+        #[serde(default)]
+        executions: u64,
+    },
+    Remove {
+        id: ScriptId,
+    },
+    Name {
+        name: String,
+        named: Named,
+    },
+    #[serde(rename = "remove_name")]
+    RemoveName {
+        name: String,
+    },
+}
+
+impl Record {
+    /// Returns the record that stores a script.
+    pub fn store(id: &ScriptId, persisted: &PersistedScript) -> Self {
+        Record::Store {
+            id: id.clone(),
+            script: persisted.script.clone(),
+            accessed: to_millis(persisted.accessed),
+            created: Some(to_millis(persisted.created)),
+            executions: persisted.executions,
+        }
+    }
+}
+
+/// A script as stores hold it, along with when it was created and last
+/// accessed, and how many times it has been executed.
+#[derive(Clone, Debug)]
+pub struct PersistedScript {
+    pub script: Script,
+    pub created: SystemTime,
+    pub accessed: SystemTime,
+    pub executions: u64,
+}
+
+/// The scripts that have been persisted, along with the names of scripts.
+#[derive(Clone, Debug, Default)]
+pub struct Persisted {
+    pub scripts: HashMap<ScriptId, PersistedScript>,
+    pub names: HashMap<String, Named>,
+}
+
+/// Persists the script registry to a directory, as a write-ahead log of
+/// changes, and a snapshot of the whole registry that's taken once the log
+/// has grown to `snapshot_records`, after which the log starts over.
+///
+/// The log and snapshots are written by a background thread, so that taking a
+/// snapshot doesn't delay requests. Each append waits until its record has
+/// been synced, which holds up the requests of every namespace for as long,
+/// while queued accesses are written along with whichever record follows.
+pub struct Persistence {
+    sender: Option<mpsc::Sender<Pending>>,
+    writer: Option<thread::JoinHandle<()>>,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl Persistence {
+    /// Opens the data directory, creating it if necessary, and loads the
+    /// scripts that it holds, then takes a snapshot, so that the log starts
+    /// out empty. A log that ends with an incomplete record, e.g. because the
+    /// process stopped while writing it, is truncated to its last complete
+    /// record. A log with any other record that cannot be parsed is left as
+    /// it is, and fails to open.
+    pub fn open(dir: &Path, snapshot_records: usize) -> io::Result<(Self, Persisted)> {
+        fs::create_dir_all(dir)?;
+
+        let mut persisted = Persisted::default();
+
+        let snapshot = read_if_exists(&dir.join(SNAPSHOT_FILE))?;
+        let (records, valid) = parse_records(&snapshot);
+
+        // Snapshots are written to a temporary file and then renamed, so they
+        // are never incomplete
+
+        if valid != snapshot.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("script snapshot in {} is corrupt", dir.display()),
+            ));
+        }
+
+        for record in records {
+            apply(&mut persisted, record);
+        }
+
+        let log_path = dir.join(LOG_FILE);
+        let log_data = read_if_exists(&log_path)?;
+        let (records, valid) = parse_records(&log_data);
+        let count = records.len();
+
+        for record in records {
+            apply(&mut persisted, record);
+        }
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+
+        // Only the last record can be incomplete, and it has no newline, so
+        // anything else that cannot be parsed is corruption, which would lose
+        // the records after it if it were discarded
+
+        if log_data[valid..].contains(&b'\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("script log in {} is corrupt", dir.display()),
+            ));
+        }
+
+        if valid != log_data.len() {
+            eprintln!(
+                "discarding {} bytes after the last complete record of {}",
+                log_data.len() - valid,
+                log_path.display()
+            );
+
+            log.set_len(valid as u64)?;
+            log.sync_all()?;
+        }
+
+        let mut writer = Writer {
+            dir: dir.to_path_buf(),
+            log,
+            records: count,
+            snapshot_records,
+            persisted: persisted.clone(),
+        };
+
+        writer.snapshot()?;
+
+        Ok((Self::start(writer)?, persisted))
+    }
+
+    /// Starts the background thread that writes the log and snapshots.
+    fn start(writer: Writer) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let failure = Arc::new(Mutex::new(None));
+        let writer_failure = failure.clone();
+
+        let writer = thread::Builder::new()
+            .name("jsaas-persistence".to_string())
+            .spawn(move || writer.run(&receiver, &writer_failure))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+            failure,
+        })
+    }
+
+    /// Appends a record to the log, returning once it has been written and
+    /// synced to disk. Once writing fails, every later record is refused with
+    /// the error, since the log can no longer reproduce the registry.
+    pub fn append(&mut self, record: Record) -> io::Result<()> {
+        let (written, receiver) = mpsc::channel();
+
+        self.send(record, Some(written))?;
+
+        match receiver.recv() {
+            Ok(Ok(())) => Ok(()),
+
+            Ok(Err(e)) => Err(io::Error::new(io::ErrorKind::Other, e)),
+
+            Err(_) => Err(writer_stopped()),
+        }
+    }
+
+    /// Queues a record to be appended to the log without waiting for it to
+    /// be written, so it may be lost if the machine stops first. This suits
+    /// records that are only kept approximately, such as accesses.
+    pub fn queue(&mut self, record: Record) -> io::Result<()> {
+        self.send(record, None)
+    }
+
+    fn send(
+        &mut self,
+        record: Record,
+        written: Option<mpsc::Sender<Result<(), String>>>,
+    ) -> io::Result<()> {
+        if let Some(ref failure) = *self.failure.lock().unwrap() {
+            return Err(io::Error::new(io::ErrorKind::Other, failure.clone()));
+        }
+
+        let sent = match self.sender {
+            Some(ref sender) => sender.send(Pending { record, written }).is_ok(),
+            None => false,
+        };
+
+        if sent {
+            Ok(())
+        } else {
+            Err(writer_stopped())
+        }
+    }
+}
+
+impl Drop for Persistence {
+    /// Waits for the records that have been appended to be written.
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// A record that's waiting to be written, along with where to report the
+/// outcome of writing it, if its append is waiting for that.
+struct Pending {
+    record: Record,
+    written: Option<mpsc::Sender<Result<(), String>>>,
+}
+
+/// Writes the log and snapshots on the background thread. It keeps its own
+/// copy of the persisted registry, which it takes snapshots of.
+struct Writer {
+    dir: PathBuf,
+    log: File,
+    records: usize,
+    snapshot_records: usize,
+    persisted: Persisted,
+}
+
+impl Writer {
+    /// Writes records as they're received, until every sender has gone, or
+    /// writing fails. Records that were queued meanwhile are written with a
+    /// single sync, and the outcome is reported to each waiting append once
+    /// its record has been synced, or has failed to be.
+    fn run(mut self, receiver: &mpsc::Receiver<Pending>, failure: &Mutex<Option<String>>) {
+        while let Ok(pending) = receiver.recv() {
+            let mut records = vec![pending.record];
+            let mut waiting = vec![pending.written];
+
+            for pending in receiver.try_iter() {
+                records.push(pending.record);
+                waiting.push(pending.written);
+            }
+
+            let result = self.write(records);
+            let outcome = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+
+            for written in waiting.into_iter().flatten() {
+                let _ = written.send(outcome.clone());
+            }
+
+            // The records are durable once they're in the log, so a snapshot
+            // that fails only fails the records after them
+
+            let result = result.and_then(|()| {
+                if self.records >= self.snapshot_records {
+                    self.snapshot()
+                } else {
+                    Ok(())
+                }
+            });
+
+            if let Err(e) = result {
+                eprintln!("cannot persist script registry: {}", e);
+
+                *failure.lock().unwrap() = Some(e.to_string());
+
+                return;
+            }
+        }
+    }
+
+    /// Appends records to the log with a single sync.
+    fn write(&mut self, records: Vec<Record>) -> io::Result<()> {
+        let mut lines = vec![];
+
+        for record in &records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+
+        self.log.write_all(&lines)?;
+        self.log.sync_data()?;
+
+        self.records += records.len();
+
+        for record in records {
+            apply(&mut self.persisted, record);
+        }
+
+        Ok(())
+    }
+
+    /// Writes a snapshot of the registry, and then empties the log. If the
+    /// process stops before the log is emptied, its records are applied to
+    /// the snapshot again when loading, which has no further effect.
+    fn snapshot(&mut self) -> io::Result<()> {
+        let temp_path = self.dir.join(SNAPSHOT_TEMP_FILE);
+        let mut temp = BufWriter::new(File::create(&temp_path)?);
+
+        let scripts = self
+            .persisted
+            .scripts
+            .iter()
+            .map(|(id, persisted)| Record::Store {
+                id: id.clone(),
+                script: persisted.script.clone(),
+                accessed: to_millis(persisted.accessed),
+                created: Some(to_millis(persisted.created)),
+                executions: persisted.executions,
+            });
+
+        let names = self
+            .persisted
+            .names
+            .iter()
+            .map(|(name, named)| Record::Name {
+                name: name.clone(),
+                named: named.clone(),
+            });
+
+        for record in scripts.chain(names) {
+            serde_json::to_writer(&mut temp, &record)?;
+            temp.write_all(b"\n")?;
+        }
+
+        temp.into_inner()?.sync_all()?;
+
+        fs::rename(&temp_path, self.dir.join(SNAPSHOT_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.records = 0;
+
+        Ok(())
+    }
+}
+
+/// Persists every change to a directory. The log cannot be queried, so the
+/// store also keeps what it has persisted in memory. This is the store
+/// that's used by default when there is a data directory.
+pub struct FileStore {
+    persistence: Persistence,
+    persisted: Persisted,
+    touched: TouchThrottle,
+}
+
+impl FileStore {
+    /// Opens the data directory and loads the scripts that it holds.
+    pub fn open(dir: &Path, snapshot_records: usize) -> io::Result<Self> {
+        let (persistence, persisted) = Persistence::open(dir, snapshot_records)?;
+
+        Ok(Self {
+            persistence,
+            persisted,
+            touched: TouchThrottle::default(),
+        })
+    }
+}
+
+impl ScriptStore for FileStore {
+    fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        Ok(self.persisted.scripts.get(id).cloned())
+    }
+
+    fn store(&mut self, id: ScriptId, script: PersistedScript) -> io::Result<()> {
+        self.persistence.append(Record::store(&id, &script))?;
+        self.persisted.scripts.insert(id, script);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        if !self.persisted.scripts.contains_key(id) {
+            return Ok(None);
+        }
+
+        self.persistence.append(Record::Remove { id: id.clone() })?;
+        self.touched.forget(id);
+
+        Ok(self.persisted.scripts.remove(id))
+    }
+
+    fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+        Ok(self
+            .persisted
+            .scripts
+            .iter()
+            .map(|(id, script)| (id.clone(), script.clone()))
+            .collect())
+    }
+
+    fn touch(&mut self, id: &ScriptId, accessed: SystemTime, executions: u64) -> io::Result<()> {
+        let script = match self.persisted.scripts.get_mut(id) {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+
+        if self.touched.should_touch(id, accessed) {
+            self.persistence.queue(Record::Touch {
+                id: id.clone(),
+                accessed: to_millis(accessed),
+                executions,
+            })?;
+        }
+
+        script.accessed = accessed;
+        script.executions = executions;
+
+        Ok(())
+    }
+
+    fn names(&self) -> io::Result<Vec<(String, Named)>> {
+        Ok(self
+            .persisted
+            .names
+            .iter()
+            .map(|(name, named)| (name.clone(), named.clone()))
+            .collect())
+    }
+
+    fn store_name(&mut self, name: String, named: Named) -> io::Result<()> {
+        self.persistence.append(Record::Name {
+            name: name.clone(),
+            named: named.clone(),
+        })?;
+
+        self.persisted.names.insert(name, named);
+
+        Ok(())
+    }
+
+    fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>> {
+        if !self.persisted.names.contains_key(name) {
+            return Ok(None);
+        }
+
+        self.persistence.append(Record::RemoveName {
+            name: name.to_string(),
+        })?;
+
+        Ok(self.persisted.names.remove(name))
+    }
+}
+
+pub fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+        .unwrap_or(0)
+}
+
+pub fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+fn writer_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the log writer has stopped")
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        result => result,
+    }
+}
+
+/// Parses complete records, which are terminated by a newline, stopping at
+/// the first that's incomplete or invalid. Returns the records and the
+/// number of bytes that they occupy.
+fn parse_records(data: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = vec![];
+    let mut position = 0;
+
+    while let Some(end) = data[position..].iter().position(|b| *b == b'\n') {
+        match serde_json::from_slice(&data[position..position + end]) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+
+        position += end + 1;
+    }
+
+    (records, position)
+}
+
+fn apply(persisted: &mut Persisted, record: Record) {
+    match record {
+        Record::Store {
+            id,
+            script,
+            accessed,
+            created,
+            executions,
+        } => {
+            persisted.scripts.insert(
+                id,
+                PersistedScript {
+                    script,
+                    created: from_millis(created.unwrap_or(accessed)),
+                    accessed: from_millis(accessed),
+                    executions,
+                },
+            );
+        }
+
+        Record::Touch {
+            id,
+            accessed,
+            executions,
+        } => {
+            if let Some(entry) = persisted.scripts.get_mut(&id) {
+                entry.accessed = from_millis(accessed);
+                entry.executions = executions;
+            }
+        }
+
+        Record::Remove { id } => {
+            persisted.scripts.remove(&id);
+        }
+
+        Record::Name { name, named } => {
+            persisted.names.insert(name, named);
+        }
+
+        Record::RemoveName { name } => {
+            persisted.names.remove(&name);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Creates an empty directory for a test's data.
+    pub fn data_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jsaas-test-{}", ScriptId::random()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn store(id: ScriptId, code: &str, accessed: u64) -> Record {
+        Record::Store {
+            id,
+            script: Script::new(code.to_string()),
+            accessed,
+            created: None,
+            executions: 0,
+        }
+    }
+
+    #[test]
+    fn test_persistence_log() {
+        let dir = data_dir();
+        let (a, b) = (ScriptId::random(), ScriptId::random());
+
+        {
+            let (mut persistence, persisted) = Persistence::open(&dir, 100).unwrap();
+
+            assert!(persisted.scripts.is_empty());
+
+            persistence
+                .append(store(a.clone(), "function() {}", 1000))
+                .unwrap();
+            persistence
+                .append(store(b.clone(), "function() {}", 2000))
+                .unwrap();
+            persistence
+                .append(Record::Touch {
+                    id: a.clone(),
+                    accessed: 3000,
+                    executions: 7,
+                })
+                .unwrap();
+            persistence
+                .append(Record::Remove { id: b.clone() })
+                .unwrap();
+        }
+
+        let (_, persisted) = Persistence::open(&dir, 100).unwrap();
+
+        assert_eq!(persisted.scripts.len(), 1);
+        assert_eq!(persisted.scripts[&a].created, from_millis(1000));
+        assert_eq!(persisted.scripts[&a].accessed, from_millis(3000));
+        assert_eq!(persisted.scripts[&a].executions, 7);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_recovers_truncated_log() {
+        let dir = data_dir();
+        let (a, b) = (ScriptId::random(), ScriptId::random());
+
+        {
+            let (mut persistence, _) = Persistence::open(&dir, 100).unwrap();
+
+            persistence
+                .append(store(a.clone(), "function() {}", 1000))
+                .unwrap();
+        }
+
+        let complete = fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+
+        log.write_all(b"{\"op\":\"store\",\"id\":\"").unwrap();
+
+        assert!(complete > 0);
+
+        {
+            let (mut persistence, persisted) = Persistence::open(&dir, 100).unwrap();
+
+            assert_eq!(persisted.scripts.keys().collect::<Vec<_>>(), vec![&a]);
+            assert_eq!(fs::metadata(dir.join(LOG_FILE)).unwrap().len(), 0);
+
+            persistence
+                .append(store(b.clone(), "function() {}", 2000))
+                .unwrap();
+        }
+
+        let (_, persisted) = Persistence::open(&dir, 100).unwrap();
+
+        assert_eq!(persisted.scripts.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_rejects_corrupt_log() {
+        let dir = data_dir();
+        let (a, b) = (ScriptId::random(), ScriptId::random());
+
+        {
+            let (mut persistence, _) = Persistence::open(&dir, 100).unwrap();
+
+            persistence
+                .append(store(a.clone(), "function() {}", 1000))
+                .unwrap();
+        }
+
+        // A complete record that cannot be parsed is followed by one that can
+
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+
+        log.write_all(b"{\"op\":\"unknown\"}\n").unwrap();
+        serde_json::to_writer(&mut log, &store(b.clone(), "function() {}", 2000)).unwrap();
+        log.write_all(b"\n").unwrap();
+
+        let length = fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+
+        let error = Persistence::open(&dir, 100).err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(dir.join(LOG_FILE)).unwrap().len(), length);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_snapshot() {
+        let dir = data_dir();
+        let (a, b, c) = (ScriptId::random(), ScriptId::random(), ScriptId::random());
+
+        {
+            let (mut persistence, _) = Persistence::open(&dir, 2).unwrap();
+
+            persistence
+                .append(store(a.clone(), "function() {}", 1000))
+                .unwrap();
+            persistence
+                .append(store(b.clone(), "function() {}", 1000))
+                .unwrap();
+        }
+
+        {
+            let (mut persistence, persisted) = Persistence::open(&dir, 2).unwrap();
+
+            assert_eq!(persisted.scripts.len(), 2);
+
+            persistence
+                .append(Record::Remove { id: b.clone() })
+                .unwrap();
+            persistence
+                .append(Record::Name {
+                    name: "adder".to_string(),
+                    named: Named {
+                        versions: vec![a.clone()],
+                        ..Named::default()
+                    },
+                })
+                .unwrap();
+            persistence
+                .append(store(c.clone(), "function() {}", 1000))
+                .unwrap();
+        }
+
+        // The first two records of the log were replaced by a snapshot, and
+        // the third was appended to the log after it
+
+        let log = fs::read(dir.join(LOG_FILE)).unwrap();
+        let snapshot = fs::read(dir.join(SNAPSHOT_FILE)).unwrap();
+
+        assert!(parse_records(&log).0.len() <= 1);
+        assert!(parse_records(&snapshot).0.len() >= 2);
+
+        let (_, persisted) = Persistence::open(&dir, 2).unwrap();
+
+        let mut ids = persisted.scripts.keys().cloned().collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.to_string());
+
+        let mut expected = vec![a.clone(), c.clone()];
+        expected.sort_by_key(|id| id.to_string());
+
+        assert_eq!(ids, expected);
+        assert_eq!(persisted.names["adder"].versions, vec![a.clone()]);
+        assert_eq!(persisted.scripts[&a].accessed, from_millis(1000));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_failure() {
+        let dir = data_dir();
+
+        let (mut persistence, _) = Persistence::open(&dir, 100).unwrap();
+
+        *persistence.failure.lock().unwrap() = Some("disk full".to_string());
+
+        let error = persistence
+            .append(Record::Remove {
+                id: ScriptId::random(),
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            crate::store::StoreError::wrap(error).to_string(),
+            "cannot persist script registry: disk full"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_append_waits_for_write() {
+        let dir = data_dir();
+        let log_path = dir.join(LOG_FILE);
+
+        File::create(&log_path).unwrap();
+
+        // The log is opened for reading only, so writing to it fails
+
+        let writer = Writer {
+            dir: dir.clone(),
+            log: File::open(&log_path).unwrap(),
+            records: 0,
+            snapshot_records: 100,
+            persisted: Persisted::default(),
+        };
+
+        let mut persistence = Persistence::start(writer).unwrap();
+
+        assert!(persistence
+            .append(store(ScriptId::random(), "function() {}", 1000))
+            .is_err());
+
+        assert!(persistence
+            .append(Record::Remove {
+                id: ScriptId::random(),
+            })
+            .is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence_touch_throttle() {
+        let id = ScriptId::random();
+        let now = SystemTime::now();
+
+        let mut touched = TouchThrottle::default();
+
+        assert!(touched.should_touch(&id, now));
+        assert!(!touched.should_touch(&id, now + Duration::from_secs(1)));
+        assert!(touched.should_touch(&id, now + TOUCH_INTERVAL));
+
+        touched.forget(&id);
+
+        assert!(touched.should_touch(&id, now + TOUCH_INTERVAL + Duration::from_secs(1)));
+    }
+}
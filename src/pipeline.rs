@@ -0,0 +1,273 @@
+use crate::duktape::Limits;
+use crate::script_registry::ScriptId;
+use std::collections::HashMap;
+use std::io;
+use std::time::Instant;
+
+/// A single step of a pipeline. `adapter`, if specified, is a function
+/// that converts the previous stage's result into the JSON-encoded array
+/// of arguments for this stage. Without one, the previous result is
+/// supplied as the only argument.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Stage {
+    pub(crate) script: ScriptId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) adapter: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Pipeline {
+    pub(crate) stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Ensures that the pipeline can be executed, i.e. that it has at
+    /// least one stage and that the first stage has no adapter, given
+    /// it receives the arguments of the request.
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        match self.stages.first() {
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pipeline must have at least one stage",
+            )),
+
+            Some(stage) if stage.adapter.is_some() => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the first stage of a pipeline cannot have an adapter",
+            )),
+
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Describes which stage of a pipeline failed, and the arguments it was
+/// executed with.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct StageFailure {
+    pub(crate) stage: usize,
+    pub(crate) script: String,
+    pub(crate) input: serde_json::Value,
+    pub(crate) error: String,
+}
+
+/// Defines a local registry for named pipelines.
+pub(crate) struct PipelineRegistry {
+    pipelines: HashMap<String, Pipeline>,
+}
+
+impl PipelineRegistry {
+    pub(crate) fn new() -> Self {
+        Self {
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Pipeline> {
+        self.pipelines.get(name)
+    }
+
+    /// Removes a pipeline given its name, returning whether it existed.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        self.pipelines.remove(name).is_some()
+    }
+
+    /// Stores a pipeline, replacing any existing one with the same name.
+    /// Returns whether the pipeline was newly created.
+    pub(crate) fn store(&mut self, name: String, pipeline: Pipeline) -> bool {
+        self.pipelines.insert(name, pipeline).is_none()
+    }
+}
+
+/// Executes the stages of a pipeline in sequence, feeding each stage's
+/// result into the next. `stages` holds each stage's script id, its code,
+/// and its optional adapter.
+///
+/// `eval` yields `None` for a result of `undefined`, which is fed to the
+/// next stage, or its adapter, as no arguments. So is an adapter's result of
+/// `undefined`. The pipeline's result is `None` if its last stage's is.
+///
+/// The time in `limits` bounds the execution of the pipeline as a whole;
+/// each call to `eval` is given the time that remains.
+pub(crate) fn run<F>(
+    stages: &[(ScriptId, String, Option<String>)],
+    args: String,
+    limits: Limits,
+    mut eval: F,
+) -> Result<Option<String>, StageFailure>
+where
+    F: FnMut(&str, &str, Limits) -> io::Result<Option<String>>,
+{
+    let start = Instant::now();
+    let mut input = args;
+    let mut result = None;
+
+    for (stage, (id, code, adapter)) in stages.iter().enumerate() {
+        let remaining = || Limits {
+            time: limits.time.checked_sub(start.elapsed()).unwrap_or_default(),
+            ..limits
+        };
+
+        let failure = |input: &str, e: io::Error| StageFailure {
+            stage,
+            script: id.to_string(),
+            input: serde_json::from_str(input)
+                .unwrap_or_else(|_| serde_json::Value::String(input.to_string())),
+            error: e.to_string(),
+        };
+
+        if stage > 0 {
+            let previous = result
+                .take()
+                .map_or_else(|| "[]".to_string(), |json| format!("[{}]", json));
+
+            input = match adapter {
+                Some(adapter) => eval(adapter, &previous, remaining())
+                    .map_err(|e| failure(&previous, e))?
+                    .unwrap_or_else(|| "[]".to_string()),
+
+                None => previous,
+            };
+        }
+
+        result = eval(code, &input, remaining()).map_err(|e| failure(&input, e))?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duktape;
+    use std::time::Duration;
+
+    fn stages(definitions: Vec<(&str, Option<&str>)>) -> Vec<(ScriptId, String, Option<String>)> {
+        definitions
+            .into_iter()
+            .map(|(code, adapter)| {
+                (
+                    ScriptId::random(),
+                    code.to_string(),
+                    adapter.map(String::from),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pipeline_validate() {
+        let script = ScriptId::random();
+
+        assert!(Pipeline { stages: vec![] }.validate().is_err());
+
+        assert!(Pipeline {
+            stages: vec![Stage {
+                script: script.clone(),
+                adapter: Some("function(v) { return [v]; }".to_string())
+            }]
+        }
+        .validate()
+        .is_err());
+
+        assert!(Pipeline {
+            stages: vec![
+                Stage {
+                    script: script.clone(),
+                    adapter: None
+                },
+                Stage {
+                    script: script.clone(),
+                    adapter: Some("function(v) { return [v]; }".to_string())
+                }
+            ]
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_pipeline_run_stages_and_adapters() {
+        let mut ctx = duktape::Context::new().unwrap();
+
+        let stages = stages(vec![
+            ("function(a, b) { return { sum: a + b }; }", None),
+            (
+                "function(n, m) { return n * m; }",
+                Some("function(v) { return [v.sum, 10]; }"),
+            ),
+            ("function(n) { return 'total: ' + n; }", None),
+        ]);
+
+        let result = run(
+            &stages,
+            "[2, 3]".to_string(),
+            Duration::from_millis(5000).into(),
+            |code, args, limits| ctx.evaluate_with(code, args, limits, None),
+        );
+
+        assert_eq!(result, Ok(Some("\"total: 50\"".to_string())));
+    }
+
+    #[test]
+    fn test_pipeline_run_undefined_results() {
+        let mut ctx = duktape::Context::new().unwrap();
+
+        // A result of undefined is fed to the next stage as no arguments,
+        // as is an adapter's
+
+        let stages = stages(vec![
+            ("function() {}", None),
+            ("function() { return arguments.length; }", None),
+            (
+                "function() { return arguments.length; }",
+                Some("function(n) { if (n !== 0) { throw new Error('got ' + n); } }"),
+            ),
+        ]);
+
+        let result = run(
+            &stages,
+            "[]".to_string(),
+            Duration::from_millis(5000).into(),
+            |code, args, limits| ctx.evaluate_with(code, args, limits, None),
+        );
+
+        assert_eq!(result, Ok(Some("0".to_string())));
+
+        let result = run(
+            &stages[..1],
+            "[]".to_string(),
+            Duration::from_millis(5000).into(),
+            |code, args, limits| ctx.evaluate_with(code, args, limits, None),
+        );
+
+        assert_eq!(result, Ok(None));
+    }
+
+    #[test]
+    fn test_pipeline_run_reports_failed_stage() {
+        let mut ctx = duktape::Context::new().unwrap();
+
+        let stages = stages(vec![
+            ("function(a) { return { value: a }; }", None),
+            ("function(o) { throw new Error('bad ' + o.value); }", None),
+        ]);
+
+        let result = run(
+            &stages,
+            "[7]".to_string(),
+            Duration::from_millis(5000).into(),
+            |code, args, limits| ctx.evaluate_with(code, args, limits, None),
+        );
+
+        assert_eq!(
+            result,
+            Err(StageFailure {
+                stage: 1,
+                script: stages[1].0.to_string(),
+                input: serde_json::from_str("[{\"value\":7}]").unwrap(),
+                error: "Error: bad 7".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use crate::script_registry::{Named, Script, ScriptId};
+use futures::{Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, HeaderMap, Method, Request, Uri};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+/// The path, within a namespace, that peers send changes to.
+pub const CHANGES_PATH: &str = "/replication/changes";
+
+/// The path, within a namespace, that peers send digests to when they
+/// resync.
+pub const SYNC_PATH: &str = "/replication/sync";
+
+/// The header that carries the secret that's shared by peers, which the
+/// replication routes require.
+const SECRET_HEADER: &str = "X-Jsaas-Replication-Secret";
+
+/// Accesses of a script are only compared to this many milliseconds, given
+/// that replicas derive their times from a monotonic clock.
+const ACCESS_RESOLUTION: u64 = 1000;
+
+/// Removals are remembered for this many milliseconds. A replica that's cut
+/// off from its peers for longer may bring back scripts that were removed in
+/// the meantime.
+const TOMBSTONE_RETENTION: u64 = 24 * 60 * 60 * 1000;
+
+/// The version of a script or name in a replica of a registry: when it was
+/// last defined or removed, when a script was last accessed, and when it was
+/// removed, if it was. Times are milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Version {
+    pub modified: u64,
+    #[serde(default)]
+    pub accessed: u64,
+    #[serde(default)]
+    pub removed: Option<u64>,
+}
+
+impl Version {
+    /// Determines if this version replaces another replica's version of the
+    /// same script or name, if it has one. The latest definition or removal
+    /// wins. A script that expired, and thus wasn't modified, only remains
+    /// removed if it wasn't used later by another replica, and otherwise
+    /// the latest access wins.
+    pub fn supersedes(&self, other: Option<&Version>) -> bool {
+        let other = match other {
+            Some(other) => other,
+            None => return true,
+        };
+
+        if self.modified != other.modified {
+            return self.modified > other.modified;
+        }
+
+        let later = |a: u64, b: u64| a > b + ACCESS_RESOLUTION;
+
+        match (self.removed, other.removed) {
+            (Some(_), None) => !later(other.accessed, self.accessed),
+            (None, _) => later(self.accessed, other.accessed),
+            (Some(_), Some(_)) => false,
+        }
+    }
+}
+
+/// A change to a registry that's replicated to peers, which apply it if
+/// its version supersedes theirs. Times are milliseconds since the Unix
+/// epoch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    Store {
+        id: ScriptId,
+        script: Script,
+        created: u64,
+        version: Version,
+    },
+    Touch {
+        id: ScriptId,
+        accessed: u64,
+    },
+    Remove {
+        id: ScriptId,
+        version: Version,
+    },
+    Name {
+        name: String,
+        named: Named,
+        version: Version,
+    },
+    RemoveName {
+        name: String,
+        version: Version,
+    },
+}
+
+/// The versions of the scripts and names in a replica, including those
+/// that were removed recently. Peers compare it with their own versions to
+/// determine what the replica is missing.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Digest {
+    pub scripts: HashMap<ScriptId, Version>,
+    pub names: HashMap<String, Version>,
+}
+
+/// Keeps track of the versions of a registry's scripts and names, and the
+/// changes that have yet to be sent to peers.
+#[derive(Debug, Default)]
+pub struct Replica {
+    pub scripts: HashMap<ScriptId, Version>,
+    pub names: HashMap<String, Version>,
+    pub outbox: Vec<Change>,
+}
+
+impl Replica {
+    /// Forgets the removals that are older than the retention time.
+    pub fn prune(&mut self, now: u64) {
+        let retained = |version: &Version| {
+            version
+                .removed
+                .map(|removed| removed + TOMBSTONE_RETENTION > now)
+                .unwrap_or(true)
+        };
+
+        self.scripts.retain(|_, version| retained(version));
+        self.names.retain(|_, version| retained(version));
+    }
+}
+
+/// Sends changes to peers, and asks them for the changes that were missed.
+/// Peers are the base URLs of other instances, e.g. `http://10.0.0.2:9412`,
+/// and they share a secret, which authorizes requests between them.
+#[derive(Clone)]
+pub struct Replicator {
+    client: Client<HttpConnector>,
+    peers: Arc<Vec<Uri>>,
+    secret: Arc<Option<String>>,
+}
+
+impl Replicator {
+    pub fn new(peers: Vec<Uri>, secret: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            peers: Arc::new(peers),
+            secret: Arc::new(secret),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
+    /// Determines if a request to a replication route comes from a peer,
+    /// i.e. carries the secret. Without a secret, no request does.
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let provided = headers.get(SECRET_HEADER).map(|v| v.as_bytes());
+
+        match (self.secret.as_ref(), provided) {
+            (Some(secret), Some(provided)) => constant_time_eq(secret.as_bytes(), provided),
+            _ => false,
+        }
+    }
+
+    /// Sends the changes that were made in a namespace, given its prefix, to
+    /// every peer. Failures are reported, and the changes reach the peer
+    /// when it next resyncs.
+    pub fn send(&self, prefix: &str, changes: &[Change]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let body = match serde_json::to_vec(changes) {
+            Ok(body) => body,
+
+            Err(e) => {
+                eprintln!("cannot replicate changes: {}", e);
+                return;
+            }
+        };
+
+        for peer in self.peers.iter() {
+            let peer = peer.clone();
+            let request = post(
+                &self.client,
+                &peer,
+                &self.secret,
+                prefix,
+                CHANGES_PATH,
+                body.clone(),
+            );
+
+            tokio::spawn(
+                request
+                    .map(|_| ())
+                    .map_err(move |e| eprintln!("cannot replicate changes to {}: {}", peer, e)),
+            );
+        }
+    }
+
+    /// Sends the digest of a namespace, given its prefix, to every peer, and
+    /// passes the changes that each responds with to `received`.
+    pub fn sync<F>(&self, prefix: &str, digest: &Digest, received: F)
+    where
+        F: Fn(Vec<Change>) + Clone + Send + 'static,
+    {
+        let body = match serde_json::to_vec(digest) {
+            Ok(body) => body,
+
+            Err(e) => {
+                eprintln!("cannot resync: {}", e);
+                return;
+            }
+        };
+
+        for peer in self.peers.iter() {
+            let peer = peer.clone();
+            let received = received.clone();
+
+            let request = post(
+                &self.client,
+                &peer,
+                &self.secret,
+                prefix,
+                SYNC_PATH,
+                body.clone(),
+            )
+            .and_then(|body| {
+                serde_json::from_slice::<Vec<Change>>(&body)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .map(received)
+            .map_err(move |e| eprintln!("cannot resync with {}: {}", peer, e));
+
+            tokio::spawn(request);
+        }
+    }
+}
+
+/// Posts a JSON body to a path of a peer, resolving to the body of its
+/// response if it succeeded.
+fn post(
+    client: &Client<HttpConnector>,
+    peer: &Uri,
+    secret: &Option<String>,
+    prefix: &str,
+    path: &str,
+    body: Vec<u8>,
+) -> impl Future<Item = Vec<u8>, Error = io::Error> {
+    let uri = format!(
+        "{}{}{}",
+        peer.to_string().trim_end_matches('/'),
+        prefix,
+        path
+    );
+
+    let mut request = Request::builder();
+
+    request
+        .method(Method::POST)
+        .uri(uri)
+        .header("Content-Type", "application/json");
+
+    if let Some(ref secret) = *secret {
+        request.header(SECRET_HEADER, secret.as_str());
+    }
+
+    let request = request
+        .body(Body::from(body))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+
+    futures::done(request)
+        .and_then({
+            let client = client.clone();
+
+            move |request| {
+                client
+                    .request(request)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
+        })
+        .and_then(|response| {
+            let status = response.status();
+
+            response
+                .into_body()
+                .concat2()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                .and_then(move |body| {
+                    if status.is_success() {
+                        Ok(body.to_vec())
+                    } else {
+                        Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!(
+                                "status {}: {}",
+                                status,
+                                String::from_utf8_lossy(&body).trim()
+                            ),
+                        ))
+                    }
+                })
+        })
+}
+
+/// Compares secrets in time that only depends on their lengths, so that
+/// timing responses doesn't reveal how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replication_is_authorized() {
+        let replicator = Replicator::new(vec![], Some("s3cret".to_string()));
+
+        let mut headers = HeaderMap::new();
+        assert!(!replicator.is_authorized(&headers));
+
+        headers.insert(SECRET_HEADER, "guess".parse().unwrap());
+        assert!(!replicator.is_authorized(&headers));
+
+        headers.insert(SECRET_HEADER, "s3creT".parse().unwrap());
+        assert!(!replicator.is_authorized(&headers));
+
+        headers.insert(SECRET_HEADER, "s3cret".parse().unwrap());
+        assert!(replicator.is_authorized(&headers));
+
+        let replicator = Replicator::new(vec![], None);
+        assert!(!replicator.is_authorized(&headers));
+    }
+
+    #[test]
+    fn test_replication_supersedes() {
+        let version = |modified, accessed, removed| Version {
+            modified,
+            accessed,
+            removed,
+        };
+
+        let stored = version(1000, 5000, None);
+
+        assert!(stored.supersedes(None));
+
+        // The latest definition or removal wins
+
+        assert!(version(2000, 0, None).supersedes(Some(&stored)));
+        assert!(version(2000, 0, Some(2000)).supersedes(Some(&stored)));
+        assert!(!version(500, 9000, None).supersedes(Some(&stored)));
+        assert!(!stored.supersedes(Some(&version(2000, 0, Some(2000)))));
+
+        // Expiring a script doesn't modify it, so it's removed unless it was
+        // accessed later
+
+        assert!(version(1000, 5000, Some(9000)).supersedes(Some(&stored)));
+        assert!(version(1000, 4500, Some(9000)).supersedes(Some(&stored)));
+        assert!(!version(1000, 3000, Some(9000)).supersedes(Some(&stored)));
+        assert!(version(1000, 7000, None).supersedes(Some(&version(1000, 5000, Some(9000)))));
+        assert!(!stored.supersedes(Some(&version(1000, 5000, Some(9000)))));
+
+        // Otherwise, only later accesses supersede
+
+        assert!(version(1000, 7000, None).supersedes(Some(&stored)));
+        assert!(!version(1000, 5500, None).supersedes(Some(&stored)));
+        assert!(!stored.supersedes(Some(&stored)));
+        assert!(!version(1000, 0, Some(9000)).supersedes(Some(&version(1000, 0, Some(8000)))));
+    }
+
+    #[test]
+    fn test_replication_prune() {
+        let mut replica = Replica::default();
+
+        let removed = |removed| Version {
+            modified: 0,
+            accessed: 0,
+            removed,
+        };
+
+        replica.names.insert("a".to_string(), removed(None));
+        replica.names.insert("b".to_string(), removed(Some(1000)));
+        replica
+            .names
+            .insert("c".to_string(), removed(Some(TOMBSTONE_RETENTION)));
+
+        replica.prune(TOMBSTONE_RETENTION + 2000);
+
+        let mut names = replica.names.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+
+        assert_eq!(names, vec!["a", "c"]);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::duktape::Limits;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+struct CacheEntry {
+    value: String,
+    inserted: Instant,
+    used: u64,
+}
+
+/// Counters that describe the effectiveness of the cache.
+pub(crate) struct CacheStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+    pub(crate) entries: usize,
+    pub(crate) bytes: usize,
+}
+
+/// Caches the results of deterministic scripts. Entries expire once
+/// `ttl` has elapsed since they were inserted, and the least recently
+/// used entries are evicted to keep the cache within `max_bytes`.
+pub(crate) struct ResultCache {
+    ttl: Duration,
+    max_bytes: usize,
+    bytes: usize,
+    clock: u64,
+    entries: HashMap<String, CacheEntry>,
+    recency: BTreeMap<u64, String>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+/// Determines the cache key for executing a script with the provided
+/// arguments. The arguments are canonicalized (object keys sorted, no
+/// insignificant whitespace) so that equivalent arguments share an entry.
+///
+/// The limits of the execution are part of the key, as a result that was
+/// computed within generous limits may not have been within stricter ones.
+///
+/// Returns `None` if the arguments are not valid JSON.
+pub(crate) fn key(script_hash: &str, limits: &Limits, args: &str) -> Option<String> {
+    let memory = limits
+        .memory
+        .map(|memory| memory.to_string())
+        .unwrap_or_default();
+
+    serde_json::from_str::<serde_json::Value>(args)
+        .ok()
+        .and_then(|args| serde_json::to_string(&args).ok())
+        .map(|args| {
+            format!(
+                "{}:{}:{}:{}",
+                script_hash,
+                limits.time.as_millis(),
+                memory,
+                args
+            )
+        })
+}
+
+impl ResultCache {
+    pub(crate) fn new(ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            ttl,
+            max_bytes,
+            bytes: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Gets a cached result, marking it as recently used if found.
+    pub(crate) fn get(&mut self, key: &str) -> Option<String> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.inserted.elapsed() > self.ttl,
+            None => {
+                self.misses += 1;
+
+                return None;
+            }
+        };
+
+        if expired {
+            self.remove(key);
+            self.misses += 1;
+
+            return None;
+        }
+
+        self.clock += 1;
+        self.hits += 1;
+
+        let clock = self.clock;
+
+        let entry = self.entries.get_mut(key)?;
+        let previous = entry.used;
+        entry.used = clock;
+
+        let value = entry.value.clone();
+
+        if let Some(k) = self.recency.remove(&previous) {
+            self.recency.insert(clock, k);
+        }
+
+        Some(value)
+    }
+
+    /// Caches a result, evicting the least recently used entries if the
+    /// cache would otherwise exceed its size.
+    pub(crate) fn insert(&mut self, key: String, value: String) {
+        let size = key.len() + value.len();
+
+        if size > self.max_bytes {
+            return;
+        }
+
+        self.remove(&key);
+
+        while self.bytes + size > self.max_bytes {
+            let oldest = self.recency.keys().next().cloned();
+
+            match oldest.and_then(|used| self.recency.remove(&used)) {
+                Some(k) => {
+                    if let Some(entry) = self.entries.remove(&k) {
+                        self.bytes -= k.len() + entry.value.len();
+                        self.evictions += 1;
+                    }
+                }
+
+                None => break,
+            }
+        }
+
+        self.clock += 1;
+        self.bytes += size;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted: Instant::now(),
+                used: self.clock,
+            },
+        );
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            entries: self.entries.len(),
+            bytes: self.bytes,
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.bytes -= key.len() + entry.value.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_cache_key_canonicalizes_args() {
+        let limits = Limits::from(Duration::from_millis(10000));
+
+        assert_eq!(
+            key("abc", &limits, r#"[ {"b": 1, "a": [2, 3]} ]"#),
+            Some(r#"abc:10000::[{"a":[2,3],"b":1}]"#.to_string())
+        );
+
+        assert_eq!(key("abc", &limits, "[{{"), None);
+    }
+
+    #[test]
+    fn test_result_cache_key_includes_limits() {
+        let limits = Limits::from(Duration::from_millis(10000));
+
+        let strict = Limits {
+            memory: Some(65536),
+            ..limits
+        };
+
+        assert_eq!(
+            key("abc", &strict, "[1]"),
+            Some("abc:10000:65536:[1]".to_string())
+        );
+
+        assert_ne!(
+            key("abc", &limits, "[1]"),
+            key("abc", &Limits::from(Duration::from_millis(100)), "[1]")
+        );
+    }
+
+    #[test]
+    fn test_result_cache_get_and_insert() {
+        let mut cache = ResultCache::new(Duration::from_millis(60000), 1024);
+
+        assert_eq!(cache.get("a"), None);
+
+        cache.insert("a".to_string(), "1".to_string());
+
+        assert_eq!(cache.get("a"), Some("1".to_string()));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.bytes, 2);
+    }
+
+    #[test]
+    fn test_result_cache_expires_entries() {
+        let mut cache = ResultCache::new(Duration::from_millis(1), 1024);
+
+        cache.insert("a".to_string(), "1".to_string());
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn test_result_cache_evicts_least_recently_used() {
+        let mut cache = ResultCache::new(Duration::from_millis(60000), 6);
+
+        cache.insert("a".to_string(), "11".to_string());
+        cache.insert("b".to_string(), "22".to_string());
+
+        // Use "a" so that "b" is the least recently used
+        assert!(cache.get("a").is_some());
+
+        cache.insert("c".to_string(), "33".to_string());
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some("11".to_string()));
+        assert_eq!(cache.get("c"), Some("33".to_string()));
+        assert_eq!(cache.stats().evictions, 1);
+
+        // Entries larger than the cache are never stored
+        cache.insert("d".to_string(), "toolarge".to_string());
+        assert_eq!(cache.get("d"), None);
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
@@ -1,137 +1,2458 @@
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use crate::bundle::{self, Bundle, BundledScript, Imported, Mode};
+use crate::events::Kind;
+use crate::listing::{self, Summary};
+use crate::persistence;
+use crate::replication::{self, Change, Replica, Version};
+use crate::self_test::{self, TestCase};
+use crate::store::{self, CachedStore, ScriptStore, StoreError};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use std::{cmp, io, mem};
 use uuid::Uuid;
 
-pub(crate) struct ScriptRegistry {
-    limit: Duration,
-    scripts: HashMap<Uuid, (String, Instant)>,
+const SHA256_PREFIX: &str = "sha256-";
+
+/// Identifies a script, either by a random UUID, or by its content, i.e.
+/// the SHA-256 digest of its normalized code, which is written as
+/// `sha256-` followed by the hex-encoded digest.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ScriptId {
+    Uuid(Uuid),
+    Sha256(String),
+}
+
+impl ScriptId {
+    pub fn random() -> Self {
+        ScriptId::Uuid(Uuid::new_v4())
+    }
+
+    /// Returns the content address of code. Thus, clients can determine a
+    /// script's id without defining it.
+    pub fn for_code(code: &str) -> Self {
+        ScriptId::Sha256(sha256_hex(normalize(code).as_bytes()))
+    }
+}
+
+impl fmt::Display for ScriptId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptId::Uuid(id) => write!(f, "{}", id),
+            ScriptId::Sha256(digest) => write!(f, "{}{}", SHA256_PREFIX, digest),
+        }
+    }
+}
+
+impl FromStr for ScriptId {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid script id");
+
+        if s.starts_with(SHA256_PREFIX) {
+            let digest = s[SHA256_PREFIX.len()..].to_ascii_lowercase();
+
+            if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                Ok(ScriptId::Sha256(digest))
+            } else {
+                Err(invalid())
+            }
+        } else {
+            Uuid::parse_str(s)
+                .map(ScriptId::Uuid)
+                .map_err(|_| invalid())
+        }
+    }
+}
+
+impl Serialize for ScriptId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ScriptId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Normalizes code for its content address, so that it doesn't depend on
+/// the line endings that are used, nor on whitespace around the code, or a
+/// byte order mark. Neither affects the meaning of the code.
+pub fn normalize(code: &str) -> String {
+    code.trim_start_matches('\u{feff}')
+        .trim()
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+}
+
+/// A script's code along with its metadata.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Script {
+    pub code: String,
+    pub hash: String,
+    pub deterministic: bool,
+    pub output: Option<String>,
+    pub tests: Vec<TestCase>,
+    #[serde(default)]
+    pub content_addressed: bool,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// A script definition as supplied by clients, i.e. a JSON object
+/// that includes the code along with any options.
+#[derive(Deserialize)]
+struct ScriptDefinition {
+    code: String,
+    #[serde(default)]
+    deterministic: bool,
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    tests: Vec<TestCase>,
+    #[serde(default)]
+    content_addressed: bool,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    ttl_ms: Option<u64>,
+    #[serde(default)]
+    pinned: bool,
+}
+
+impl Script {
+    pub fn new(code: String) -> Self {
+        let hash = sha256_hex(code.as_bytes());
+
+        Self {
+            code,
+            hash,
+            deterministic: false,
+            output: None,
+            tests: vec![],
+            content_addressed: false,
+            labels: vec![],
+            ttl_ms: None,
+            pinned: false,
+        }
+    }
+
+    /// Parses a script from a request body, which is either the code
+    /// itself, or a JSON object that defines it, e.g.
+    ///
+    ///   {"code": "function(a, b) { return a + b; }", "deterministic": true}
+    ///
+    /// Function expressions are never valid JSON, so the two cannot be
+    /// confused. A definition may also declare the media type of the
+    /// script's string and buffer results with `output`, e.g. "text/html",
+    /// and test cases that it must pass with `tests`, e.g.
+    ///
+    ///   [{"args": [1, 2], "expect": 3}, {"args": [], "error": "TypeError"}]
+    ///
+    /// With `content_addressed`, the script's id is derived from its code,
+    /// rather than being random. `labels` are arbitrary strings that the
+    /// script can be found by when listing scripts.
+    ///
+    /// A script expires once it's gone unused for `ttl_ms` milliseconds,
+    /// or the default expiration time if it's not specified, unless it's
+    /// `pinned`, in which case it never expires.
+    pub fn parse(body: &[u8]) -> io::Result<Self> {
+        let code = String::from_utf8(body.to_vec()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot extract script from request body",
+            )
+        })?;
+
+        if !code.trim_start().starts_with('{') {
+            return Ok(Self::new(code));
+        }
+
+        let definition = serde_json::from_str::<ScriptDefinition>(&code)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if let Some(ref output) = definition.output {
+            if !output.contains('/') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "output must be a media type",
+                ));
+            }
+        }
+
+        self_test::validate(&definition.tests)?;
+
+        if definition.pinned && definition.ttl_ms.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a pinned script cannot have a ttl_ms",
+            ));
+        }
+
+        Ok(Self {
+            deterministic: definition.deterministic,
+            output: definition.output,
+            tests: definition.tests,
+            content_addressed: definition.content_addressed,
+            labels: definition.labels,
+            ttl_ms: definition.ttl_ms,
+            pinned: definition.pinned,
+            ..Self::new(definition.code)
+        })
+    }
+
+    /// Determines if another definition has the same options as this one,
+    /// regardless of its code.
+    pub fn has_options_of(&self, other: &Script) -> bool {
+        self.deterministic == other.deterministic
+            && self.output == other.output
+            && self.tests == other.tests
+            && self.content_addressed == other.content_addressed
+            && self.labels == other.labels
+            && self.ttl_ms == other.ttl_ms
+            && self.pinned == other.pinned
+    }
+}
+
+/// The versions of a named script, which are numbered from 1, and its
+/// aliases, which refer to versions by number. Versions are immutable,
+/// while aliases may be moved to any version.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Named {
+    pub versions: Vec<ScriptId>,
+    pub aliases: BTreeMap<String, usize>,
+}
+
+/// The alias that's moved to each new version of a named script.
+pub const LATEST: &str = "latest";
+
+impl Named {
+    /// Returns the id of a version, given its number or an alias.
+    pub fn resolve(&self, reference: &str) -> Option<ScriptId> {
+        let version = match reference.parse::<usize>() {
+            Ok(version) => version,
+            Err(_) => *self.aliases.get(reference)?,
+        };
+
+        version
+            .checked_sub(1)
+            .and_then(|i| self.versions.get(i))
+            .cloned()
+    }
+}
+
+/// Checks that the name of a script consists only of ASCII letters, digits,
+/// '-', '_' and '.'.
+pub fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "names must consist of letters, digits, '-', '_' and '.'",
+        ))
+    }
+}
+
+/// Checks that the name of an alias is valid as for a script, and isn't a
+/// number, given numbers refer to versions instead.
+pub fn validate_alias(alias: &str) -> io::Result<()> {
+    validate_name(alias)?;
+
+    if alias.parse::<usize>().is_ok() {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "names of aliases cannot be numbers",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Determines if a reference to a script, as accepted by `lookup`, is
+/// well-formed: a script id, or `by-name/` followed by a name and optionally
+/// `@` and a version or alias.
+pub fn is_reference(reference: &str) -> bool {
+    if reference.starts_with("by-name/") {
+        let mut fields = reference[8..].splitn(2, '@');
+        let name = fields.next().unwrap_or_default();
+
+        validate_name(name).is_ok() && fields.next().map_or(true, |v| validate_name(v).is_ok())
+    } else {
+        reference.parse::<ScriptId>().is_ok()
+    }
+}
+
+/// Returns the hex-encoded SHA-256 digest of the provided data.
+pub fn sha256_hex(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    let mut hex = String::with_capacity(digest.len() * 2);
+
+    for byte in digest.iter() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+
+    hex
+}
+
+/// A script in the registry, along with when it was created and last
+/// accessed, and how many times it has been executed.
+#[derive(Clone, Debug)]
+pub struct Stored {
+    pub script: Script,
+    pub created: SystemTime,
+    pub accessed: Instant,
+    pub executions: u64,
+}
+
+impl Stored {
+    pub fn new(script: Script) -> Self {
+        Self {
+            script,
+            created: SystemTime::now(),
+            accessed: Instant::now(),
+            executions: 0,
+        }
+    }
+}
+
+/// How long scripts may go unused before they expire. `default` applies to
+/// scripts that don't specify a TTL, and the TTLs that scripts specify, as
+/// well as pinning, are limited to `maximum`, if any.
+#[derive(Clone, Copy, Debug)]
+pub struct Expiration {
+    pub default: Duration,
+    pub maximum: Option<Duration>,
+}
+
+impl Expiration {
+    /// Returns how long a script may go unused before it expires, if ever.
+    pub fn ttl(&self, script: &Script) -> Option<Duration> {
+        let ttl = if script.pinned {
+            None
+        } else {
+            Some(
+                script
+                    .ttl_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(self.default),
+            )
+        };
+
+        match (ttl, self.maximum) {
+            (Some(ttl), Some(maximum)) if ttl > maximum => Some(maximum),
+            (None, maximum) => maximum,
+            (ttl, _) => ttl,
+        }
+    }
+
+    /// Determines if a script that has gone unused for `age` has expired.
+    fn expired(&self, script: &Script, age: Duration) -> bool {
+        self.ttl(script).map(|ttl| age > ttl).unwrap_or(false)
+    }
+}
+
+/// The maximum number of scripts that a registry holds, and the maximum
+/// total size of their code, if any.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capacity {
+    pub entries: Option<usize>,
+    pub bytes: Option<usize>,
+}
+
+impl Capacity {
+    fn exceeded(&self, entries: usize, bytes: usize) -> bool {
+        self.entries.map(|max| entries > max).unwrap_or(false)
+            || self.bytes.map(|max| bytes > max).unwrap_or(false)
+    }
+}
+
+/// The changes that an import has made so far.
+#[derive(Default)]
+struct ImportProgress {
+    removed: Vec<(ScriptId, Stored)>,
+    removed_names: Vec<String>,
+    stored: Vec<ScriptId>,
+    named: Vec<String>,
+}
+
+pub struct ScriptRegistry {
+    expiration: Expiration,
+    capacity: Capacity,
+    store: CachedStore,
+    replica: Option<Replica>,
+    events: Vec<Kind>,
+    executions: u64,
+    expirations: u64,
+    evictions: u64,
+}
+
+/// Counters and gauges that describe the scripts in a registry.
+pub struct RegistryStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub executions: u64,
+    pub expirations: u64,
+    pub evictions: u64,
 }
 
 /// Defines a local "registry" for scripts where
 /// they can be stored and retrieved
 impl ScriptRegistry {
-    pub(crate) fn new(limit: Duration) -> Self {
-        Self {
-            limit,
-            scripts: HashMap::new(),
+    /// Creates a registry that keeps its scripts in memory only.
+    #[cfg(test)]
+    pub fn new(expiration: Expiration, capacity: Capacity) -> Self {
+        Self::open(
+            expiration,
+            capacity,
+            Box::new(crate::store::MemoryStore::default()),
+        )
+        .unwrap()
+    }
+
+    /// Creates a registry that keeps its scripts in a store, e.g. one that
+    /// persists them, starting with the scripts that it holds. Scripts that
+    /// were last accessed longer ago than their expiration time are
+    /// discarded, unless they're versions of a named script. Fails if the
+    /// store cannot be read.
+    pub fn open(
+        expiration: Expiration,
+        capacity: Capacity,
+        store: Box<ScriptStore>,
+    ) -> io::Result<Self> {
+        let mut registry = Self {
+            expiration,
+            capacity,
+            store: CachedStore::load(store)?,
+            replica: None,
+            events: vec![],
+            executions: 0,
+            expirations: 0,
+            evictions: 0,
+        };
+
+        for id in registry.expired(Instant::now()) {
+            report(registry.store.remove(&id));
         }
+
+        Ok(registry)
     }
 
     /// Gets a script's contents, incrementing its
     /// last accessed counter if found. An owned
     /// copy is returned given the narrow use
     /// case.
-    pub(crate) fn get(&mut self, id: &Uuid) -> Option<String> {
+    pub fn get(&mut self, id: &ScriptId) -> Option<Script> {
         let now = Instant::now();
 
-        if let Some(o) = self.scripts.get_mut(id) {
-            o.1 = now;
+        // Scripts that have expired are removed when they're read, rather
+        // than waiting for them to be swept
+
+        let expired = match self.store.get(id) {
+            Some(stored) => {
+                self.expiration
+                    .expired(&stored.script, now.duration_since(stored.accessed))
+                    && !self.is_version(id)
+            }
+
+            None => false,
+        };
+
+        if expired {
+            if let Some(stored) = report(self.store.remove(id)).unwrap_or_default() {
+                self.replicate_removal(id, &stored, false);
+                self.events.push(Kind::Expired { id: id.clone() });
+            }
+
+            self.expirations += 1;
+
+            return None;
+        }
+
+        let executions = self.store.get(id)?.executions;
+
+        report(self.store.touch(id, now, executions));
+
+        self.store.get(id).map(|stored| stored.script.clone())
+    }
+
+    pub fn stats(&self) -> RegistryStats {
+        RegistryStats {
+            entries: self.store.list().count(),
+            bytes: self
+                .store
+                .list()
+                .map(|(_, stored)| stored.script.code.len())
+                .sum(),
+            executions: self.executions,
+            expirations: self.expirations,
+            evictions: self.evictions,
+        }
+    }
+
+    /// Counts an execution of a script.
+    pub fn executed(&mut self, id: &ScriptId) {
+        let touched = self
+            .store
+            .get(id)
+            .map(|stored| (stored.accessed, stored.executions + 1));
+
+        if let Some((accessed, executions)) = touched {
+            report(self.store.touch(id, accessed, executions));
+            self.executions += 1;
         }
+    }
 
-        self.scripts.get(id).map(|(s, _)| s.to_string())
+    /// Exports the scripts that haven't expired, and the names of scripts,
+    /// as a bundle.
+    pub fn export(&self) -> Bundle {
+        let now = Instant::now();
+        let versions = versions(self.store.names());
+
+        let mut scripts = self
+            .store
+            .list()
+            .filter(|&(id, stored)| {
+                versions.contains(id)
+                    || !self
+                        .expiration
+                        .expired(&stored.script, now.duration_since(stored.accessed))
+            })
+            .map(|(id, stored)| BundledScript {
+                id: id.clone(),
+                script: stored.script.clone(),
+                created: persistence::to_millis(stored.created),
+                executions: stored.executions,
+            })
+            .collect::<Vec<_>>();
+
+        scripts.sort_by_key(|bundled| bundled.id.to_string());
+
+        Bundle {
+            format: bundle::FORMAT,
+            scripts,
+            names: self
+                .store
+                .names()
+                .iter()
+                .map(|(name, named)| (name.clone(), named.clone()))
+                .collect(),
+        }
+    }
+
+    /// Imports a bundle, keeping the ids of its scripts. Imported scripts
+    /// count as having just been accessed, so that they don't expire right
+    /// away. Nothing is imported if the bundle is invalid, or if the
+    /// registry cannot hold it without evicting scripts.
+    ///
+    /// If the store fails to persist a change, the import stops there, so
+    /// it may be partial, e.g. the registry may have been cleared but only
+    /// some of the bundle stored. The changes that were made are still
+    /// replicated and published.
+    pub fn import(&mut self, bundle: Bundle, mode: Mode) -> io::Result<Imported> {
+        {
+            let store = &self.store;
+
+            bundle::validate(&bundle, |id| mode == Mode::Merge && store.get(id).is_some())?;
+        }
+
+        let bundled = bundle.scripts.iter().map(|b| &b.id).collect::<HashSet<_>>();
+
+        let (kept_entries, kept_bytes) = match mode {
+            Mode::Merge => self
+                .store
+                .list()
+                .filter(|&(id, _)| !bundled.contains(id))
+                .fold((0, 0), |(entries, bytes), (_, stored)| {
+                    (entries + 1, bytes + stored.script.code.len())
+                }),
+
+            Mode::Replace => (0, 0),
+        };
+
+        let entries = kept_entries + bundle.scripts.len();
+        let bytes = kept_bytes
+            + bundle
+                .scripts
+                .iter()
+                .map(|b| b.script.code.len())
+                .sum::<usize>();
+
+        if self.capacity.exceeded(entries, bytes) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "script registry cannot hold the bundle",
+            ));
+        }
+
+        let existed = bundled
+            .iter()
+            .filter(|&&id| self.store.get(id).is_some())
+            .map(|&id| id.clone())
+            .collect::<HashSet<_>>();
+
+        let imported = Imported {
+            scripts: bundle.scripts.len(),
+            names: bundle.names.len(),
+        };
+
+        let mut progress = ImportProgress::default();
+        let result = self.apply_import(bundle, mode, &mut progress);
+
+        // Peers are sent the changes individually, as are subscribers to
+        // events. This is done for whatever was applied, even if the store
+        // failed partway, so that they don't diverge from the registry
+
+        for (id, stored) in progress.removed {
+            if self.store.get(&id).is_none() {
+                self.replicate_removal(&id, &stored, true);
+                self.events.push(Kind::Deleted { id });
+            }
+        }
+
+        for id in progress.stored {
+            self.replicate_script(&id);
+
+            self.events.push(if existed.contains(&id) {
+                Kind::Updated { id }
+            } else {
+                Kind::Created { id }
+            });
+        }
+
+        for name in progress.removed_names.into_iter().chain(progress.named) {
+            self.replicate_name(&name);
+            self.name_changed(&name);
+        }
+
+        result.map(|()| imported)
+    }
+
+    /// Applies an import to the store, recording each change as it's made.
+    fn apply_import(
+        &mut self,
+        bundle: Bundle,
+        mode: Mode,
+        progress: &mut ImportProgress,
+    ) -> io::Result<()> {
+        if mode == Mode::Replace {
+            self.clear(progress)?;
+        }
+
+        for bundled in bundle.scripts {
+            let id = bundled.id.clone();
+
+            self.store
+                .store(
+                    bundled.id,
+                    Stored {
+                        script: bundled.script,
+                        created: persistence::from_millis(bundled.created),
+                        accessed: Instant::now(),
+                        executions: bundled.executions,
+                    },
+                )
+                .map_err(StoreError::wrap)?;
+
+            progress.stored.push(id);
+        }
+
+        for (name, named) in bundle.names {
+            self.store
+                .store_name(name.clone(), named)
+                .map_err(StoreError::wrap)?;
+
+            progress.named.push(name);
+        }
+
+        Ok(())
+    }
+
+    /// Starts keeping track of changes to the registry, so that they can be
+    /// replicated to peers. Scripts that are already stored count as having
+    /// been defined when they were created, and names as having been defined
+    /// before any others.
+    pub fn replicate(&mut self) {
+        let mut replica = Replica::default();
+
+        for (id, stored) in self.store.list() {
+            let version = Version {
+                modified: persistence::to_millis(stored.created),
+                ..Version::default()
+            };
+
+            replica.scripts.insert(id.clone(), version);
+        }
+
+        for name in self.store.names().keys() {
+            replica.names.insert(name.clone(), Version::default());
+        }
+
+        self.replica = Some(replica);
+    }
+
+    /// Takes the changes that have been made since they were last taken, so
+    /// that they can be sent to peers.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        match self.replica {
+            Some(ref mut replica) => mem::replace(&mut replica.outbox, vec![]),
+            None => vec![],
+        }
+    }
+
+    /// Takes the events that have happened since they were last taken, so
+    /// that they can be published.
+    pub fn take_events(&mut self) -> Vec<Kind> {
+        mem::replace(&mut self.events, vec![])
+    }
+
+    /// Returns the versions of the scripts and names in the registry,
+    /// including those that have been removed recently.
+    pub fn digest(&self) -> replication::Digest {
+        let mut digest = replication::Digest::default();
+
+        if let Some(ref replica) = self.replica {
+            for id in replica.scripts.keys() {
+                if let Some(version) = self.script_version(id) {
+                    digest.scripts.insert(id.clone(), version);
+                }
+            }
+
+            digest.names = replica.names.clone();
+        }
+
+        digest
+    }
+
+    /// Returns the changes that a peer is missing, given its digest. Scripts
+    /// come first, so that they're stored before the names that refer to
+    /// them.
+    pub fn changes_for(&self, digest: &replication::Digest) -> Vec<Change> {
+        let replica = match self.replica {
+            Some(ref replica) => replica,
+            None => return vec![],
+        };
+
+        let mut changes = vec![];
+
+        for id in replica.scripts.keys() {
+            let version = match self.script_version(id) {
+                Some(version) => version,
+                None => continue,
+            };
+
+            let theirs = digest.scripts.get(id);
+
+            if !version.supersedes(theirs) {
+                continue;
+            }
+
+            let change = match (self.store.get(id), theirs) {
+                (None, None) => continue,
+
+                (None, Some(_)) => Change::Remove {
+                    id: id.clone(),
+                    version,
+                },
+
+                (Some(_), Some(theirs))
+                    if theirs.removed.is_none() && theirs.modified == version.modified =>
+                {
+                    Change::Touch {
+                        id: id.clone(),
+                        accessed: version.accessed,
+                    }
+                }
+
+                (Some(stored), _) => Change::Store {
+                    id: id.clone(),
+                    script: stored.script.clone(),
+                    created: persistence::to_millis(stored.created),
+                    version,
+                },
+            };
+
+            changes.push(change);
+        }
+
+        for (name, version) in &replica.names {
+            let theirs = digest.names.get(name);
+
+            if !version.supersedes(theirs) {
+                continue;
+            }
+
+            match (self.store.names().get(name), theirs) {
+                (Some(named), _) => changes.push(Change::Name {
+                    name: name.clone(),
+                    named: named.clone(),
+                    version: *version,
+                }),
+
+                (None, Some(_)) => changes.push(Change::RemoveName {
+                    name: name.clone(),
+                    version: *version,
+                }),
+
+                (None, None) => {}
+            }
+        }
+
+        changes
+    }
+
+    /// Applies the changes that a peer has made, unless they're superseded
+    /// by changes made here, returning how many were applied. They're
+    /// persisted, but not replicated any further.
+    pub fn apply(&mut self, changes: Vec<Change>) -> usize {
+        if self.replica.is_none() {
+            return 0;
+        }
+
+        let mut applied = 0;
+
+        for change in changes {
+            if self.apply_change(change) {
+                applied += 1;
+            }
+        }
+
+        applied
+    }
+
+    /// Lists the scripts that match a query, except for those that have
+    /// expired but haven't been removed yet.
+    pub fn list(&self, query: &listing::Query) -> listing::Page {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+
+        let mut names = HashMap::<&ScriptId, Vec<String>>::new();
+
+        for (name, named) in self.store.names() {
+            for (i, id) in named.versions.iter().enumerate() {
+                names
+                    .entry(id)
+                    .or_default()
+                    .push(format!("{}@{}", name, i + 1));
+            }
+        }
+
+        let summaries = self
+            .store
+            .list()
+            .filter_map(|(id, stored)| {
+                let age = now.duration_since(stored.accessed);
+                let accessed = system_now - age;
+                let names = names.remove(id).unwrap_or_default();
+
+                if names.is_empty() && self.expiration.expired(&stored.script, age) {
+                    return None;
+                }
+
+                let expires = if names.is_empty() {
+                    self.expiration
+                        .ttl(&stored.script)
+                        .map(|ttl| persistence::to_millis(accessed + ttl))
+                } else {
+                    None
+                };
+
+                Some(Summary {
+                    id: id.to_string(),
+                    names,
+                    labels: stored.script.labels.clone(),
+                    size: stored.script.code.len(),
+                    created: persistence::to_millis(stored.created),
+                    accessed: persistence::to_millis(accessed),
+                    expires,
+                    executions: stored.executions,
+                })
+            })
+            .collect();
+
+        listing::page(summaries, query)
+    }
+
+    /// Gets the id and contents of a script given a reference to it, which
+    /// is either its id, or `by-name/` followed by the script's name, and
+    /// optionally `@` and a version number or alias, which defaults to
+    /// the latest version.
+    pub fn lookup(&mut self, reference: &str) -> Option<(ScriptId, Script)> {
+        let id = if reference.starts_with("by-name/") {
+            let mut fields = reference[8..].splitn(2, '@');
+            let name = fields.next().unwrap_or_default();
+            let version = fields.next().unwrap_or(LATEST);
+
+            self.store.names().get(name)?.resolve(version)?
+        } else {
+            reference.parse().ok()?
+        };
+
+        self.get(&id).map(|script| (id, script))
+    }
+
+    pub fn contains(&self, id: &ScriptId) -> bool {
+        self.store.get(id).is_some()
+    }
+
+    pub fn named(&self, name: &str) -> Option<&Named> {
+        self.store.names().get(name)
+    }
+
+    /// Determines if a script is a version of a named script, which cannot
+    /// be removed on its own.
+    pub fn is_version(&self, id: &ScriptId) -> bool {
+        self.store
+            .names()
+            .values()
+            .any(|named| named.versions.contains(id))
+    }
+
+    /// Stores a script as the next version of a named script, creating the
+    /// name if necessary, and moves the `latest` alias to it. Returns the
+    /// number of the version along with the script's id.
+    pub fn store_version(&mut self, name: &str, script: Script) -> io::Result<(usize, ScriptId)> {
+        let id = self.store(script)?;
+
+        let mut named = self.store.names().get(name).cloned().unwrap_or_default();
+        named.versions.push(id.clone());
+
+        let version = named.versions.len();
+        named.aliases.insert(LATEST.to_string(), version);
+
+        self.store
+            .store_name(name.to_string(), named)
+            .map_err(StoreError::wrap)?;
+        self.replicate_name(name);
+        self.name_changed(name);
+
+        Ok((version, id))
+    }
+
+    /// Moves an alias of a named script to one of its versions, creating
+    /// the alias if necessary. Returns whether the alias was newly created.
+    pub fn set_alias(&mut self, name: &str, alias: &str, version: usize) -> io::Result<bool> {
+        let mut named = self
+            .store
+            .names()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cannot find script"))?;
+
+        if version == 0 || version > named.versions.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot find version {} of script {}", version, name),
+            ));
+        }
+
+        let created = named.aliases.insert(alias.to_string(), version).is_none();
+
+        self.store
+            .store_name(name.to_string(), named)
+            .map_err(StoreError::wrap)?;
+        self.replicate_name(name);
+        self.name_changed(name);
+
+        Ok(created)
+    }
+
+    /// Removes a named script, returning whether it existed. Its versions
+    /// remain available by id until they expire.
+    pub fn remove_name(&mut self, name: &str) -> io::Result<bool> {
+        let removed = self.store.remove_name(name).map_err(StoreError::wrap)?;
+
+        if removed.is_some() {
+            self.replicate_name(name);
+            self.name_changed(name);
+        }
+
+        Ok(removed.is_some())
+    }
+
+    /// Removes the scripts that haven't been used in the amount of time that
+    /// they may go unused, except for versions of named scripts, returning
+    /// how many were removed. This happens whenever a script is stored, and
+    /// periodically so that scripts expire even when none are being stored.
+    pub fn remove_expired(&mut self) -> usize {
+        let expired = self.expired(Instant::now());
+
+        for id in &expired {
+            if let Some(stored) = report(self.store.remove(id)).unwrap_or_default() {
+                self.replicate_removal(id, &stored, false);
+                self.events.push(Kind::Expired { id: id.clone() });
+            }
+        }
+
+        if let Some(ref mut replica) = self.replica {
+            replica.prune(persistence::to_millis(SystemTime::now()));
+        }
+
+        self.expirations += expired.len() as u64;
+
+        expired.len()
+    }
+
+    /// Returns the ids of the scripts that have expired by `now`, except
+    /// for versions of named scripts.
+    fn expired(&self, now: Instant) -> Vec<ScriptId> {
+        let versions = versions(self.store.names());
+
+        self.store
+            .list()
+            .filter(|&(id, stored)| {
+                self.expiration
+                    .expired(&stored.script, now.duration_since(stored.accessed))
+                    && !versions.contains(id)
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Removes all scripts and names, recording them as they're removed so
+    /// that their removal can be replicated.
+    fn clear(&mut self, progress: &mut ImportProgress) -> io::Result<()> {
+        let ids = self
+            .store
+            .list()
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        let names = self.store.names().keys().cloned().collect::<Vec<_>>();
+
+        for id in ids {
+            if let Some(stored) = self.store.remove(&id).map_err(StoreError::wrap)? {
+                progress.removed.push((id, stored));
+            }
+        }
+
+        for name in names {
+            if self
+                .store
+                .remove_name(&name)
+                .map_err(StoreError::wrap)?
+                .is_some()
+            {
+                progress.removed_names.push(name);
+            }
+        }
+
+        Ok(())
     }
 
     /// Removes a script given its id
-    pub(crate) fn remove(&mut self, id: &Uuid) {
-        self.scripts.remove(id);
+    pub fn remove(&mut self, id: &ScriptId) -> io::Result<()> {
+        if let Some(stored) = self.store.remove(id).map_err(StoreError::wrap)? {
+            self.replicate_removal(id, &stored, true);
+            self.events.push(Kind::Deleted { id: id.clone() });
+        }
+
+        Ok(())
+    }
+
+    /// Removes a script to make room for another. Unlike removing it, this
+    /// doesn't supersede uses of the script by peers.
+    fn evict(&mut self, id: &ScriptId) -> io::Result<()> {
+        if let Some(stored) = self.store.remove(id).map_err(StoreError::wrap)? {
+            self.replicate_removal(id, &stored, false);
+            self.events.push(Kind::Evicted { id: id.clone() });
+        }
+
+        Ok(())
     }
 
     /// Stores a script, evicting any that haven't been used in a
-    /// specified amount of time.
-    pub(crate) fn store(&mut self, script: String) -> Uuid {
-        let id = Uuid::new_v4();
-        let now = Instant::now();
+    /// specified amount of time, except for versions of named scripts.
+    /// If the registry is at capacity, the least recently used scripts are
+    /// evicted too, and if that isn't possible, the script isn't stored.
+    ///
+    /// A content-addressed script that's already stored isn't replaced,
+    /// and only counts as an access of the existing script. If the existing
+    /// script has other options, this fails with `AlreadyExists` instead.
+    pub fn store(&mut self, script: Script) -> io::Result<ScriptId> {
+        let id = if script.content_addressed {
+            ScriptId::for_code(&script.code)
+        } else {
+            ScriptId::random()
+        };
+
+        self.remove_expired();
+
+        if let Some(stored) = self.store.get(&id) {
+            if !stored.script.has_options_of(&script) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "script is already defined with other options",
+                ));
+            }
+
+            let _ = self.get(&id);
+
+            return Ok(id);
+        }
+
+        self.make_room(None, script.code.len())?;
+
+        self.store
+            .store(id.clone(), Stored::new(script))
+            .map_err(StoreError::wrap)?;
+        self.replicate_script(&id);
+        self.events.push(Kind::Created { id: id.clone() });
+
+        Ok(id)
+    }
+
+    /// Replaces the definition of a script, returning whether it existed.
+    /// Other scripts may be evicted to make room for it, as when storing.
+    pub fn replace(&mut self, id: &ScriptId, script: Script) -> io::Result<bool> {
+        let (created, executions) = match self.store.get(id) {
+            Some(stored) => (stored.created, stored.executions),
+            None => return Ok(false),
+        };
+
+        self.make_room(Some(id), script.code.len())?;
+
+        let stored = Stored {
+            script,
+            created,
+            accessed: Instant::now(),
+            executions,
+        };
+
+        self.store
+            .store(id.clone(), stored)
+            .map_err(StoreError::wrap)?;
+        self.replicate_script(id);
+        self.events.push(Kind::Updated { id: id.clone() });
+
+        Ok(true)
+    }
+
+    /// Evicts the least recently used scripts until a script of `size`
+    /// bytes fits, in place of the script being replaced, if any. Pinned
+    /// scripts and versions of named scripts are never evicted, so if they
+    /// fill the registry, nothing is evicted and an error is returned.
+    fn make_room(&mut self, replacing: Option<&ScriptId>, size: usize) -> io::Result<()> {
+        if self.capacity.exceeded(1, size) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "script is larger than the script registry can hold",
+            ));
+        }
+
+        let versions = versions(self.store.names());
+
+        let mut entries = 1;
+        let mut bytes = size;
+        let mut candidates = vec![];
+
+        for (id, stored) in self.store.list() {
+            if Some(id) == replacing {
+                continue;
+            }
+
+            entries += 1;
+            bytes += stored.script.code.len();
+
+            if !stored.script.pinned && !versions.contains(id) {
+                candidates.push((stored.accessed, id.clone(), stored.script.code.len()));
+            }
+        }
+
+        candidates.sort_by_key(|&(accessed, _, _)| accessed);
+
+        let mut evicted = vec![];
+
+        for (_, id, size) in candidates {
+            if !self.capacity.exceeded(entries, bytes) {
+                break;
+            }
+
+            entries -= 1;
+            bytes -= size;
+            evicted.push(id);
+        }
+
+        if self.capacity.exceeded(entries, bytes) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "script registry is full of pinned scripts and versions of named scripts",
+            ));
+        }
+
+        for id in &evicted {
+            self.evict(id)?;
+            self.evictions += 1;
+        }
+
+        Ok(())
+    }
+
+    fn apply_change(&mut self, change: Change) -> bool {
+        match change {
+            Change::Store {
+                id,
+                script,
+                created,
+                version,
+            } => {
+                if !version.supersedes(self.script_version(&id).as_ref()) {
+                    return false;
+                }
+
+                let replacing = if self.store.get(&id).is_some() {
+                    Some(&id)
+                } else {
+                    None
+                };
+
+                if let Err(e) = self.make_room(replacing, script.code.len()) {
+                    eprintln!("cannot replicate script {}: {}", id, e);
+                    return false;
+                }
+
+                let executions = self
+                    .store
+                    .get(&id)
+                    .map(|stored| stored.executions)
+                    .unwrap_or_default();
+
+                let stored = Stored {
+                    script,
+                    created: persistence::from_millis(created),
+                    accessed: accessed_at(version.accessed),
+                    executions,
+                };
+
+                if report(self.store.store(id.clone(), stored)).is_none() {
+                    return false;
+                }
+
+                self.events.push(if replacing.is_some() {
+                    Kind::Updated { id: id.clone() }
+                } else {
+                    Kind::Created { id: id.clone() }
+                });
+
+                if let Some(ref mut replica) = self.replica {
+                    replica.scripts.insert(id, version);
+                }
+            }
+
+            Change::Touch { id, accessed } => {
+                let version = match self.script_version(&id) {
+                    Some(ref version) if version.removed.is_none() => *version,
+                    _ => return false,
+                };
+
+                let touched = Version {
+                    accessed,
+                    ..version
+                };
+
+                let executions = match self.store.get(&id) {
+                    Some(stored) if touched.supersedes(Some(&version)) => stored.executions,
+                    _ => return false,
+                };
+
+                if report(self.store.touch(&id, accessed_at(accessed), executions)).is_none() {
+                    return false;
+                }
+            }
+
+            Change::Remove { id, version } => {
+                if self.is_version(&id) || !version.supersedes(self.script_version(&id).as_ref()) {
+                    return false;
+                }
+
+                match report(self.store.remove(&id)) {
+                    Some(Some(_)) => self.events.push(Kind::Deleted { id: id.clone() }),
+                    Some(None) => (),
+                    None => return false,
+                }
+
+                if let Some(ref mut replica) = self.replica {
+                    replica.scripts.insert(id, version);
+                }
+            }
+
+            Change::Name {
+                name,
+                named,
+                version,
+            } => {
+                let current = self.replica.as_ref().and_then(|r| r.names.get(&name));
+
+                if !version.supersedes(current) || validate_name(&name).is_err() {
+                    return false;
+                }
+
+                if report(self.store.store_name(name.clone(), named)).is_none() {
+                    return false;
+                }
+
+                self.name_changed(&name);
+
+                if let Some(ref mut replica) = self.replica {
+                    replica.names.insert(name, version);
+                }
+            }
+
+            Change::RemoveName { name, version } => {
+                let current = self.replica.as_ref().and_then(|r| r.names.get(&name));
+
+                if !version.supersedes(current) {
+                    return false;
+                }
+
+                match report(self.store.remove_name(&name)) {
+                    Some(Some(_)) => self.name_changed(&name),
+                    Some(None) => (),
+                    None => return false,
+                }
+
+                if let Some(ref mut replica) = self.replica {
+                    replica.names.insert(name, version);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Returns the version of a script, as far as replication is concerned,
+    /// along with when it was last accessed if it's stored.
+    fn script_version(&self, id: &ScriptId) -> Option<Version> {
+        let replica = self.replica.as_ref()?;
+
+        match (self.store.get(id), replica.scripts.get(id)) {
+            (Some(stored), version) => Some(Version {
+                modified: version
+                    .map(|v| v.modified)
+                    .unwrap_or_else(|| persistence::to_millis(stored.created)),
+                accessed: accessed_millis(stored),
+                removed: None,
+            }),
+
+            (None, version) => version.cloned(),
+        }
+    }
+
+    /// Records that a script has been defined, so that peers are sent the
+    /// change. Its version is later than any that it had before, even if
+    /// the clock is behind.
+    fn replicate_script(&mut self, id: &ScriptId) {
+        let now = persistence::to_millis(SystemTime::now());
+
+        if let (Some(replica), Some(stored)) = (self.replica.as_mut(), self.store.get(id)) {
+            let previous = replica.scripts.get(id).map(|v| v.modified + 1);
+
+            let version = Version {
+                modified: cmp::max(now, previous.unwrap_or_default()),
+                accessed: accessed_millis(stored),
+                removed: None,
+            };
+
+            replica.scripts.insert(id.clone(), version);
+
+            replica.outbox.push(Change::Store {
+                id: id.clone(),
+                script: stored.script.clone(),
+                created: persistence::to_millis(stored.created),
+                version,
+            });
+        }
+    }
+
+    /// Records that a script has been removed, so that peers are sent the
+    /// change. A script that's removed explicitly is removed by peers too,
+    /// whereas one that expired or was evicted isn't modified by that, and
+    /// is kept by peers that have used it since.
+    fn replicate_removal(&mut self, id: &ScriptId, stored: &Stored, explicit: bool) {
+        let now = persistence::to_millis(SystemTime::now());
+
+        if let Some(ref mut replica) = self.replica {
+            let previous = replica.scripts.get(id).map(|v| v.modified);
+
+            let modified = match previous {
+                Some(modified) if explicit => cmp::max(now, modified + 1),
+                Some(modified) => modified,
+                None if explicit => now,
+                None => persistence::to_millis(stored.created),
+            };
+
+            let version = Version {
+                modified,
+                accessed: accessed_millis(stored),
+                removed: Some(now),
+            };
+
+            replica.scripts.insert(id.clone(), version);
+
+            replica.outbox.push(Change::Remove {
+                id: id.clone(),
+                version,
+            });
+        }
+    }
+
+    /// Records that a name has been defined or removed, so that subscribers
+    /// are sent an event.
+    fn name_changed(&mut self, name: &str) {
+        let name = name.to_string();
+
+        self.events.push(if self.store.names().contains_key(&name) {
+            Kind::NameUpdated { name }
+        } else {
+            Kind::NameDeleted { name }
+        });
+    }
+
+    /// Records that a name has been defined or removed, so that peers are
+    /// sent the change.
+    fn replicate_name(&mut self, name: &str) {
+        let now = persistence::to_millis(SystemTime::now());
+
+        if let Some(ref mut replica) = self.replica {
+            let previous = replica.names.get(name).map(|v| v.modified + 1);
+
+            let version = Version {
+                modified: cmp::max(now, previous.unwrap_or_default()),
+                accessed: 0,
+                removed: if self.store.names().contains_key(name) {
+                    None
+                } else {
+                    Some(now)
+                },
+            };
+
+            replica.names.insert(name.to_string(), version);
+
+            replica.outbox.push(match self.store.names().get(name) {
+                Some(named) => Change::Name {
+                    name: name.to_string(),
+                    named: named.clone(),
+                    version,
+                },
+
+                None => Change::RemoveName {
+                    name: name.to_string(),
+                    version,
+                },
+            });
+        }
+    }
+}
+
+/// Returns when a script was last accessed, in milliseconds since the Unix
+/// epoch.
+fn accessed_millis(stored: &Stored) -> u64 {
+    persistence::to_millis(store::to_system_time(stored.accessed))
+}
 
-        let limit = self.limit;
-        self.scripts
-            .retain(|_, &mut (_, last_accessed)| now.duration_since(last_accessed) <= limit);
+/// Returns the instant of an access, given in milliseconds since the Unix
+/// epoch. Accesses from the future count as happening now.
+fn accessed_at(millis: u64) -> Instant {
+    store::to_instant(persistence::from_millis(millis))
+}
 
-        self.scripts.insert(id, (script, now));
+/// Reports a change that the store couldn't persist, when the change isn't
+/// made on behalf of a client that could be told about it, e.g. when a
+/// script expires or is touched. Returns the result of the change, if any.
+fn report<T>(result: io::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("{}", StoreError::wrap(e));
 
-        id
+            None
+        }
     }
 }
 
+/// Returns the ids of all versions of named scripts.
+fn versions(names: &HashMap<String, Named>) -> HashSet<ScriptId> {
+    names
+        .values()
+        .flat_map(|named| named.versions.iter().cloned())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::persistence::PersistedScript;
+
+    fn file_store(dir: &std::path::Path, snapshot_records: usize) -> Box<ScriptStore> {
+        Box::new(crate::persistence::FileStore::open(dir, snapshot_records).unwrap())
+    }
+
+    fn persisted(code: &str) -> PersistedScript {
+        PersistedScript {
+            script: Script::new(code.to_string()),
+            created: SystemTime::now(),
+            accessed: SystemTime::now(),
+            executions: 0,
+        }
+    }
+
+    fn expiration(ms: u64) -> Expiration {
+        Expiration {
+            default: Duration::from_millis(ms),
+            maximum: None,
+        }
+    }
 
     #[test]
     fn test_script_registry_get_none() {
-        let mut registry = ScriptRegistry::new(Duration::from_millis(0));
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
 
         assert!(registry
-            .get(&Uuid::parse_str("50b0cb8f-1f59-4ba5-8935-ba54bb64bc3f").unwrap())
+            .get(&"50b0cb8f-1f59-4ba5-8935-ba54bb64bc3f".parse().unwrap())
             .is_none());
 
         let script = "function() { return 3 + 4; }";
 
-        let id = registry.store(script.to_string());
+        let id = registry.store(Script::new(script.to_string())).unwrap();
 
-        assert_eq!(registry.get(&id), Some(script.to_string()));
+        assert_eq!(registry.get(&id), Some(Script::new(script.to_string())));
     }
 
     #[test]
     fn test_script_registry_store_and_get() {
-        let mut registry = ScriptRegistry::new(Duration::from_millis(0));
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
 
         let script = "function() { return 3 + 4; }";
 
-        let id = registry.store(script.to_string());
+        let id = registry.store(Script::new(script.to_string())).unwrap();
 
-        assert_eq!(registry.get(&id), Some(script.to_string()));
+        assert_eq!(registry.get(&id), Some(Script::new(script.to_string())));
     }
 
     #[test]
     fn test_script_registry_store_and_remove() {
-        let mut registry = ScriptRegistry::new(Duration::from_millis(60000));
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
 
         let script = "function() { return 3 + 4; }";
 
-        let id = registry.store(script.to_string());
+        let id = registry.store(Script::new(script.to_string())).unwrap();
 
-        registry.remove(&id);
+        registry.remove(&id).unwrap();
 
         assert_eq!(registry.get(&id), None);
     }
 
+    #[test]
+    fn test_script_registry_replace() {
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
+
+        let id = registry
+            .store(Script::new("function() { return 1; }".to_string()))
+            .unwrap();
+
+        assert!(registry
+            .replace(&id, Script::new("function() { return 2; }".to_string()))
+            .unwrap());
+        assert!(!registry
+            .replace(
+                &ScriptId::random(),
+                Script::new("function() {}".to_string())
+            )
+            .unwrap());
+
+        assert_eq!(
+            registry.get(&id),
+            Some(Script::new("function() { return 2; }".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_script_registry_list() {
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
+
+        let labelled =
+            Script::parse(br#"{"code": "function() { return 1; }", "labels": ["billing"]}"#)
+                .unwrap();
+
+        let id = registry.store(labelled).unwrap();
+        let (_, version) = registry
+            .store_version("adder", Script::new("function() {}".to_string()))
+            .unwrap();
+
+        registry.executed(&id);
+        registry.executed(&id);
+
+        let page = registry.list(&listing::Query::parse(Some("label=billing")).unwrap());
+
+        assert_eq!(page.total, 1);
+        assert_eq!(page.scripts[0].id, id.to_string());
+        assert_eq!(page.scripts[0].size, 24);
+        assert_eq!(page.scripts[0].executions, 2);
+        assert_eq!(
+            page.scripts[0].expires,
+            Some(page.scripts[0].accessed + 60000)
+        );
+
+        let page = registry.list(&listing::Query::parse(Some("name=add")).unwrap());
+
+        assert_eq!(page.total, 1);
+        assert_eq!(page.scripts[0].id, version.to_string());
+        assert_eq!(page.scripts[0].names, vec!["adder@1".to_string()]);
+        assert_eq!(page.scripts[0].expires, None);
+    }
+
+    #[test]
+    fn test_script_parse() {
+        let script = Script::parse(b"function() { return 3 + 4; }").unwrap();
+        assert_eq!(script.code, "function() { return 3 + 4; }");
+        assert!(!script.deterministic);
+
+        let script =
+            Script::parse(br#"{"code": "function() { return 3 + 4; }", "deterministic": true}"#)
+                .unwrap();
+        assert_eq!(script.code, "function() { return 3 + 4; }");
+        assert_eq!(
+            script.hash,
+            sha256_hex("function() { return 3 + 4; }".as_bytes())
+        );
+        assert!(script.deterministic);
+
+        let script = Script::parse(br#"{"code": "function() {}", "output": "text/html"}"#).unwrap();
+        assert_eq!(script.output, Some("text/html".to_string()));
+
+        assert!(Script::parse(br#"{"code": "function() {}", "output": "html"}"#).is_err());
+
+        let script = Script::parse(
+            br#"{"code": "function(a) { return a; }", "tests": [{"args": [1], "expect": 1}]}"#,
+        )
+        .unwrap();
+        assert_eq!(script.tests.len(), 1);
+
+        assert!(Script::parse(br#"{"code": "function() {}", "tests": [{"args": []}]}"#).is_err());
+        assert!(Script::parse(br#"{"deterministic": true}"#).is_err());
+        assert!(Script::parse(&[0xff, 0xfe]).is_err());
+        assert!(
+            Script::parse(br#"{"code": "function() {}", "ttl_ms": 1, "pinned": true}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_script_registry_expiration_ttl() {
+        let script = |definition: &str| Script::parse(definition.as_bytes()).unwrap();
+        let default = script(r#"{"code": "function() {}"}"#);
+        let short = script(r#"{"code": "function() {}", "ttl_ms": 1000}"#);
+        let long = script(r#"{"code": "function() {}", "ttl_ms": 9000}"#);
+        let pinned = script(r#"{"code": "function() {}", "pinned": true}"#);
+
+        let unbounded = expiration(5000);
+        assert_eq!(unbounded.ttl(&default), Some(Duration::from_millis(5000)));
+        assert_eq!(unbounded.ttl(&short), Some(Duration::from_millis(1000)));
+        assert_eq!(unbounded.ttl(&long), Some(Duration::from_millis(9000)));
+        assert_eq!(unbounded.ttl(&pinned), None);
+
+        let bounded = Expiration {
+            maximum: Some(Duration::from_millis(3000)),
+            ..unbounded
+        };
+        assert_eq!(bounded.ttl(&default), Some(Duration::from_millis(3000)));
+        assert_eq!(bounded.ttl(&short), Some(Duration::from_millis(1000)));
+        assert_eq!(bounded.ttl(&long), Some(Duration::from_millis(3000)));
+        assert_eq!(bounded.ttl(&pinned), Some(Duration::from_millis(3000)));
+    }
+
+    #[test]
+    fn test_script_registry_per_script_expiration() {
+        let mut registry = ScriptRegistry::new(expiration(1), Capacity::default());
+
+        let parse = |definition: &str| Script::parse(definition.as_bytes()).unwrap();
+        let pinned = registry
+            .store(parse(r#"{"code": "function() {}", "pinned": true}"#))
+            .unwrap();
+        let long = registry
+            .store(parse(r#"{"code": "function() {}", "ttl_ms": 60000}"#))
+            .unwrap();
+        let short = registry
+            .store(parse(r#"{"code": "function() {}"}"#))
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let _ = registry
+            .store(Script::new("function() {}".to_string()))
+            .unwrap();
+
+        assert!(registry.get(&pinned).is_some());
+        assert!(registry.get(&long).is_some());
+        assert!(registry.get(&short).is_none());
+
+        let page = registry.list(&listing::Query::parse(Some("sort=expires")).unwrap());
+        let expires = page
+            .scripts
+            .iter()
+            .map(|s| s.expires.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(expires, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_script_registry_capacity() {
+        let capacity = Capacity {
+            entries: Some(3),
+            bytes: Some(64),
+        };
+
+        let mut registry = ScriptRegistry::new(expiration(60000), capacity);
+
+        let script = |code: &str| Script::new(code.to_string());
+        let pinned = || Script::parse(br#"{"code": "function() {}", "pinned": true}"#).unwrap();
+
+        let a = registry.store(script("function() { return 1; }")).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        let b = registry.store(script("function() { return 2; }")).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        let _ = registry.get(&a);
+
+        // Least recently used scripts are evicted first, to stay within
+        // both the number of entries and bytes
+
+        let c = registry.store(pinned()).unwrap();
+        let d = registry.store(script("function() { return 4; }")).unwrap();
+        assert!(registry.get(&b).is_none());
+        assert!(registry.get(&a).is_some());
+
+        let e = registry
+            .store(script(&format!(
+                "function() {{ return '{}'; }}",
+                "x".repeat(18)
+            )))
+            .unwrap();
+        assert!(registry.get(&a).is_none());
+        assert!(registry.get(&d).is_none());
+        assert!(registry.get(&c).is_some());
+        assert!(registry.get(&e).is_some());
+
+        // Once only pinned scripts remain, nothing is stored
+
+        let _ = registry.store(pinned()).unwrap();
+        let _ = registry.store(pinned()).unwrap();
+        assert!(registry.store(script("function() {}")).is_err());
+        assert!(registry.get(&e).is_none());
+        assert!(registry.store(script(&"x".repeat(65))).is_err());
+        assert_eq!(registry.stats().evictions, 4);
+    }
+
     #[test]
     fn test_script_registry_evicts_old_entries() {
-        let mut registry = ScriptRegistry::new(Duration::from_millis(1));
+        let mut registry = ScriptRegistry::new(expiration(1), Capacity::default());
 
         let script = "function() { return 3 + 4; }";
 
-        let id = registry.store(script.to_string());
+        let id = registry.store(Script::new(script.to_string())).unwrap();
 
         // Entries are lazily evicted, so cause eviction by storing a new one
 
         std::thread::sleep(Duration::from_millis(50));
 
-        let _ = registry.store(script.to_string());
+        let _ = registry.store(Script::new(script.to_string())).unwrap();
 
         // Evicted because of 1ms duration
         assert_eq!(registry.get(&id), None);
     }
 
+    #[test]
+    fn test_script_registry_removes_expired_entries() {
+        let mut registry = ScriptRegistry::new(expiration(10), Capacity::default());
+
+        let script = || Script::new("function() { return 3 + 4; }".to_string());
+
+        let read = registry.store(script()).unwrap();
+        let swept = registry.store(script()).unwrap();
+        let (_, version) = registry.store_version("adder", script()).unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Expired scripts are neither read nor listed, even if they haven't
+        // been removed yet
+
+        let page = registry.list(&listing::Query::parse(None).unwrap());
+        assert_eq!(page.total, 1);
+
+        assert_eq!(registry.get(&read), None);
+        assert_eq!(registry.stats().entries, 2);
+
+        assert_eq!(registry.remove_expired(), 1);
+        assert_eq!(registry.remove_expired(), 0);
+        assert!(!registry.contains(&swept));
+        assert!(registry.get(&version).is_some());
+
+        let stats = registry.stats();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.expirations, 2);
+        assert_eq!(stats.evictions, 0);
+    }
+
     #[test]
     fn test_script_registry_get_extends_eviction() {
-        let mut registry = ScriptRegistry::new(Duration::from_millis(10));
+        let mut registry = ScriptRegistry::new(expiration(10000), Capacity::default());
+
+        let script = "function() { return 3 + 4; }";
+
+        // Our script was last accessed most of the expiration time ago, so
+        // it expires within a few seconds. Getting it extends the expiration
+        // time, so it doesn't then
+
+        let id = registry.store(Script::new(script.to_string())).unwrap();
+
+        let now = Instant::now();
+        let later = now + Duration::from_millis(5000);
+
+        registry
+            .store
+            .touch(&id, now - Duration::from_millis(8000), 0)
+            .unwrap();
+
+        assert_eq!(registry.expired(later), vec![id.clone()]);
+
+        assert_eq!(registry.get(&id), Some(Script::new(script.to_string())));
+
+        assert!(registry.expired(later).is_empty());
+    }
+
+    #[test]
+    fn test_script_id() {
+        let id = ScriptId::for_code("function() { return 1; }");
+
+        assert_eq!(
+            id.to_string(),
+            format!("sha256-{}", sha256_hex(b"function() { return 1; }"))
+        );
+        assert_eq!(id.to_string().parse::<ScriptId>().unwrap(), id);
+        assert_eq!(
+            id.to_string()
+                .to_uppercase()
+                .replace("SHA256", "sha256")
+                .parse::<ScriptId>()
+                .unwrap(),
+            id
+        );
+
+        assert_eq!(
+            ScriptId::for_code("\u{feff}\r\nfunction() {\r\n  return 1;\r\n}\n\n"),
+            ScriptId::for_code("function() {\n  return 1;\n}")
+        );
+        assert_ne!(
+            ScriptId::for_code("function() { return 1; }"),
+            ScriptId::for_code("function() { return  1; }")
+        );
+
+        let uuid = "50b0cb8f-1f59-4ba5-8935-ba54bb64bc3f";
+        assert_eq!(uuid.parse::<ScriptId>().unwrap().to_string(), uuid);
+
+        assert_eq!(
+            serde_json::from_str::<ScriptId>(&format!("\"{}\"", id)).unwrap(),
+            id
+        );
+        assert_eq!(serde_json::to_string(&id).unwrap(), format!("\"{}\"", id));
+
+        assert!("sha256-abc".parse::<ScriptId>().is_err());
+        assert!(format!("sha256-{}", "g".repeat(64))
+            .parse::<ScriptId>()
+            .is_err());
+        assert!("by-name/adder".parse::<ScriptId>().is_err());
+    }
+
+    #[test]
+    fn test_script_registry_content_addressed() {
+        let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
+
+        let script =
+            Script::parse(br#"{"code": "function() { return 1; }", "content_addressed": true}"#)
+                .unwrap();
+        let id = registry.store(script.clone()).unwrap();
+
+        assert_eq!(id, ScriptId::for_code("function() { return 1; }"));
+
+        // Storing the same code again keeps the existing script
+
+        let again =
+            Script::parse(br#"{"code": "function() { return 1; }\n", "content_addressed": true}"#)
+                .unwrap();
+
+        assert_eq!(registry.store(again).unwrap(), id);
+        assert_eq!(registry.store.list().count(), 1);
+        assert_eq!(registry.get(&id), Some(script.clone()));
+
+        // ...unless its options differ, which would otherwise be discarded
+
+        let options = vec![
+            r#""deterministic": true"#,
+            r#""output": "text/plain""#,
+            r#""tests": [{"args": [], "expect": 1}]"#,
+            r#""ttl_ms": 1000"#,
+            r#""pinned": true"#,
+            r#""labels": ["billing"]"#,
+        ];
+
+        for option in options {
+            let definition = format!(
+                r#"{{"code": "function() {{ return 1; }}", "content_addressed": true, {}}}"#,
+                option
+            );
+
+            let other = Script::parse(definition.as_bytes()).unwrap();
+            let error = registry.store(other).unwrap_err();
+
+            assert_eq!(error.kind(), io::ErrorKind::AlreadyExists, "{}", option);
+            assert_eq!(registry.get(&id), Some(script.clone()));
+        }
+
+        let random = registry
+            .store(Script::new("function() { return 1; }".to_string()))
+            .unwrap();
+
+        assert_ne!(random, id);
+        assert_eq!(registry.store.list().count(), 2);
+    }
+
+    #[test]
+    fn test_script_registry_versions() {
+        let mut registry = ScriptRegistry::new(expiration(1), Capacity::default());
+
+        let (v1, id1) = registry
+            .store_version("adder", Script::new("function() { return 1; }".to_string()))
+            .unwrap();
+        let (v2, id2) = registry
+            .store_version("adder", Script::new("function() { return 2; }".to_string()))
+            .unwrap();
+
+        assert_eq!((v1, v2), (1, 2));
+        assert_eq!(
+            registry.lookup("by-name/adder").map(|s| s.0),
+            Some(id2.clone())
+        );
+        assert_eq!(
+            registry.lookup("by-name/adder@1").map(|s| s.0),
+            Some(id1.clone())
+        );
+        assert_eq!(
+            registry.lookup(&id1.to_string()).map(|s| s.0),
+            Some(id1.clone())
+        );
+        assert!(registry.lookup("by-name/adder@3").is_none());
+        assert!(registry.lookup("by-name/adder@stable").is_none());
+        assert!(registry.lookup("by-name/subtractor").is_none());
+
+        assert!(registry.set_alias("adder", "stable", 2).unwrap());
+        assert!(!registry.set_alias("adder", "stable", 1).unwrap());
+        assert!(registry.set_alias("adder", "stable", 3).is_err());
+        assert!(registry.set_alias("subtractor", "stable", 1).is_err());
+
+        assert_eq!(
+            registry.lookup("by-name/adder@stable").map(|s| s.0),
+            Some(id1.clone())
+        );
+
+        // Versions aren't evicted, unlike other scripts
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        let _ = registry
+            .store(Script::new("function() {}".to_string()))
+            .unwrap();
+
+        assert!(registry.is_version(&id1));
+        assert!(registry.get(&id1).is_some());
+
+        assert!(registry.remove_name("adder").unwrap());
+        assert!(!registry.remove_name("adder").unwrap());
+        assert!(!registry.is_version(&id1));
+        assert!(registry.lookup("by-name/adder").is_none());
+    }
+
+    #[test]
+    fn test_script_registry_validate_names() {
+        assert!(validate_name("orders-v2.total_1").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name("a@b").is_err());
+        assert!(validate_alias("stable").is_ok());
+        assert!(validate_alias("2").is_err());
+
+        assert!(is_reference(&ScriptId::random().to_string()));
+        assert!(is_reference("by-name/adder"));
+        assert!(is_reference("by-name/adder@2"));
+        assert!(is_reference("by-name/adder@stable"));
+        assert!(!is_reference("lint"));
+        assert!(!is_reference("by-name/"));
+        assert!(!is_reference("by-name/adder@"));
+        assert!(!is_reference("by-name/adder/aliases/stable"));
+        assert!(!is_reference(&format!("{}/jobs", ScriptId::random())));
+    }
+
+    #[test]
+    fn test_script_registry_survives_restart() {
+        let dir = crate::persistence::tests::data_dir();
 
         let script = "function() { return 3 + 4; }";
 
-        // Since entries are lazily evicted, wait 100ms so that we know
-        // we'd be evicted, get our script, then immediately store one.
-        // We expect to be able to get our original script then, since
-        // getting it extended the eviction time
+        let (kept, removed) = {
+            let mut registry =
+                ScriptRegistry::open(expiration(60000), Capacity::default(), file_store(&dir, 2))
+                    .unwrap();
+
+            let kept = registry.store(Script::new(script.to_string())).unwrap();
+            let removed = registry.store(Script::new(script.to_string())).unwrap();
+            let _ = registry.store(Script::new(script.to_string())).unwrap();
+
+            registry.remove(&removed).unwrap();
+
+            registry
+                .store_version("adder", Script::new(script.to_string()))
+                .unwrap();
+
+            (kept, removed)
+        };
+
+        let mut registry =
+            ScriptRegistry::open(expiration(60000), Capacity::default(), file_store(&dir, 2))
+                .unwrap();
+
+        assert_eq!(registry.store.list().count(), 3);
+        assert_eq!(registry.get(&kept), Some(Script::new(script.to_string())));
+        assert_eq!(registry.get(&removed), None);
+
+        // Scripts that expired while the registry was stopped are discarded
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        let mut registry =
+            ScriptRegistry::open(expiration(10), Capacity::default(), file_store(&dir, 2)).unwrap();
+
+        assert_eq!(registry.store.list().count(), 1);
+        assert!(registry.lookup("by-name/adder@1").is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_script_registry_export_and_import() {
+        let dir = crate::persistence::tests::data_dir();
+
+        let mut source = ScriptRegistry::new(expiration(60000), Capacity::default());
+
+        let pinned = Script::parse(br#"{"code": "function() {}", "pinned": true}"#).unwrap();
+        let pinned = source.store(pinned).unwrap();
+        let (_, version) = source
+            .store_version("adder", Script::new("function() { return 1; }".to_string()))
+            .unwrap();
+        source.executed(&pinned);
+
+        let bundle = source.export();
+        assert_eq!(bundle.scripts.len(), 2);
+        assert_eq!(bundle.names.len(), 1);
+
+        let json = serde_json::to_string(&bundle).unwrap();
+        let bundle = || serde_json::from_str::<Bundle>(&json).unwrap();
+
+        // Merging keeps the scripts that are already in the registry, and
+        // the imported scripts keep their ids and metadata
+
+        {
+            let mut target = ScriptRegistry::open(
+                expiration(60000),
+                Capacity::default(),
+                file_store(&dir, 100),
+            )
+            .unwrap();
+
+            let existing = target
+                .store(Script::new("function() { return 2; }".to_string()))
+                .unwrap();
+
+            let imported = target.import(bundle(), Mode::Merge).unwrap();
+            assert_eq!(
+                imported,
+                Imported {
+                    scripts: 2,
+                    names: 1
+                }
+            );
+
+            assert!(target.get(&existing).is_some());
+            assert!(target.get(&pinned).unwrap().pinned);
+            assert_eq!(target.lookup("by-name/adder").unwrap().0, version);
+            assert_eq!(target.store.get(&pinned).unwrap().executions, 1);
+        }
+
+        // Imports are persisted, and replacing removes the other scripts
+
+        let mut target = ScriptRegistry::open(
+            expiration(60000),
+            Capacity::default(),
+            file_store(&dir, 100),
+        )
+        .unwrap();
+        assert_eq!(target.store.list().count(), 3);
+
+        target.import(bundle(), Mode::Replace).unwrap();
+        assert_eq!(target.store.list().count(), 2);
+
+        // Nothing is imported if the registry cannot hold the bundle
+
+        let capacity = Capacity {
+            entries: Some(1),
+            bytes: None,
+        };
+
+        let mut full = ScriptRegistry::new(expiration(60000), capacity);
+        let existing = full
+            .store(Script::new("function() {}".to_string()))
+            .unwrap();
+        assert!(full.import(bundle(), Mode::Merge).is_err());
+        assert!(full.import(bundle(), Mode::Replace).is_err());
+        assert!(full.get(&existing).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_script_registry_replication() {
+        let replica = || {
+            let mut registry = ScriptRegistry::new(expiration(60000), Capacity::default());
+            registry.replicate();
+            registry
+        };
+
+        let mut a = replica();
+        let mut b = replica();
+
+        // Changes that are sent to a peer are applied there, once
+
+        let id = a
+            .store(Script::new("function() { return 1; }".to_string()))
+            .unwrap();
+        let (_, version) = a
+            .store_version("adder", Script::new("function() { return 2; }".to_string()))
+            .unwrap();
+
+        let changes = a.take_changes();
+        assert_eq!(changes.len(), 3);
+        assert!(a.take_changes().is_empty());
+
+        assert_eq!(b.apply(changes.clone()), 3);
+        assert_eq!(b.apply(changes), 0);
+        assert!(b.get(&id).is_some());
+        assert_eq!(b.lookup("by-name/adder").unwrap().0, version);
+        assert!(b.take_changes().is_empty());
+
+        // Changes that are missed while peers are cut off from each other
+        // are caught up on when they resync
+
+        a.remove(&id).unwrap();
+        assert!(a.remove_name("adder").unwrap());
+        a.take_changes();
+
+        let other = b
+            .store(Script::new("function() { return 3; }".to_string()))
+            .unwrap();
+        b.take_changes();
+
+        assert_eq!(b.apply(a.changes_for(&b.digest())), 2);
+        assert_eq!(a.apply(b.changes_for(&a.digest())), 1);
+
+        assert!(b.get(&id).is_none());
+        assert!(b.named("adder").is_none());
+        assert!(a.get(&other).is_some());
+
+        assert!(a.changes_for(&b.digest()).is_empty());
+        assert!(b.changes_for(&a.digest()).is_empty());
+
+        // Registries that aren't replicated ignore changes
+
+        let mut c = ScriptRegistry::new(expiration(60000), Capacity::default());
+        c.store(Script::new("function() {}".to_string())).unwrap();
+        assert!(c.take_changes().is_empty());
+        assert_eq!(c.apply(b.changes_for(&c.digest())), 0);
+    }
+
+    #[test]
+    fn test_script_registry_events() {
+        let capacity = Capacity {
+            entries: Some(2),
+            bytes: None,
+        };
+
+        let mut registry = ScriptRegistry::new(expiration(60000), capacity);
+
+        let script = || Script::new("function() {}".to_string());
+
+        let a = registry.store(script()).unwrap();
+        registry.replace(&a, script()).unwrap();
+        let (_, b) = registry.store_version("adder", script()).unwrap();
+        registry.set_alias("adder", "stable", 1).unwrap();
+        let c = registry.store(script()).unwrap();
+        registry.remove(&c).unwrap();
+        registry.remove_name("adder").unwrap();
+
+        assert_eq!(
+            registry.take_events(),
+            vec![
+                Kind::Created { id: a.clone() },
+                Kind::Updated { id: a.clone() },
+                Kind::Created { id: b },
+                Kind::NameUpdated {
+                    name: "adder".to_string()
+                },
+                Kind::NameUpdated {
+                    name: "adder".to_string()
+                },
+                Kind::Evicted { id: a },
+                Kind::Created { id: c.clone() },
+                Kind::Deleted { id: c },
+                Kind::NameDeleted {
+                    name: "adder".to_string()
+                },
+            ]
+        );
+
+        assert!(registry.take_events().is_empty());
+
+        let mut registry = ScriptRegistry::new(expiration(1), Capacity::default());
+        let id = registry.store(script()).unwrap();
+        registry.take_events();
+
+        std::thread::sleep(Duration::from_millis(10));
+        registry.remove_expired();
+
+        assert_eq!(registry.take_events(), vec![Kind::Expired { id }]);
+    }
+
+    /// A store that holds scripts, but fails to change them.
+    struct ReadOnlyStore(crate::store::MemoryStore);
+
+    impl ScriptStore for ReadOnlyStore {
+        fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+            self.0.get(id)
+        }
+
+        fn store(&mut self, _: ScriptId, _: PersistedScript) -> io::Result<()> {
+            Err(read_only())
+        }
+
+        fn remove(&mut self, _: &ScriptId) -> io::Result<Option<PersistedScript>> {
+            Err(read_only())
+        }
+
+        fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+            self.0.list()
+        }
+
+        fn touch(&mut self, _: &ScriptId, _: SystemTime, _: u64) -> io::Result<()> {
+            Err(read_only())
+        }
+
+        fn names(&self) -> io::Result<Vec<(String, Named)>> {
+            self.0.names()
+        }
+
+        fn store_name(&mut self, _: String, _: Named) -> io::Result<()> {
+            Err(read_only())
+        }
+
+        fn remove_name(&mut self, _: &str) -> io::Result<Option<Named>> {
+            Err(read_only())
+        }
+    }
+
+    fn read_only() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "read-only file system")
+    }
+
+    /// A store that fails every change after the first `changes` of them.
+    struct FailingStore {
+        inner: crate::store::MemoryStore,
+        changes: usize,
+    }
+
+    impl FailingStore {
+        fn change(&mut self) -> io::Result<()> {
+            if self.changes == 0 {
+                return Err(read_only());
+            }
+
+            self.changes -= 1;
+
+            Ok(())
+        }
+    }
+
+    impl ScriptStore for FailingStore {
+        fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+            self.inner.get(id)
+        }
+
+        fn store(&mut self, id: ScriptId, script: PersistedScript) -> io::Result<()> {
+            self.change()?;
+            self.inner.store(id, script)
+        }
+
+        fn remove(&mut self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+            self.change()?;
+            self.inner.remove(id)
+        }
+
+        fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+            self.inner.list()
+        }
+
+        fn touch(
+            &mut self,
+            id: &ScriptId,
+            accessed: SystemTime,
+            executions: u64,
+        ) -> io::Result<()> {
+            self.inner.touch(id, accessed, executions)
+        }
+
+        fn names(&self) -> io::Result<Vec<(String, Named)>> {
+            self.inner.names()
+        }
+
+        fn store_name(&mut self, name: String, named: Named) -> io::Result<()> {
+            self.change()?;
+            self.inner.store_name(name, named)
+        }
+
+        fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>> {
+            self.change()?;
+            self.inner.remove_name(name)
+        }
+    }
+
+    #[test]
+    fn test_script_registry_import_failure() {
+        let mut source = ScriptRegistry::new(expiration(60000), Capacity::default());
+
+        let imported = vec![
+            source
+                .store(Script::new("function() { return 1; }".to_string()))
+                .unwrap(),
+            source
+                .store(Script::new("function() { return 2; }".to_string()))
+                .unwrap(),
+        ];
+
+        let bundle = source.export();
+
+        let mut store = crate::store::MemoryStore::default();
+        let existing = ScriptId::random();
+
+        store
+            .store(existing.clone(), persisted("function() {}"))
+            .unwrap();
+
+        // The existing script is removed, and one of the bundle's scripts is
+        // stored, before the store fails
+
+        let mut registry = ScriptRegistry::open(
+            expiration(60000),
+            Capacity::default(),
+            Box::new(FailingStore {
+                inner: store,
+                changes: 2,
+            }),
+        )
+        .unwrap();
+
+        let error = registry.import(bundle, Mode::Replace).unwrap_err();
+        assert!(StoreError::is(&error));
+
+        let events = registry.take_events();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], Kind::Deleted { id: existing });
+
+        match events[1] {
+            Kind::Created { ref id } => assert!(imported.contains(id)),
+            ref other => panic!("unexpected event {:?}", other),
+        }
+
+        assert_eq!(registry.stats().entries, 1);
+    }
+
+    #[test]
+    fn test_script_registry_store_failure() {
+        let mut store = crate::store::MemoryStore::default();
+        let id = ScriptId::random();
+
+        store.store(id.clone(), persisted("function() {}")).unwrap();
+
+        store
+            .store_name(
+                "adder".to_string(),
+                Named {
+                    versions: vec![id.clone()],
+                    ..Named::default()
+                },
+            )
+            .unwrap();
+
+        let mut registry = ScriptRegistry::open(
+            expiration(60000),
+            Capacity::default(),
+            Box::new(ReadOnlyStore(store)),
+        )
+        .unwrap();
+
+        let script = || Script::new("function() {}".to_string());
+
+        let errors = vec![
+            registry.store(script()).unwrap_err(),
+            registry.replace(&id, script()).unwrap_err(),
+            registry.store_version("adder", script()).unwrap_err(),
+            registry.remove(&id).unwrap_err(),
+            registry.remove_name("adder").unwrap_err(),
+        ];
+
+        for error in errors {
+            assert!(StoreError::is(&error));
+            assert!(error.to_string().contains("read-only file system"));
+        }
+
+        // Reads still succeed, even though they cannot be recorded
 
-        let id = registry.store(script.to_string());
+        assert_eq!(registry.get(&id), Some(script()));
+        assert_eq!(registry.stats().entries, 1);
+        assert!(registry.named("adder").is_some());
+        assert!(registry.take_events().is_empty());
 
-        std::thread::sleep(Duration::from_millis(100));
+        // Errors of the registry's own aren't mistaken for the store's
 
-        let _ = registry.get(&id);
+        let capacity = Capacity {
+            entries: Some(0),
+            bytes: None,
+        };
 
-        let _ = registry.store(script.to_string());
+        let mut full = ScriptRegistry::new(expiration(60000), capacity);
 
-        assert_eq!(registry.get(&id), Some(script.to_string()));
+        assert!(!StoreError::is(&full.store(script()).unwrap_err()));
     }
 }
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::io;
+
+/// A test case that's stored with a script: the arguments to call it with,
+/// and either the result that it must return, that it must return
+/// `undefined`, or text that its error must contain.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TestCase {
+    pub args: Value,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expect: Option<Value>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub expect_undefined: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of running one test case, along with what the script did.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CaseOutcome {
+    index: usize,
+    passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "is_false")]
+    undefined: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The outcome of running a script's test cases.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub passed: bool,
+    cases: Vec<CaseOutcome>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Distinguishes an expected result of `null` from a missing one.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Checks that each test case has an array of arguments, and exactly one
+/// of an expected result, `expect_undefined` or an expected error.
+pub fn validate(tests: &[TestCase]) -> io::Result<()> {
+    let valid = tests.iter().all(|t| {
+        let expectations = [t.expect.is_some(), t.expect_undefined, t.error.is_some()];
+
+        t.args.is_array() && expectations.iter().filter(|e| **e).count() == 1
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "tests must have an array of args, and one of an expected result, expect_undefined or error",
+        ))
+    }
+}
+
+/// Runs test cases, given a function that evaluates the script with a
+/// JSON-encoded array of arguments, yielding `None` if it returns
+/// `undefined`.
+pub fn run<F>(tests: &[TestCase], mut eval: F) -> Report
+where
+    F: FnMut(&str) -> io::Result<Option<String>>,
+{
+    let cases: Vec<CaseOutcome> = tests
+        .iter()
+        .enumerate()
+        .map(|(index, test)| {
+            let (result, undefined, error) = match eval(&test.args.to_string()) {
+                Ok(Some(json)) => (serde_json::from_str::<Value>(&json).ok(), false, None),
+                Ok(None) => (None, true, None),
+                Err(e) => (None, false, Some(e.to_string())),
+            };
+
+            let passed = match (&test.expect, &test.error, &result, &error) {
+                (Some(expected), _, Some(result), _) => same(expected, result),
+                (_, Some(expected), _, Some(error)) => error.contains(expected.as_str()),
+                _ => test.expect_undefined && undefined,
+            };
+
+            CaseOutcome {
+                index,
+                passed,
+                result,
+                undefined,
+                error,
+            }
+        })
+        .collect();
+
+    Report {
+        passed: cases.iter().all(|c| c.passed),
+        cases,
+    }
+}
+
+/// Compares values as JavaScript would produce them, i.e. numbers are
+/// compared by value regardless of how they're written.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).map(|w| same(v, w)).unwrap_or(false))
+        }
+
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cases(json: &str) -> Vec<TestCase> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_self_test_validate() {
+        assert!(validate(&cases(r#"[{"args": [1], "expect": null}]"#)).is_ok());
+        assert!(validate(&cases(r#"[{"args": [], "error": "TypeError"}]"#)).is_ok());
+        assert!(validate(&cases(r#"[{"args": [1]}]"#)).is_err());
+        assert!(validate(&cases(r#"[{"args": 1, "expect": 1}]"#)).is_err());
+        assert!(validate(&cases(r#"[{"args": [], "expect": 1, "error": "e"}]"#)).is_err());
+        assert!(validate(&cases(r#"[{"args": [], "expect_undefined": true}]"#)).is_ok());
+        assert!(validate(&cases(r#"[{"args": [], "expect_undefined": false}]"#)).is_err());
+        assert!(validate(&cases(
+            r#"[{"args": [], "expect": null, "expect_undefined": true}]"#
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_self_test_run() {
+        let tests = cases(
+            r#"[
+                {"args": [1, 2], "expect": 3.0},
+                {"args": [{"a": [1]}], "expect": {"a": [1]}},
+                {"args": ["x"], "error": "TypeError"},
+                {"args": [2, 2], "expect": 5},
+                {"args": [], "expect_undefined": true},
+                {"args": [], "expect": null}
+            ]"#,
+        );
+
+        let report = run(&tests, |args| match args {
+            "[1,2]" => Ok(Some("3".to_string())),
+            "[{\"a\":[1]}]" => Ok(Some("{\"a\":[1]}".to_string())),
+            "[\"x\"]" => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "TypeError: not a number",
+            )),
+            "[]" => Ok(None),
+            _ => Ok(Some("4".to_string())),
+        });
+
+        assert!(!report.passed);
+
+        assert_eq!(
+            report.cases.iter().map(|c| c.passed).collect::<Vec<_>>(),
+            vec![true, true, true, false, true, false]
+        );
+
+        assert_eq!(
+            report.cases[3],
+            CaseOutcome {
+                index: 3,
+                passed: false,
+                result: Some(Value::from(4)),
+                undefined: false,
+                error: None,
+            }
+        );
+
+        // undefined isn't null
+        assert_eq!(
+            report.cases[5],
+            CaseOutcome {
+                index: 5,
+                passed: false,
+                result: None,
+                undefined: true,
+                error: None,
+            }
+        );
+    }
+}
@@ -1,39 +1,223 @@
+use crate::store;
 use num_cpus;
+use std::collections::BTreeMap;
 use std::{env, fmt, io, net, path, time};
 
 const DEFAULT_BIND_ADDR: &str = "127.0.0.1:9412";
+const DEFAULT_DATA_SNAPSHOT_RECORDS: &str = "10000";
+const DEFAULT_DATA_STORE: &str = "";
+const DEFAULT_EVENTS_HISTORY: &str = "1000";
+const DEFAULT_EVENTS_WEBHOOK_URL: &str = "";
+const DEFAULT_JOB_EXECUTION_COMPLETION_TIME: &str = "3600000";
+const DEFAULT_JOB_MAX_PENDING: &str = "1000";
+const DEFAULT_JOB_RETENTION_TIME: &str = "3600000";
+const DEFAULT_NAMESPACES: &str = "{}";
+const DEFAULT_NDJSON_MAX_LINE_LENGTH: &str = "1048576";
+const DEFAULT_REPLICATION_PEERS: &str = "";
+const DEFAULT_REPLICATION_RESYNC_INTERVAL: &str = "30000";
+const DEFAULT_REPLICATION_SECRET: &str = "";
+const DEFAULT_REQUEST_MAX_ARGUMENTS_BYTES: &str = "1048576";
+const DEFAULT_REQUEST_MAX_BATCH_BYTES: &str = "67108864";
+const DEFAULT_REQUEST_MAX_SCRIPT_BYTES: &str = "1048576";
+const DEFAULT_RESULT_CACHE_MAX_BYTES: &str = "67108864";
+const DEFAULT_RESULT_CACHE_TTL: &str = "60000";
 const DEFAULT_SCRIPT_DEFINITION_EXPIRATION_TIME: &str = "86400000";
+const DEFAULT_SCRIPT_DEFINITION_MAX_BYTES: &str = "0";
+const DEFAULT_SCRIPT_DEFINITION_MAX_COUNT: &str = "0";
+const DEFAULT_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME: &str = "0";
+const DEFAULT_SCRIPT_DEFINITION_SWEEP_INTERVAL: &str = "60000";
 const DEFAULT_SCRIPT_EXECUTION_COMPLETION_TIME: &str = "10000";
+const DEFAULT_SCRIPT_EXECUTION_MAX_COMPLETION_TIME: &str = "60000";
+const DEFAULT_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT: &str = "0";
+const DEFAULT_SCRIPT_EXECUTION_MEMORY_LIMIT: &str = "0";
 const DEFAULT_SCRIPT_EXECUTION_THREAD_POOL_SIZE: &str = "0";
 
 /// Represents the settings for the program. These are sourced
 /// strictly from environment variables.
+#[derive(Clone)]
 pub(crate) struct Settings {
     pub(crate) bind_addr: net::SocketAddr,
+    pub(crate) data_dir: Option<path::PathBuf>,
+    pub(crate) data_snapshot_records: usize,
+    pub(crate) data_store: store::Backend,
+    pub(crate) events_history: usize,
+    pub(crate) events_webhook_url: Option<hyper::Uri>,
+    pub(crate) job_execution_completion_time: time::Duration,
+    pub(crate) job_max_pending: usize,
+    pub(crate) job_retention_time: time::Duration,
+    pub(crate) namespaces: BTreeMap<String, NamespaceSettings>,
+    pub(crate) ndjson_max_line_length: usize,
+    pub(crate) replication_peers: Vec<hyper::Uri>,
+    pub(crate) replication_resync_interval: time::Duration,
+    pub(crate) replication_secret: Option<String>,
+    pub(crate) request_max_arguments_bytes: usize,
+    pub(crate) request_max_batch_bytes: usize,
+    pub(crate) request_max_script_bytes: usize,
+    pub(crate) result_cache_max_bytes: usize,
+    pub(crate) result_cache_ttl: time::Duration,
     pub(crate) script_definition_expiration_time: time::Duration,
+    pub(crate) script_definition_max_expiration_time: time::Duration,
+    pub(crate) script_definition_max_count: usize,
+    pub(crate) script_definition_max_bytes: usize,
+    pub(crate) script_definition_sweep_interval: time::Duration,
     pub(crate) script_execution_completion_time: time::Duration,
+    pub(crate) script_execution_max_completion_time: time::Duration,
+    pub(crate) script_execution_max_memory_limit: usize,
+    pub(crate) script_execution_memory_limit: usize,
     pub(crate) script_execution_thread_pool_size: usize,
     pub(crate) tls_bind_addr: Option<net::SocketAddr>,
     pub(crate) tls_public_certificate_path: Option<path::PathBuf>,
     pub(crate) tls_private_key_path: Option<path::PathBuf>,
 }
 
+/// The settings of a namespace, which override those of the server. Times
+/// are in milliseconds, and as for the server, a maximum of 0 signifies
+/// that there's no maximum.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NamespaceSettings {
+    pub(crate) events_webhook_url: Option<String>,
+    pub(crate) job_execution_completion_time: Option<u64>,
+    pub(crate) job_max_pending: Option<usize>,
+    pub(crate) result_cache_max_bytes: Option<usize>,
+    pub(crate) script_definition_expiration_time: Option<u64>,
+    pub(crate) script_definition_max_expiration_time: Option<u64>,
+    pub(crate) script_definition_max_count: Option<usize>,
+    pub(crate) script_definition_max_bytes: Option<usize>,
+    pub(crate) script_execution_completion_time: Option<u64>,
+    pub(crate) script_execution_max_completion_time: Option<u64>,
+    pub(crate) script_execution_max_memory_limit: Option<usize>,
+    pub(crate) script_execution_memory_limit: Option<usize>,
+}
+
+impl NamespaceSettings {
+    /// Returns the server's settings, overridden by those of the namespace.
+    pub(crate) fn apply(&self, settings: &Settings) -> Settings {
+        let millis = |ms: Option<u64>, default: time::Duration| {
+            ms.map(time::Duration::from_millis).unwrap_or(default)
+        };
+
+        // A namespace's webhook replaces the server's, and an empty URL
+        // signifies that the namespace has none
+
+        let events_webhook_url = match self.events_webhook_url {
+            Some(ref url) if url.trim().is_empty() => None,
+            Some(ref url) => parse_http_url(url.trim(), "webhook").ok(),
+            None => settings.events_webhook_url.clone(),
+        };
+
+        Settings {
+            events_webhook_url,
+            job_execution_completion_time: millis(
+                self.job_execution_completion_time,
+                settings.job_execution_completion_time,
+            ),
+            job_max_pending: self.job_max_pending.unwrap_or(settings.job_max_pending),
+            result_cache_max_bytes: self
+                .result_cache_max_bytes
+                .unwrap_or(settings.result_cache_max_bytes),
+            script_definition_expiration_time: millis(
+                self.script_definition_expiration_time,
+                settings.script_definition_expiration_time,
+            ),
+            script_definition_max_expiration_time: millis(
+                self.script_definition_max_expiration_time,
+                settings.script_definition_max_expiration_time,
+            ),
+            script_definition_max_count: self
+                .script_definition_max_count
+                .unwrap_or(settings.script_definition_max_count),
+            script_definition_max_bytes: self
+                .script_definition_max_bytes
+                .unwrap_or(settings.script_definition_max_bytes),
+            script_execution_completion_time: millis(
+                self.script_execution_completion_time,
+                settings.script_execution_completion_time,
+            ),
+            script_execution_max_completion_time: millis(
+                self.script_execution_max_completion_time,
+                settings.script_execution_max_completion_time,
+            ),
+            script_execution_max_memory_limit: self
+                .script_execution_max_memory_limit
+                .unwrap_or(settings.script_execution_max_memory_limit),
+            script_execution_memory_limit: self
+                .script_execution_memory_limit
+                .unwrap_or(settings.script_execution_memory_limit),
+            ..settings.clone()
+        }
+    }
+}
+
 fn to_io_error<T, E: fmt::Display>(result: Result<T, E>) -> io::Result<T> {
     result.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}", e)))
 }
 
+/// Parses a comma-separated list of the URLs of peers, which are reached
+/// over HTTP.
+fn parse_peers(peers: &str) -> io::Result<Vec<hyper::Uri>> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| parse_http_url(peer, "peer"))
+        .collect()
+}
+
+/// Parses a URL that's reached over HTTP, which `what` describes in errors.
+fn parse_http_url(url: &str, what: &str) -> io::Result<hyper::Uri> {
+    let uri = to_io_error(url.parse::<hyper::Uri>())?;
+
+    if uri.scheme_part().map(|s| s.as_str()) == Some("http") && uri.authority_part().is_some() {
+        Ok(uri)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} must be an http URL", what, url),
+        ))
+    }
+}
+
 impl Settings {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         env_jsaas_bind_addr: &str,
+        env_jsaas_data_dir: &str,
+        env_jsaas_data_snapshot_records: &str,
+        env_jsaas_data_store: &str,
+        env_jsaas_events_history: &str,
+        env_jsaas_events_webhook_url: &str,
+        env_jsaas_job_execution_completion_time: &str,
+        env_jsaas_job_max_pending: &str,
+        env_jsaas_job_retention_time: &str,
+        env_jsaas_namespaces: &str,
+        env_jsaas_ndjson_max_line_length: &str,
+        env_jsaas_replication_peers: &str,
+        env_jsaas_replication_resync_interval: &str,
+        env_jsaas_replication_secret: &str,
+        env_jsaas_request_max_arguments_bytes: &str,
+        env_jsaas_request_max_batch_bytes: &str,
+        env_jsaas_request_max_script_bytes: &str,
+        env_jsaas_result_cache_max_bytes: &str,
+        env_jsaas_result_cache_ttl: &str,
         env_jsaas_script_definition_expiration_time: &str,
+        env_jsaas_script_definition_max_expiration_time: &str,
+        env_jsaas_script_definition_max_count: &str,
+        env_jsaas_script_definition_max_bytes: &str,
+        env_jsaas_script_definition_sweep_interval: &str,
         env_jsaas_script_execution_thread_pool_size: &str,
         env_jsaas_script_execution_completion_time: &str,
+        env_jsaas_script_execution_max_completion_time: &str,
+        env_jsaas_script_execution_max_memory_limit: &str,
+        env_jsaas_script_execution_memory_limit: &str,
         env_jsaas_tls_bind_addr: &str,
         env_jsaas_tls_public_certificate_path: &str,
         env_jsaas_tls_private_key_path: &str,
     ) -> io::Result<Settings> {
         let bind_addr =
             env::var(env_jsaas_bind_addr).unwrap_or_else(|_| DEFAULT_BIND_ADDR.to_string());
+        let ndjson_max_line_length = env::var(env_jsaas_ndjson_max_line_length)
+            .unwrap_or_else(|_| DEFAULT_NDJSON_MAX_LINE_LENGTH.to_string());
         let script_definition_expiration_time =
             env::var(env_jsaas_script_definition_expiration_time)
                 .unwrap_or_else(|_| DEFAULT_SCRIPT_DEFINITION_EXPIRATION_TIME.to_string());
@@ -42,14 +226,128 @@ impl Settings {
         let script_execution_thread_pool_size =
             env::var(env_jsaas_script_execution_thread_pool_size)
                 .unwrap_or_else(|_| DEFAULT_SCRIPT_EXECUTION_THREAD_POOL_SIZE.to_string());
+        let job_execution_completion_time = env::var(env_jsaas_job_execution_completion_time)
+            .unwrap_or_else(|_| DEFAULT_JOB_EXECUTION_COMPLETION_TIME.to_string());
+        let job_retention_time = env::var(env_jsaas_job_retention_time)
+            .unwrap_or_else(|_| DEFAULT_JOB_RETENTION_TIME.to_string());
+        let result_cache_max_bytes = env::var(env_jsaas_result_cache_max_bytes)
+            .unwrap_or_else(|_| DEFAULT_RESULT_CACHE_MAX_BYTES.to_string());
+        let result_cache_ttl = env::var(env_jsaas_result_cache_ttl)
+            .unwrap_or_else(|_| DEFAULT_RESULT_CACHE_TTL.to_string());
+        let script_execution_max_completion_time =
+            env::var(env_jsaas_script_execution_max_completion_time)
+                .unwrap_or_else(|_| DEFAULT_SCRIPT_EXECUTION_MAX_COMPLETION_TIME.to_string());
+        let script_execution_max_memory_limit =
+            env::var(env_jsaas_script_execution_max_memory_limit)
+                .unwrap_or_else(|_| DEFAULT_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT.to_string());
+        let script_execution_memory_limit = env::var(env_jsaas_script_execution_memory_limit)
+            .unwrap_or_else(|_| DEFAULT_SCRIPT_EXECUTION_MEMORY_LIMIT.to_string());
+        let data_snapshot_records = env::var(env_jsaas_data_snapshot_records)
+            .unwrap_or_else(|_| DEFAULT_DATA_SNAPSHOT_RECORDS.to_string());
+        let script_definition_max_expiration_time =
+            env::var(env_jsaas_script_definition_max_expiration_time)
+                .unwrap_or_else(|_| DEFAULT_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME.to_string());
+        let script_definition_max_count = env::var(env_jsaas_script_definition_max_count)
+            .unwrap_or_else(|_| DEFAULT_SCRIPT_DEFINITION_MAX_COUNT.to_string());
+        let script_definition_max_bytes = env::var(env_jsaas_script_definition_max_bytes)
+            .unwrap_or_else(|_| DEFAULT_SCRIPT_DEFINITION_MAX_BYTES.to_string());
+        let script_definition_sweep_interval = env::var(env_jsaas_script_definition_sweep_interval)
+            .unwrap_or_else(|_| DEFAULT_SCRIPT_DEFINITION_SWEEP_INTERVAL.to_string());
+        let namespaces =
+            env::var(env_jsaas_namespaces).unwrap_or_else(|_| DEFAULT_NAMESPACES.to_string());
+        let request_max_arguments_bytes = env::var(env_jsaas_request_max_arguments_bytes)
+            .unwrap_or_else(|_| DEFAULT_REQUEST_MAX_ARGUMENTS_BYTES.to_string());
+        let request_max_batch_bytes = env::var(env_jsaas_request_max_batch_bytes)
+            .unwrap_or_else(|_| DEFAULT_REQUEST_MAX_BATCH_BYTES.to_string());
+        let request_max_script_bytes = env::var(env_jsaas_request_max_script_bytes)
+            .unwrap_or_else(|_| DEFAULT_REQUEST_MAX_SCRIPT_BYTES.to_string());
+        let replication_peers = env::var(env_jsaas_replication_peers)
+            .unwrap_or_else(|_| DEFAULT_REPLICATION_PEERS.to_string());
+        let replication_resync_interval = env::var(env_jsaas_replication_resync_interval)
+            .unwrap_or_else(|_| DEFAULT_REPLICATION_RESYNC_INTERVAL.to_string());
+        let data_store =
+            env::var(env_jsaas_data_store).unwrap_or_else(|_| DEFAULT_DATA_STORE.to_string());
+        let events_history = env::var(env_jsaas_events_history)
+            .unwrap_or_else(|_| DEFAULT_EVENTS_HISTORY.to_string());
+        let events_webhook_url = env::var(env_jsaas_events_webhook_url)
+            .unwrap_or_else(|_| DEFAULT_EVENTS_WEBHOOK_URL.to_string());
+        let job_max_pending = env::var(env_jsaas_job_max_pending)
+            .unwrap_or_else(|_| DEFAULT_JOB_MAX_PENDING.to_string());
+        let replication_secret = env::var(env_jsaas_replication_secret)
+            .unwrap_or_else(|_| DEFAULT_REPLICATION_SECRET.to_string());
 
         let bind_addr = to_io_error(bind_addr.parse::<net::SocketAddr>())?;
+        let ndjson_max_line_length = to_io_error(ndjson_max_line_length.parse::<usize>())?;
         let script_definition_expiration_time_ms =
             to_io_error(script_definition_expiration_time.parse::<u64>())?;
         let script_execution_thread_pool_size =
             to_io_error(script_execution_thread_pool_size.parse::<usize>())?;
         let script_execution_completion_time_ms =
             to_io_error(script_execution_completion_time.parse::<u64>())?;
+        let job_execution_completion_time =
+            time::Duration::from_millis(to_io_error(job_execution_completion_time.parse::<u64>())?);
+        let job_retention_time =
+            time::Duration::from_millis(to_io_error(job_retention_time.parse::<u64>())?);
+        let result_cache_max_bytes = to_io_error(result_cache_max_bytes.parse::<usize>())?;
+        let result_cache_ttl =
+            time::Duration::from_millis(to_io_error(result_cache_ttl.parse::<u64>())?);
+        let script_execution_max_completion_time = time::Duration::from_millis(to_io_error(
+            script_execution_max_completion_time.parse::<u64>(),
+        )?);
+        let script_execution_max_memory_limit =
+            to_io_error(script_execution_max_memory_limit.parse::<usize>())?;
+        let script_execution_memory_limit =
+            to_io_error(script_execution_memory_limit.parse::<usize>())?;
+        let data_snapshot_records = to_io_error(data_snapshot_records.parse::<usize>())?;
+        let script_definition_max_expiration_time = time::Duration::from_millis(to_io_error(
+            script_definition_max_expiration_time.parse::<u64>(),
+        )?);
+        let script_definition_max_count =
+            to_io_error(script_definition_max_count.parse::<usize>())?;
+        let script_definition_max_bytes =
+            to_io_error(script_definition_max_bytes.parse::<usize>())?;
+        let script_definition_sweep_interval = time::Duration::from_millis(to_io_error(
+            script_definition_sweep_interval.parse::<u64>(),
+        )?);
+        let namespaces: BTreeMap<String, NamespaceSettings> =
+            to_io_error(serde_json::from_str(&namespaces))?;
+
+        for overrides in namespaces.values() {
+            match overrides.events_webhook_url {
+                Some(ref url) if !url.trim().is_empty() => {
+                    parse_http_url(url.trim(), "webhook")?;
+                }
+
+                _ => {}
+            }
+        }
+        let request_max_arguments_bytes =
+            to_io_error(request_max_arguments_bytes.parse::<usize>())?;
+        let request_max_batch_bytes = to_io_error(request_max_batch_bytes.parse::<usize>())?;
+        let request_max_script_bytes = to_io_error(request_max_script_bytes.parse::<usize>())?;
+        let replication_peers = parse_peers(&replication_peers)?;
+        let replication_resync_interval =
+            time::Duration::from_millis(to_io_error(replication_resync_interval.parse::<u64>())?);
+        let events_history = to_io_error(events_history.parse::<usize>())?;
+        let events_webhook_url = match events_webhook_url.trim() {
+            "" => None,
+            url => Some(parse_http_url(url, "webhook")?),
+        };
+        let job_max_pending = to_io_error(job_max_pending.parse::<usize>())?;
+        let replication_secret = match replication_secret.trim() {
+            "" => None,
+            secret => Some(secret.to_string()),
+        };
+
+        // The replication routes accept changes to the scripts, and give
+        // them away, so they're only open to peers that know the secret
+
+        if !replication_peers.is_empty() && replication_secret.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "replication requires a secret that's shared by the peers",
+            ));
+        }
 
         let script_execution_thread_pool_size = if script_execution_thread_pool_size == 0 {
             num_cpus::get()
@@ -62,6 +360,19 @@ impl Settings {
             None => None,
         };
 
+        let data_dir = env::var(env_jsaas_data_dir)
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(|p| path::Path::new(&p).to_path_buf());
+
+        // Scripts are kept in files by default if there's a data directory
+
+        let data_store = match data_store.as_str() {
+            "" if data_dir.is_some() => store::Backend::File,
+            "" => store::Backend::Memory,
+            backend => backend.parse()?,
+        };
+
         let tls_public_certificate_path = env::var(env_jsaas_tls_public_certificate_path)
             .ok()
             .filter(|p| !p.is_empty())
@@ -74,12 +385,37 @@ impl Settings {
 
         Ok(Settings {
             bind_addr,
+            data_dir,
+            data_snapshot_records,
+            data_store,
+            events_history,
+            events_webhook_url,
+            job_execution_completion_time,
+            job_max_pending,
+            job_retention_time,
+            namespaces,
+            ndjson_max_line_length,
+            replication_peers,
+            replication_resync_interval,
+            replication_secret,
+            request_max_arguments_bytes,
+            request_max_batch_bytes,
+            request_max_script_bytes,
+            result_cache_max_bytes,
+            result_cache_ttl,
             script_definition_expiration_time: time::Duration::from_millis(
                 script_definition_expiration_time_ms,
             ),
+            script_definition_max_expiration_time,
+            script_definition_max_count,
+            script_definition_max_bytes,
+            script_definition_sweep_interval,
             script_execution_completion_time: time::Duration::from_millis(
                 script_execution_completion_time_ms,
             ),
+            script_execution_max_completion_time,
+            script_execution_max_memory_limit,
+            script_execution_memory_limit,
             script_execution_thread_pool_size,
             tls_bind_addr,
             tls_public_certificate_path,
@@ -97,9 +433,34 @@ mod tests {
     fn test_settings_default() {
         let settings = Settings::new(
             "JSAAS_TEST_1_BIND_ADDR",
+            "JSAAS_TEST_1_DATA_DIR",
+            "JSAAS_TEST_1_DATA_SNAPSHOT_RECORDS",
+            "JSAAS_TEST_1_DATA_STORE",
+            "JSAAS_TEST_1_EVENTS_HISTORY",
+            "JSAAS_TEST_1_EVENTS_WEBHOOK_URL",
+            "JSAAS_TEST_1_JOB_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_1_JOB_MAX_PENDING",
+            "JSAAS_TEST_1_JOB_RETENTION_TIME",
+            "JSAAS_TEST_1_NAMESPACES",
+            "JSAAS_TEST_1_NDJSON_MAX_LINE_LENGTH",
+            "JSAAS_TEST_1_REPLICATION_PEERS",
+            "JSAAS_TEST_1_REPLICATION_RESYNC_INTERVAL",
+            "JSAAS_TEST_1_REPLICATION_SECRET",
+            "JSAAS_TEST_1_REQUEST_MAX_ARGUMENTS_BYTES",
+            "JSAAS_TEST_1_REQUEST_MAX_BATCH_BYTES",
+            "JSAAS_TEST_1_REQUEST_MAX_SCRIPT_BYTES",
+            "JSAAS_TEST_1_RESULT_CACHE_MAX_BYTES",
+            "JSAAS_TEST_1_RESULT_CACHE_TTL",
             "JSAAS_TEST_1_SCRIPT_DEFINITION_EXPIRATION_TIME",
+            "JSAAS_TEST_1_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME",
+            "JSAAS_TEST_1_SCRIPT_DEFINITION_MAX_COUNT",
+            "JSAAS_TEST_1_SCRIPT_DEFINITION_MAX_BYTES",
+            "JSAAS_TEST_1_SCRIPT_DEFINITION_SWEEP_INTERVAL",
             "JSAAS_TEST_1_SCRIPT_EXECUTION_THREAD_POOL_SIZE",
             "JSAAS_TEST_1_SCRIPT_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_1_SCRIPT_EXECUTION_MAX_COMPLETION_TIME",
+            "JSAAS_TEST_1_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT",
+            "JSAAS_TEST_1_SCRIPT_EXECUTION_MEMORY_LIMIT",
             "JSAAS_TEST_1_TLS_BIND_ADDR",
             "JSAAS_TEST_1_TLS_PUBLIC_CERTIFICATE_PATH",
             "JSAAS_TEST_1_TLS_PRIVATE_KEY_PATH",
@@ -110,6 +471,7 @@ mod tests {
             settings.bind_addr,
             "127.0.0.1:9412".parse::<net::SocketAddr>().unwrap()
         );
+        assert_eq!(settings.ndjson_max_line_length, 1048576);
         assert_eq!(
             settings.script_definition_expiration_time,
             time::Duration::from_secs(86400)
@@ -118,10 +480,50 @@ mod tests {
             settings.script_execution_completion_time,
             time::Duration::from_secs(10)
         );
+        assert_eq!(
+            settings.job_execution_completion_time,
+            time::Duration::from_secs(3600)
+        );
+        assert_eq!(settings.job_retention_time, time::Duration::from_secs(3600));
+        assert_eq!(settings.result_cache_max_bytes, 67108864);
+        assert_eq!(settings.result_cache_ttl, time::Duration::from_secs(60));
+        assert_eq!(
+            settings.script_execution_max_completion_time,
+            time::Duration::from_secs(60)
+        );
+        assert_eq!(settings.script_execution_max_memory_limit, 0);
+        assert_eq!(settings.script_execution_memory_limit, 0);
+        assert_eq!(settings.data_snapshot_records, 10000);
+        assert!(settings.namespaces.is_empty());
+        assert_eq!(
+            settings.script_definition_max_expiration_time,
+            time::Duration::from_secs(0)
+        );
+        assert_eq!(settings.script_definition_max_count, 0);
+        assert_eq!(settings.script_definition_max_bytes, 0);
+        assert_eq!(
+            settings.script_definition_sweep_interval,
+            time::Duration::from_secs(60)
+        );
+        assert_eq!(settings.request_max_arguments_bytes, 1048576);
+        assert_eq!(settings.request_max_batch_bytes, 67108864);
+        assert_eq!(settings.request_max_script_bytes, 1048576);
+        assert!(settings.replication_peers.is_empty());
+        assert_eq!(
+            settings.replication_resync_interval,
+            time::Duration::from_secs(30)
+        );
+        assert_eq!(settings.data_store, store::Backend::Memory);
+        assert_eq!(settings.events_history, 1000);
+        assert_eq!(settings.events_webhook_url, None);
+        assert_eq!(settings.job_max_pending, 1000);
+        assert_eq!(settings.replication_secret, None);
         assert!(settings.script_execution_thread_pool_size > 0);
 
         assert_eq!(settings.tls_bind_addr, None);
 
+        assert_eq!(settings.data_dir, None);
+
         assert_eq!(settings.tls_public_certificate_path, None);
 
         assert_eq!(settings.tls_private_key_path, None);
@@ -130,18 +532,80 @@ mod tests {
     #[test]
     fn test_settings_env_vars_valid() {
         env::set_var("JSAAS_TEST_2_BIND_ADDR", "127.0.0.2:1234");
+        env::set_var("JSAAS_TEST_2_NDJSON_MAX_LINE_LENGTH", "4096");
         env::set_var("JSAAS_TEST_2_SCRIPT_DEFINITION_EXPIRATION_TIME", "5000");
         env::set_var("JSAAS_TEST_2_SCRIPT_EXECUTION_THREAD_POOL_SIZE", "7");
         env::set_var("JSAAS_TEST_2_SCRIPT_EXECUTION_COMPLETION_TIME", "1000");
         env::set_var("JSAAS_TEST_2_TLS_BIND_ADDR", "127.0.0.3:1235");
         env::set_var("JSAAS_TEST_2_TLS_PUBLIC_CERTIFICATE_PATH", "/root/pub.pem");
         env::set_var("JSAAS_TEST_2_TLS_PRIVATE_KEY_PATH", "/root/priv.pem");
+        env::set_var("JSAAS_TEST_2_JOB_EXECUTION_COMPLETION_TIME", "20000");
+        env::set_var("JSAAS_TEST_2_JOB_RETENTION_TIME", "30000");
+        env::set_var("JSAAS_TEST_2_RESULT_CACHE_MAX_BYTES", "1024");
+        env::set_var("JSAAS_TEST_2_RESULT_CACHE_TTL", "5000");
+        env::set_var("JSAAS_TEST_2_SCRIPT_EXECUTION_MAX_COMPLETION_TIME", "2000");
+        env::set_var("JSAAS_TEST_2_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT", "8388608");
+        env::set_var("JSAAS_TEST_2_SCRIPT_EXECUTION_MEMORY_LIMIT", "1048576");
+        env::set_var("JSAAS_TEST_2_DATA_DIR", "/var/lib/jsaas");
+        env::set_var("JSAAS_TEST_2_DATA_SNAPSHOT_RECORDS", "500");
+        env::set_var(
+            "JSAAS_TEST_2_NAMESPACES",
+            r#"{"billing": {"script_execution_completion_time": 2500, "script_definition_max_count": 10, "job_max_pending": 5, "result_cache_max_bytes": 256}, "ops": {"events_webhook_url": "http://127.0.0.1:8081/hooks"}, "quiet": {"events_webhook_url": ""}}"#,
+        );
+        env::set_var("JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_COUNT", "1000");
+        env::set_var("JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_BYTES", "67108864");
+        env::set_var("JSAAS_TEST_2_SCRIPT_DEFINITION_SWEEP_INTERVAL", "5000");
+        env::set_var("JSAAS_TEST_2_REQUEST_MAX_ARGUMENTS_BYTES", "65536");
+        env::set_var("JSAAS_TEST_2_REQUEST_MAX_BATCH_BYTES", "0");
+        env::set_var("JSAAS_TEST_2_REQUEST_MAX_SCRIPT_BYTES", "32768");
+        env::set_var(
+            "JSAAS_TEST_2_REPLICATION_PEERS",
+            "http://127.0.0.1:9413,http://127.0.0.1:9414/",
+        );
+        env::set_var("JSAAS_TEST_2_REPLICATION_RESYNC_INTERVAL", "5000");
+        env::set_var("JSAAS_TEST_2_DATA_STORE", "sqlite");
+        env::set_var("JSAAS_TEST_2_EVENTS_HISTORY", "50");
+        env::set_var("JSAAS_TEST_2_JOB_MAX_PENDING", "25");
+        env::set_var("JSAAS_TEST_2_REPLICATION_SECRET", "s3cret");
+        env::set_var(
+            "JSAAS_TEST_2_EVENTS_WEBHOOK_URL",
+            "http://127.0.0.1:8080/hooks",
+        );
+        env::set_var(
+            "JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME",
+            "172800000",
+        );
 
         let settings = Settings::new(
             "JSAAS_TEST_2_BIND_ADDR",
+            "JSAAS_TEST_2_DATA_DIR",
+            "JSAAS_TEST_2_DATA_SNAPSHOT_RECORDS",
+            "JSAAS_TEST_2_DATA_STORE",
+            "JSAAS_TEST_2_EVENTS_HISTORY",
+            "JSAAS_TEST_2_EVENTS_WEBHOOK_URL",
+            "JSAAS_TEST_2_JOB_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_2_JOB_MAX_PENDING",
+            "JSAAS_TEST_2_JOB_RETENTION_TIME",
+            "JSAAS_TEST_2_NAMESPACES",
+            "JSAAS_TEST_2_NDJSON_MAX_LINE_LENGTH",
+            "JSAAS_TEST_2_REPLICATION_PEERS",
+            "JSAAS_TEST_2_REPLICATION_RESYNC_INTERVAL",
+            "JSAAS_TEST_2_REPLICATION_SECRET",
+            "JSAAS_TEST_2_REQUEST_MAX_ARGUMENTS_BYTES",
+            "JSAAS_TEST_2_REQUEST_MAX_BATCH_BYTES",
+            "JSAAS_TEST_2_REQUEST_MAX_SCRIPT_BYTES",
+            "JSAAS_TEST_2_RESULT_CACHE_MAX_BYTES",
+            "JSAAS_TEST_2_RESULT_CACHE_TTL",
             "JSAAS_TEST_2_SCRIPT_DEFINITION_EXPIRATION_TIME",
+            "JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME",
+            "JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_COUNT",
+            "JSAAS_TEST_2_SCRIPT_DEFINITION_MAX_BYTES",
+            "JSAAS_TEST_2_SCRIPT_DEFINITION_SWEEP_INTERVAL",
             "JSAAS_TEST_2_SCRIPT_EXECUTION_THREAD_POOL_SIZE",
             "JSAAS_TEST_2_SCRIPT_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_2_SCRIPT_EXECUTION_MAX_COMPLETION_TIME",
+            "JSAAS_TEST_2_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT",
+            "JSAAS_TEST_2_SCRIPT_EXECUTION_MEMORY_LIMIT",
             "JSAAS_TEST_2_TLS_BIND_ADDR",
             "JSAAS_TEST_2_TLS_PUBLIC_CERTIFICATE_PATH",
             "JSAAS_TEST_2_TLS_PRIVATE_KEY_PATH",
@@ -152,6 +616,7 @@ mod tests {
             settings.bind_addr,
             "127.0.0.2:1234".parse::<net::SocketAddr>().unwrap()
         );
+        assert_eq!(settings.ndjson_max_line_length, 4096);
         assert_eq!(
             settings.script_definition_expiration_time,
             time::Duration::from_secs(5)
@@ -160,6 +625,77 @@ mod tests {
             settings.script_execution_completion_time,
             time::Duration::from_secs(1)
         );
+        assert_eq!(
+            settings.job_execution_completion_time,
+            time::Duration::from_secs(20)
+        );
+        assert_eq!(settings.job_retention_time, time::Duration::from_secs(30));
+        assert_eq!(settings.result_cache_max_bytes, 1024);
+        assert_eq!(settings.result_cache_ttl, time::Duration::from_secs(5));
+        assert_eq!(
+            settings.script_execution_max_completion_time,
+            time::Duration::from_secs(2)
+        );
+        assert_eq!(settings.script_execution_max_memory_limit, 8388608);
+        assert_eq!(settings.script_execution_memory_limit, 1048576);
+        assert_eq!(settings.data_snapshot_records, 500);
+
+        let billing = settings.namespaces["billing"].apply(&settings);
+        assert_eq!(
+            billing.script_execution_completion_time,
+            time::Duration::from_millis(2500)
+        );
+        assert_eq!(billing.script_definition_max_count, 10);
+        assert_eq!(billing.job_max_pending, 5);
+        assert_eq!(billing.result_cache_max_bytes, 256);
+        assert_eq!(billing.result_cache_ttl, settings.result_cache_ttl);
+        assert_eq!(billing.events_webhook_url, settings.events_webhook_url);
+
+        let ops = settings.namespaces["ops"].apply(&settings);
+        assert_eq!(
+            ops.events_webhook_url,
+            Some("http://127.0.0.1:8081/hooks".parse().unwrap())
+        );
+
+        let quiet = settings.namespaces["quiet"].apply(&settings);
+        assert_eq!(quiet.events_webhook_url, None);
+        assert_eq!(
+            billing.script_execution_max_completion_time,
+            settings.script_execution_max_completion_time
+        );
+        assert_eq!(
+            settings.script_definition_max_expiration_time,
+            time::Duration::from_secs(172800)
+        );
+        assert_eq!(settings.script_definition_max_count, 1000);
+        assert_eq!(settings.script_definition_max_bytes, 67108864);
+        assert_eq!(
+            settings.script_definition_sweep_interval,
+            time::Duration::from_secs(5)
+        );
+        assert_eq!(settings.request_max_arguments_bytes, 65536);
+        assert_eq!(settings.request_max_batch_bytes, 0);
+        assert_eq!(settings.request_max_script_bytes, 32768);
+        assert_eq!(
+            settings
+                .replication_peers
+                .iter()
+                .map(|peer| peer.to_string())
+                .collect::<Vec<_>>(),
+            vec!["http://127.0.0.1:9413/", "http://127.0.0.1:9414/"]
+        );
+        assert_eq!(
+            settings.replication_resync_interval,
+            time::Duration::from_secs(5)
+        );
+        assert_eq!(settings.data_store, store::Backend::Sqlite);
+        assert_eq!(settings.events_history, 50);
+        assert_eq!(
+            settings.events_webhook_url,
+            Some("http://127.0.0.1:8080/hooks".parse().unwrap())
+        );
+        assert_eq!(settings.job_max_pending, 25);
+        assert_eq!(settings.replication_secret, Some("s3cret".to_string()));
         assert_eq!(settings.script_execution_thread_pool_size, 7);
 
         assert_eq!(
@@ -176,24 +712,60 @@ mod tests {
             settings.tls_private_key_path,
             Some(path::Path::new("/root/priv.pem").to_path_buf())
         );
+
+        assert_eq!(
+            settings.data_dir,
+            Some(path::Path::new("/var/lib/jsaas").to_path_buf())
+        );
     }
 
     #[test]
     fn test_settings_env_vars_invalid() {
         env::set_var("JSAAS_TEST_3_BIND_ADDR", "*@!($!");
+        env::set_var("JSAAS_TEST_3_NDJSON_MAX_LINE_LENGTH", "");
         env::set_var("JSAAS_TEST_3_SCRIPT_DEFINITION_EXPIRATION_TIME", "");
         env::set_var("JSAAS_TEST_3_SCRIPT_EXECUTION_THREAD_POOL_SIZE", "");
         env::set_var("JSAAS_TEST_3_SCRIPT_EXECUTION_COMPLETION_TIME", "");
 
         assert!(Settings::new(
             "JSAAS_TEST_3_BIND_ADDR",
+            "JSAAS_TEST_3_DATA_DIR",
+            "JSAAS_TEST_3_DATA_SNAPSHOT_RECORDS",
+            "JSAAS_TEST_3_DATA_STORE",
+            "JSAAS_TEST_3_EVENTS_HISTORY",
+            "JSAAS_TEST_3_EVENTS_WEBHOOK_URL",
+            "JSAAS_TEST_3_JOB_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_3_JOB_MAX_PENDING",
+            "JSAAS_TEST_3_JOB_RETENTION_TIME",
+            "JSAAS_TEST_3_NAMESPACES",
+            "JSAAS_TEST_3_NDJSON_MAX_LINE_LENGTH",
+            "JSAAS_TEST_3_REPLICATION_PEERS",
+            "JSAAS_TEST_3_REPLICATION_RESYNC_INTERVAL",
+            "JSAAS_TEST_3_REPLICATION_SECRET",
+            "JSAAS_TEST_3_REQUEST_MAX_ARGUMENTS_BYTES",
+            "JSAAS_TEST_3_REQUEST_MAX_BATCH_BYTES",
+            "JSAAS_TEST_3_REQUEST_MAX_SCRIPT_BYTES",
+            "JSAAS_TEST_3_RESULT_CACHE_MAX_BYTES",
+            "JSAAS_TEST_3_RESULT_CACHE_TTL",
             "JSAAS_TEST_3_SCRIPT_DEFINITION_EXPIRATION_TIME",
+            "JSAAS_TEST_3_SCRIPT_DEFINITION_MAX_EXPIRATION_TIME",
+            "JSAAS_TEST_3_SCRIPT_DEFINITION_MAX_COUNT",
+            "JSAAS_TEST_3_SCRIPT_DEFINITION_MAX_BYTES",
+            "JSAAS_TEST_3_SCRIPT_DEFINITION_SWEEP_INTERVAL",
             "JSAAS_TEST_3_SCRIPT_EXECUTION_THREAD_POOL_SIZE",
             "JSAAS_TEST_3_SCRIPT_EXECUTION_COMPLETION_TIME",
+            "JSAAS_TEST_3_SCRIPT_EXECUTION_MAX_COMPLETION_TIME",
+            "JSAAS_TEST_3_SCRIPT_EXECUTION_MAX_MEMORY_LIMIT",
+            "JSAAS_TEST_3_SCRIPT_EXECUTION_MEMORY_LIMIT",
             "JSAAS_TEST_3_TLS_BIND_ADDR",
             "JSAAS_TEST_3_TLS_PUBLIC_CERTIFICATE_PATH",
             "JSAAS_TEST_3_TLS_PRIVATE_KEY_PATH"
         )
         .is_err());
+
+        assert!(serde_json::from_str::<BTreeMap<String, NamespaceSettings>>(
+            r#"{"billing": {"timeout": 1}}"#
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,283 @@
+use crate::persistence::{self, PersistedScript, TouchThrottle};
+use crate::script_registry::{Named, ScriptId};
+use crate::store::ScriptStore;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+const DATABASE_FILE: &str = "scripts.sqlite";
+
+/// Changes are written to a write-ahead log, which is only synced to disk
+/// when it's checkpointed into the database, rather than for every change,
+/// so the last few changes may be lost if the machine stops.
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+
+    CREATE TABLE IF NOT EXISTS scripts (
+        id TEXT PRIMARY KEY,
+        script TEXT NOT NULL,
+        created INTEGER NOT NULL,
+        accessed INTEGER NOT NULL,
+        executions INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS names (
+        name TEXT PRIMARY KEY,
+        named TEXT NOT NULL
+    );
+";
+
+/// Persists every change to an embedded SQLite database in a directory, and
+/// reads scripts from it. Scripts and names are stored as JSON, along with
+/// times in milliseconds since the Unix epoch. As with the file store,
+/// accesses are recorded at most once per `TOUCH_INTERVAL`.
+pub struct SqliteStore {
+    connection: Connection,
+    touched: TouchThrottle,
+}
+
+impl SqliteStore {
+    /// Opens the database in a directory, creating both if necessary.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let connection = Connection::open(dir.join(DATABASE_FILE)).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        Ok(Self {
+            connection,
+            touched: TouchThrottle::default(),
+        })
+    }
+
+    /// Reads the scripts that match a condition, given its parameters.
+    fn read_scripts(
+        &self,
+        condition: &str,
+        params: &[&ToSql],
+    ) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+        let mut statement = self
+            .connection
+            .prepare(&format!(
+                "SELECT id, script, created, accessed, executions FROM scripts {}",
+                condition
+            ))
+            .map_err(to_io_error)?;
+
+        let rows = statement
+            .query_map(params, |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })
+            .map_err(to_io_error)?;
+
+        let mut scripts = vec![];
+
+        for row in rows {
+            let (id, script, created, accessed, executions) = row.map_err(to_io_error)?;
+
+            let script = PersistedScript {
+                script: serde_json::from_str(&script)?,
+                created: persistence::from_millis(created as u64),
+                accessed: persistence::from_millis(accessed as u64),
+                executions: executions as u64,
+            };
+
+            scripts.push((id.parse()?, script));
+        }
+
+        Ok(scripts)
+    }
+
+    /// Reads the named scripts that match a condition, given its parameters.
+    fn read_names(&self, condition: &str, params: &[&ToSql]) -> io::Result<Vec<(String, Named)>> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT name, named FROM names {}", condition))
+            .map_err(to_io_error)?;
+
+        let rows = statement
+            .query_map(params, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(to_io_error)?;
+
+        let mut names = vec![];
+
+        for row in rows {
+            let (name, named) = row.map_err(to_io_error)?;
+
+            names.push((name, serde_json::from_str(&named)?));
+        }
+
+        Ok(names)
+    }
+
+    fn delete(&self, sql: &str, key: &str) -> io::Result<()> {
+        self.connection
+            .execute(sql, params![key])
+            .map(|_| ())
+            .map_err(to_io_error)
+    }
+}
+
+impl ScriptStore for SqliteStore {
+    fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        let mut scripts = self.read_scripts("WHERE id = ?1", params![id.to_string()])?;
+
+        Ok(scripts.pop().map(|(_, script)| script))
+    }
+
+    fn store(&mut self, id: ScriptId, script: PersistedScript) -> io::Result<()> {
+        let code = serde_json::to_string(&script.script)?;
+
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO scripts (id, script, created, accessed, executions)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id.to_string(),
+                    code,
+                    persistence::to_millis(script.created) as i64,
+                    persistence::to_millis(script.accessed) as i64,
+                    script.executions as i64,
+                ],
+            )
+            .map(|_| ())
+            .map_err(to_io_error)
+    }
+
+    fn remove(&mut self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        let removed = self.get(id)?;
+
+        if removed.is_some() {
+            self.delete("DELETE FROM scripts WHERE id = ?1", &id.to_string())?;
+            self.touched.forget(id);
+        }
+
+        Ok(removed)
+    }
+
+    fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+        self.read_scripts("", NO_PARAMS)
+    }
+
+    fn touch(&mut self, id: &ScriptId, accessed: SystemTime, executions: u64) -> io::Result<()> {
+        if self.touched.should_touch(id, accessed) {
+            self.connection
+                .execute(
+                    "UPDATE scripts SET accessed = ?1, executions = ?2 WHERE id = ?3",
+                    params![
+                        persistence::to_millis(accessed) as i64,
+                        executions as i64,
+                        id.to_string(),
+                    ],
+                )
+                .map_err(to_io_error)?;
+        }
+
+        Ok(())
+    }
+
+    fn names(&self) -> io::Result<Vec<(String, Named)>> {
+        self.read_names("", NO_PARAMS)
+    }
+
+    fn store_name(&mut self, name: String, named: Named) -> io::Result<()> {
+        let named = serde_json::to_string(&named)?;
+
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO names (name, named) VALUES (?1, ?2)",
+                params![name, named],
+            )
+            .map(|_| ())
+            .map_err(to_io_error)
+    }
+
+    fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>> {
+        let removed = self
+            .read_names("WHERE name = ?1", params![name])?
+            .pop()
+            .map(|(_, named)| named);
+
+        if removed.is_some() {
+            self.delete("DELETE FROM names WHERE name = ?1", name)?;
+        }
+
+        Ok(removed)
+    }
+}
+
+fn to_io_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::tests::data_dir;
+    use crate::script_registry::Script;
+
+    #[test]
+    fn test_sqlite_store() {
+        let dir = data_dir();
+        let id = ScriptId::random();
+        let accessed = persistence::from_millis(2000);
+
+        {
+            let mut store = SqliteStore::open(&dir).unwrap();
+
+            store
+                .store(
+                    id.clone(),
+                    PersistedScript {
+                        script: Script::new("function() {}".to_string()),
+                        created: persistence::from_millis(1000),
+                        accessed: persistence::from_millis(1000),
+                        executions: 0,
+                    },
+                )
+                .unwrap();
+            store.touch(&id, accessed, 2).unwrap();
+            store
+                .store_name("adder".to_string(), Named::default())
+                .unwrap();
+            store
+                .store_name("removed".to_string(), Named::default())
+                .unwrap();
+            assert!(store.remove_name("removed").unwrap().is_some());
+            assert!(store.remove_name("removed").unwrap().is_none());
+        }
+
+        {
+            let mut store = SqliteStore::open(&dir).unwrap();
+
+            let script = store.get(&id).unwrap().unwrap();
+            assert_eq!(script.script.code, "function() {}");
+            assert_eq!(script.accessed, accessed);
+            assert_eq!(script.executions, 2);
+            assert_eq!(
+                store.names().unwrap(),
+                vec![("adder".to_string(), Named::default())]
+            );
+
+            assert!(store.remove(&id).unwrap().is_some());
+            assert!(store.remove(&id).unwrap().is_none());
+        }
+
+        let store = SqliteStore::open(&dir).unwrap();
+        assert!(store.list().unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
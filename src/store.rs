@@ -0,0 +1,402 @@
+use crate::persistence::{FileStore, PersistedScript};
+use crate::script_registry::{Named, ScriptId, Stored};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStore;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+/// Holds the scripts of a registry, and the names of scripts. Values are
+/// returned owned, so that stores may read them from wherever they keep
+/// them, and times are given as `SystemTime`s, so that they can be kept
+/// across restarts. A change that cannot be persisted fails with an error.
+///
+/// Stores don't expire or evict scripts, which is up to the registry, and
+/// aren't read on every request, as the registry keeps what they hold in
+/// memory with a `CachedStore`.
+pub trait ScriptStore: Send {
+    fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>>;
+
+    /// Stores a script, replacing any with the same id.
+    fn store(&mut self, id: ScriptId, script: PersistedScript) -> io::Result<()>;
+
+    fn remove(&mut self, id: &ScriptId) -> io::Result<Option<PersistedScript>>;
+
+    /// Lists the scripts in no particular order.
+    fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>>;
+
+    /// Records when a script was last accessed and how many times it has
+    /// been executed. Stores may persist this less often than it changes.
+    fn touch(&mut self, id: &ScriptId, accessed: SystemTime, executions: u64) -> io::Result<()>;
+
+    /// Lists the named scripts in no particular order.
+    fn names(&self) -> io::Result<Vec<(String, Named)>>;
+
+    /// Stores a named script, replacing any with the same name.
+    fn store_name(&mut self, name: String, named: Named) -> io::Result<()>;
+
+    fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>>;
+}
+
+/// Keeps the scripts and names that a store holds in memory, so that the
+/// registry can read them without I/O. Changes are made in the store first,
+/// and only made in memory once the store has persisted them.
+///
+/// Accesses are kept as `Instant`s in memory, so that expiration isn't
+/// affected by changes to the system clock while the process runs.
+pub struct CachedStore {
+    store: Box<ScriptStore>,
+    scripts: HashMap<ScriptId, Stored>,
+    names: HashMap<String, Named>,
+}
+
+impl CachedStore {
+    /// Loads the scripts and names that a store holds.
+    pub fn load(store: Box<ScriptStore>) -> io::Result<Self> {
+        let scripts = store
+            .list()?
+            .into_iter()
+            .map(|(id, script)| {
+                let stored = Stored {
+                    script: script.script,
+                    created: script.created,
+                    accessed: to_instant(script.accessed),
+                    executions: script.executions,
+                };
+
+                (id, stored)
+            })
+            .collect();
+
+        let names = store.names()?.into_iter().collect();
+
+        Ok(Self {
+            store,
+            scripts,
+            names,
+        })
+    }
+
+    pub fn get(&self, id: &ScriptId) -> Option<&Stored> {
+        self.scripts.get(id)
+    }
+
+    pub fn store(&mut self, id: ScriptId, stored: Stored) -> io::Result<()> {
+        self.store.store(
+            id.clone(),
+            PersistedScript {
+                script: stored.script.clone(),
+                created: stored.created,
+                accessed: to_system_time(stored.accessed),
+                executions: stored.executions,
+            },
+        )?;
+
+        self.scripts.insert(id, stored);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &ScriptId) -> io::Result<Option<Stored>> {
+        if !self.scripts.contains_key(id) {
+            return Ok(None);
+        }
+
+        self.store.remove(id)?;
+
+        Ok(self.scripts.remove(id))
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = (&ScriptId, &Stored)> {
+        self.scripts.iter()
+    }
+
+    pub fn touch(&mut self, id: &ScriptId, accessed: Instant, executions: u64) -> io::Result<()> {
+        if let Some(stored) = self.scripts.get_mut(id) {
+            self.store.touch(id, to_system_time(accessed), executions)?;
+
+            stored.accessed = accessed;
+            stored.executions = executions;
+        }
+
+        Ok(())
+    }
+
+    pub fn names(&self) -> &HashMap<String, Named> {
+        &self.names
+    }
+
+    pub fn store_name(&mut self, name: String, named: Named) -> io::Result<()> {
+        self.store.store_name(name.clone(), named.clone())?;
+
+        self.names.insert(name, named);
+
+        Ok(())
+    }
+
+    pub fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>> {
+        if !self.names.contains_key(name) {
+            return Ok(None);
+        }
+
+        self.store.remove_name(name)?;
+
+        Ok(self.names.remove(name))
+    }
+}
+
+/// Returns the time at which something happened, given the instant of it.
+pub fn to_system_time(instant: Instant) -> SystemTime {
+    let age = Instant::now().duration_since(instant);
+
+    SystemTime::now() - age
+}
+
+/// Returns the instant at which something happened, given the time of it.
+/// Times in the future count as now.
+pub fn to_instant(time: SystemTime) -> Instant {
+    let now = Instant::now();
+
+    let age = SystemTime::now().duration_since(time).unwrap_or_default();
+
+    now.checked_sub(age).unwrap_or(now)
+}
+
+/// An error that a store failed a change with, which the registry wraps
+/// the store's errors in, so that they can be told apart from its own,
+/// e.g. when it's full.
+#[derive(Debug)]
+pub struct StoreError(io::Error);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot persist script registry: {}", self.0)
+    }
+}
+
+impl Error for StoreError {}
+
+impl StoreError {
+    pub fn wrap(e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), StoreError(e))
+    }
+
+    /// Determines if an error is one that a store failed a change with.
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().map_or(false, |e| e.is::<StoreError>())
+    }
+}
+
+/// The kinds of stores that are built in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Memory,
+    File,
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "memory" => Ok(Backend::Memory),
+            "file" => Ok(Backend::File),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown script store {}", s),
+            )),
+        }
+    }
+}
+
+/// Opens a store of the provided kind, loading the scripts that it holds.
+/// Stores other than the memory store are kept in `dir`.
+pub fn open(
+    backend: Backend,
+    dir: Option<PathBuf>,
+    snapshot_records: usize,
+) -> io::Result<Box<ScriptStore>> {
+    let dir = match (backend, dir) {
+        (Backend::Memory, _) => return Ok(Box::new(MemoryStore::default())),
+        (_, Some(dir)) => dir,
+
+        (_, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the file and sqlite script stores require a data directory",
+            ));
+        }
+    };
+
+    match backend {
+        Backend::File => Ok(Box::new(FileStore::open(&dir, snapshot_records)?)),
+        Backend::Sqlite => open_sqlite(&dir),
+        Backend::Memory => unreachable!(),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(dir: &Path) -> io::Result<Box<ScriptStore>> {
+    Ok(Box::new(SqliteStore::open(dir)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite(_dir: &Path) -> io::Result<Box<ScriptStore>> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the sqlite script store requires building with the sqlite feature",
+    ))
+}
+
+/// Keeps scripts in memory only, so they're lost when the process stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    scripts: HashMap<ScriptId, PersistedScript>,
+    names: HashMap<String, Named>,
+}
+
+impl ScriptStore for MemoryStore {
+    fn get(&self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        Ok(self.scripts.get(id).cloned())
+    }
+
+    fn store(&mut self, id: ScriptId, script: PersistedScript) -> io::Result<()> {
+        self.scripts.insert(id, script);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &ScriptId) -> io::Result<Option<PersistedScript>> {
+        Ok(self.scripts.remove(id))
+    }
+
+    fn list(&self) -> io::Result<Vec<(ScriptId, PersistedScript)>> {
+        Ok(self
+            .scripts
+            .iter()
+            .map(|(id, script)| (id.clone(), script.clone()))
+            .collect())
+    }
+
+    fn touch(&mut self, id: &ScriptId, accessed: SystemTime, executions: u64) -> io::Result<()> {
+        if let Some(script) = self.scripts.get_mut(id) {
+            script.accessed = accessed;
+            script.executions = executions;
+        }
+
+        Ok(())
+    }
+
+    fn names(&self) -> io::Result<Vec<(String, Named)>> {
+        Ok(self
+            .names
+            .iter()
+            .map(|(name, named)| (name.clone(), named.clone()))
+            .collect())
+    }
+
+    fn store_name(&mut self, name: String, named: Named) -> io::Result<()> {
+        self.names.insert(name, named);
+
+        Ok(())
+    }
+
+    fn remove_name(&mut self, name: &str) -> io::Result<Option<Named>> {
+        Ok(self.names.remove(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script_registry::Script;
+
+    #[test]
+    fn test_store_backend() {
+        assert_eq!("memory".parse::<Backend>().unwrap(), Backend::Memory);
+        assert_eq!("file".parse::<Backend>().unwrap(), Backend::File);
+        assert_eq!("sqlite".parse::<Backend>().unwrap(), Backend::Sqlite);
+        assert!("redis".parse::<Backend>().is_err());
+
+        assert!(open(Backend::Memory, None, 100).is_ok());
+        assert!(open(Backend::File, None, 100).is_err());
+        assert!(open(Backend::Sqlite, None, 100).is_err());
+    }
+
+    fn persisted(code: &str) -> PersistedScript {
+        PersistedScript {
+            script: Script::new(code.to_string()),
+            created: SystemTime::now(),
+            accessed: SystemTime::now(),
+            executions: 0,
+        }
+    }
+
+    #[test]
+    fn test_store_memory() {
+        let mut store = MemoryStore::default();
+        let id = ScriptId::random();
+
+        store.store(id.clone(), persisted("function() {}")).unwrap();
+
+        let accessed = SystemTime::now();
+        store.touch(&id, accessed, 3).unwrap();
+
+        let script = store.get(&id).unwrap().unwrap();
+        assert_eq!(script.executions, 3);
+        assert_eq!(script.accessed, accessed);
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        store
+            .store_name("adder".to_string(), Named::default())
+            .unwrap();
+        assert_eq!(store.names().unwrap().len(), 1);
+        assert!(store.remove_name("adder").unwrap().is_some());
+        assert!(store.remove_name("adder").unwrap().is_none());
+
+        assert!(store.remove(&id).unwrap().is_some());
+        assert!(store.get(&id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_store_cached() {
+        let mut store = MemoryStore::default();
+        let id = ScriptId::random();
+
+        store.store(id.clone(), persisted("function() {}")).unwrap();
+        store
+            .store_name("adder".to_string(), Named::default())
+            .unwrap();
+
+        let mut cached = CachedStore::load(Box::new(store)).unwrap();
+
+        assert_eq!(cached.get(&id).unwrap().script.code, "function() {}");
+        assert!(cached.names().contains_key("adder"));
+
+        // Changes are made in the store as well as in memory
+
+        let accessed = Instant::now();
+        cached.touch(&id, accessed, 2).unwrap();
+        assert_eq!(cached.get(&id).unwrap().accessed, accessed);
+
+        let other = ScriptId::random();
+        cached
+            .store(
+                other.clone(),
+                Stored::new(Script::new("function() {}".to_string())),
+            )
+            .unwrap();
+        assert!(cached.remove_name("adder").unwrap().is_some());
+
+        let store = cached.store;
+        assert_eq!(store.get(&id).unwrap().unwrap().executions, 2);
+        assert!(store.get(&other).unwrap().is_some());
+        assert!(store.names().unwrap().is_empty());
+    }
+}
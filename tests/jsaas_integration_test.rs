@@ -1,7 +1,8 @@
 use std::env;
+use std::fs;
 use std::panic;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -71,6 +72,187 @@ fn test_jsaas() {
     });
 }
 
+#[test]
+#[ignore]
+/// Runs three JSaaS servers that replicate to each other, and checks that
+/// scripts that are stored or removed on one reach the others, including a
+/// server that was stopped at the time and one that only starts later.
+fn test_jsaas_replication() {
+    let jsaas = cargo_dir().join("jsaas");
+    let dir = env::temp_dir().join(format!("jsaas-replication-test-{}", process::id()));
+    let ports = [9421, 9422, 9423];
+
+    let mut a = Server::start(&jsaas, ports[0], &ports, None);
+    let mut b = Server::start(&jsaas, ports[1], &ports, Some(&dir));
+
+    a.wait_until_ready();
+    b.wait_until_ready();
+
+    // A script that's stored on one server appears on its peer
+
+    let first = a.store("function(a, b) { return a * b; }");
+
+    eventually(Duration::from_secs(30), Duration::from_millis(200), || {
+        b.status(&first) == 200
+    });
+
+    assert_eq!(b.execute(&first, "[4, 3]"), "12");
+
+    // As does its removal
+
+    let second = a.store("function() { return 2; }");
+
+    eventually(Duration::from_secs(30), Duration::from_millis(200), || {
+        b.status(&second) == 200
+    });
+
+    assert_eq!(a.remove(&second), 204);
+
+    eventually(Duration::from_secs(30), Duration::from_millis(200), || {
+        b.status(&second) == 404
+    });
+
+    // A server that's stopped misses changes, and catches up on them when
+    // it resyncs after restarting, even though it persisted the script that
+    // was removed in the meantime
+
+    b.stop();
+
+    let third = a.store("function() { return 3; }");
+    assert_eq!(a.remove(&first), 204);
+
+    let mut b = Server::start(&jsaas, ports[1], &ports, Some(&dir));
+    b.wait_until_ready();
+
+    eventually(Duration::from_secs(30), Duration::from_millis(200), || {
+        b.status(&third) == 200 && b.status(&first) == 404
+    });
+
+    // A server that couldn't be reached until now catches up too
+
+    let mut c = Server::start(&jsaas, ports[2], &ports, None);
+    c.wait_until_ready();
+
+    eventually(Duration::from_secs(30), Duration::from_millis(200), || {
+        c.status(&third) == 200 && c.status(&first) == 404 && c.status(&second) == 404
+    });
+
+    a.stop();
+    b.stop();
+    c.stop();
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// A JSaaS server that replicates to its peers, which is stopped when it's
+/// dropped, so that it doesn't outlive a test that panics.
+struct Server {
+    child: Option<Child>,
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Server {
+    /// Starts a server on a port, whose peers are servers on the other
+    /// ports, and which persists its scripts to `dir`, if any.
+    fn start(jsaas: &Path, port: u16, ports: &[u16], dir: Option<&Path>) -> Self {
+        let peers = ports
+            .iter()
+            .filter(|p| **p != port)
+            .map(|p| format!("http://127.0.0.1:{}", p))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut command = Command::new(jsaas);
+
+        command
+            .env("JSAAS_BIND_ADDR", format!("127.0.0.1:{}", port))
+            .env("JSAAS_REPLICATION_PEERS", peers)
+            .env("JSAAS_REPLICATION_SECRET", "integration")
+            .env("JSAAS_REPLICATION_RESYNC_INTERVAL", "500")
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+
+        if let Some(dir) = dir {
+            command.env("JSAAS_DATA_DIR", dir);
+        }
+
+        Self {
+            child: Some(command.spawn().unwrap()),
+            url: format!("http://127.0.0.1:{}", port),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn wait_until_ready(&self) {
+        eventually(Duration::from_secs(60), Duration::from_millis(200), || {
+            self.client
+                .get(&format!("{}/ping", self.url))
+                .send()
+                .map(|r| r.status().is_success())
+                .unwrap_or(false)
+        });
+    }
+
+    /// Stores a script, returning its id.
+    fn store(&self, code: &str) -> String {
+        let created_data = self
+            .client
+            .post(&format!("{}/scripts", self.url))
+            .body(code.to_string())
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+
+        serde_json::from_str::<ScriptCreated>(&created_data)
+            .unwrap()
+            .id
+    }
+
+    fn execute(&self, id: &str, args: &str) -> String {
+        self.client
+            .post(&format!("{}/scripts/{}", self.url, id))
+            .body(args.to_string())
+            .send()
+            .unwrap()
+            .text()
+            .unwrap()
+    }
+
+    fn remove(&self, id: &str) -> u16 {
+        self.client
+            .delete(&format!("{}/scripts/{}", self.url, id))
+            .send()
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    /// Returns the status of getting a script, or 0 if the server couldn't
+    /// be reached.
+    fn status(&self, id: &str) -> u16 {
+        self.client
+            .get(&format!("{}/scripts/{}", self.url, id))
+            .send()
+            .map(|r| r.status().as_u16())
+            .unwrap_or(0)
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
 /// Repeatedly runs the provided function upto a timelimit of `limit`,
 /// waiting `retry` interval between retries.
 fn eventually<F: Fn() -> bool>(limit: Duration, retry: Duration, f: F) {